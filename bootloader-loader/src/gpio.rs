@@ -128,6 +128,27 @@ impl Gpio {
         }
     }
 
+    /// Drives the given pin, which must be configured as an output, either high or low
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin is out of bounds
+    #[inline]
+    pub fn set_output(&mut self, pin: u8, high: bool) {
+        assert!(pin < Self::NUM_PINS, "Pin should be in bounds");
+        // `GPSET` registers start at offset 0x1C and `GPCLR` registers at offset 0x28, each with
+        // one bit per pin; writing a 0 bit has no effect
+        let base_offset = if high { 0x1C / 4 } else { 0x28 / 4 };
+        // SAFETY: The register index is in bounds since `pin` is in bounds, and writes to these
+        // registers only affect the selected pin
+        unsafe {
+            self.base_address
+                .as_ptr()
+                .add(base_offset + usize::from(pin / 32))
+                .write_volatile(1 << (pin % 32));
+        }
+    }
+
     /// Selects the pull on the given pin
     ///
     /// # Panics
@@ -146,3 +167,86 @@ impl Gpio {
         }
     }
 }
+
+#[expect(
+    dead_code,
+    reason = "Edge detection is for a future loader to wait on a reset button"
+)]
+impl Gpio {
+    /// Returns a pointer to the register holding the bit for the given pin, in the bank of
+    /// one-bit-per-pin registers starting at the given byte offset
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin is out of bounds
+    fn pin_bit_register(&self, byte_offset: usize, pin: u8) -> *mut u32 {
+        assert!(pin < Self::NUM_PINS, "Pin should be in bounds");
+        // SAFETY: The register index is in bounds since `pin` is in bounds
+        unsafe {
+            self.base_address
+                .as_ptr()
+                .add(byte_offset / 4 + usize::from(pin / 32))
+        }
+    }
+
+    /// Sets the bit for the given pin in the bank of read-write registers at the given byte
+    /// offset, leaving the bits of other pins unchanged
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin is out of bounds
+    fn set_pin_bit(&mut self, byte_offset: usize, pin: u8) {
+        let register = self.pin_bit_register(byte_offset, pin);
+        // SAFETY: The register is a valid GPIO register, which nothing else accesses
+        unsafe {
+            register.write_volatile(register.read_volatile() | 1 << (pin % 32));
+        }
+    }
+
+    /// Enables detection of rising edges on the given pin, which are then reported by
+    /// `take_event`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin is out of bounds
+    #[inline]
+    pub fn enable_rising_edge(&mut self, pin: u8) {
+        // `GPREN` registers start at offset 0x4C
+        self.set_pin_bit(0x4C, pin);
+    }
+
+    /// Enables detection of falling edges on the given pin, which are then reported by
+    /// `take_event`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin is out of bounds
+    #[inline]
+    pub fn enable_falling_edge(&mut self, pin: u8) {
+        // `GPFEN` registers start at offset 0x58
+        self.set_pin_bit(0x58, pin);
+    }
+
+    /// Returns whether an enabled edge has been detected on the given pin since the last call,
+    /// and clears the detection so that the next edge is reported afresh
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin is out of bounds
+    #[inline]
+    pub fn take_event(&mut self, pin: u8) -> bool {
+        // `GPEDS` registers start at offset 0x40. Writing a 1 bit clears that pin's event, and
+        // writing a 0 bit has no effect, so only this pin's event is cleared
+        let register = self.pin_bit_register(0x40, pin);
+        let bit = 1 << (pin % 32);
+        // SAFETY: The register is a valid GPIO register, which nothing else accesses
+        unsafe {
+            if register.read_volatile() & bit == 0 {
+                false
+            } else {
+                register.write_volatile(bit);
+                true
+            }
+        }
+    }
+}
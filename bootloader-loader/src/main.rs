@@ -46,25 +46,45 @@
 #![feature(exposed_provenance)]
 
 mod gpio;
+mod soft_uart;
+mod timer;
 mod uart;
 
 use core::arch;
 use core::arch::aarch64;
+use core::fmt::Write;
 use core::hint;
 use core::mem::MaybeUninit;
 use core::num::NonZeroUsize;
 use core::panic::PanicInfo;
 use core::ptr;
 use core::ptr::NonNull;
+use core::time::Duration;
 use gpio::FunctionSelect;
 use gpio::Gpio;
 use gpio::Pull;
+use soft_uart::SoftUart;
 use uart::IoError;
-use uart::Uart;
+use uart::{baud_divisors, Uart, UART_CLOCK_HZ};
 
 /// Byte to indicate to the server of a request
 const SERVER_REQUEST: u8 = b'\x1B';
 
+/// Baud rate for the bit-banged fallback transmitter, used only if the PL011 is unavailable
+const SOFT_UART_BAUD: u32 = 9600;
+
+/// Number of seconds to keep trying to load a kernel before giving up
+const LOAD_TIMEOUT_SECONDS: u64 = 120;
+
+/// Baud rate that the UART starts at, and that both sides return to if a faster rate fails
+const DEFAULT_BAUD: u32 = 921_600;
+
+/// Fastest baud rate to offer the server, which is the UART clock divided by the minimum divisor
+const MAX_BAUD: u32 = 3_000_000;
+
+/// Time to give the server to switch rates before confirming the new rate to it
+const BAUD_SWITCH_DELAY: Duration = Duration::from_millis(10);
+
 /// The boot sequence for the bootloader
 /// * Moves the code segment of the bootloader out of the way to make room for the loaded kernel
 /// * Prepares Rust execution
@@ -172,70 +192,181 @@ extern "C" fn main(load_addr: usize) {
 
     #[expect(
         clippy::unwrap_used,
-        reason = "The pointer is aligned and nonnull, so this should never fail"
+        reason = "The address is nonzero, so this should never fail"
     )]
     // SAFETY: This points to a valid, permanent UART register map in physical memory. No other
     // code accesses this while this bootloader is running
-    let mut uart = unsafe { Uart::new(NonZeroUsize::new(0x4_7E20_1000).unwrap()) }.unwrap();
+    let Some(mut uart) = (unsafe { Uart::new(NonZeroUsize::new(0x4_7E20_1000).unwrap()) }) else {
+        // Without the PL011, the kernel cannot be loaded, but at least report why over the TX pin
+        if let Some(mut serial) = SoftUart::new(gpio, 14, SOFT_UART_BAUD) {
+            // Nothing else can be done if this fails, so ignore the result
+            let _: core::fmt::Result =
+                serial.write_str("bootloader: PL011 UART unavailable, halting\n");
+        }
+        loop {
+            hint::spin_loop();
+        }
+    };
 
     // Ignore any residual reads that may be left
     uart.clear_reads();
 
+    // Give up on a wedged load, rather than silently waiting forever for the server
+    let deadline = timer::frequency()
+        .checked_mul(LOAD_TIMEOUT_SECONDS)
+        .and_then(|timeout| timer::counter().checked_add(timeout));
+    uart.set_deadline(deadline);
+
     loop {
         match try_load_kernel(&mut uart, load_addr) {
             Ok(addr) => {
+                uart.set_deadline(None);
                 // On success, notify the server with a 0 byte.
                 #[expect(clippy::expect_used, reason = "No better failure modes decided yet")]
                 uart.write_byte(0)
                     .expect("Unrecoverable error: unable to transmit via UART");
                 break addr;
             }
+            Err(IoError::Timeout) => give_up(&mut uart),
             Err(_) => {
+                // Any failures should be reported by transmitting a nonzero byte
+                let result = uart.write_byte(0xFF);
+                if let Err(IoError::Timeout) = result {
+                    give_up(&mut uart);
+                }
                 #[expect(
                     clippy::expect_used,
                     reason = "If writing this byte also fails, then we have no choice but to panic"
                 )]
-                // Any failures should be reported by transmitting a nonzero byte
-                uart.write_byte(0xFF)
-                    .expect("Unrecoverable error: unable to transmit via UART");
+                result.expect("Unrecoverable error: unable to transmit via UART");
             }
         }
     }
 }
 
+/// Reports over the UART that loading timed out, and then parks the core
+fn give_up(uart: &mut Uart) -> ! {
+    uart.set_deadline(None);
+    // Nothing else can be done if this fails, so ignore the result
+    let _: core::fmt::Result =
+        uart.write_str("\n!!! bootloader: timed out loading kernel, halting !!!\n");
+    loop {
+        hint::spin_loop();
+    }
+}
+
+/// Reads a little-endian `u32` over the UART
+fn read_u32(uart: &mut Uart) -> Result<u32, IoError> {
+    #[expect(
+        clippy::as_conversions,
+        reason = "No other way to const-convert a `u32` to a `usize`"
+    )]
+    let mut bytes = [MaybeUninit::uninit(); (u32::BITS / 8) as usize];
+    uart.read_bytes(&mut bytes)?;
+    // SAFETY: The call to `read_bytes` promises to initialize the entire array
+    let bytes = unsafe { MaybeUninit::array_assume_init(bytes) };
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Negotiates the fastest baud rate that both the server and the bootloader support. If the server
+/// cannot use that rate, it returns to the default rate and sends a nonzero fallback byte in
+/// place of its confirmation, and the bootloader follows it back
+///
+/// Returns an `Error` if an IO error occurs at the default rate
+fn negotiate_baud(uart: &mut Uart) -> Result<(), IoError> {
+    uart.write_byte(SERVER_REQUEST)?;
+    // Ask for baud configuration, offering the fastest supported rate
+    uart.write_byte(1)?;
+    uart.write_u32(MAX_BAUD)?;
+    // The server replies with the rate to use, which is acknowledged at the current rate, since
+    // the server is still listening at it
+    let baud = read_u32(uart)?;
+    let usable = baud <= MAX_BAUD && baud_divisors(baud, UART_CLOCK_HZ).is_some();
+    uart.write_byte(if usable { 0 } else { 1 })?;
+    if usable {
+        // Switching the rate waits for the transmit FIFO to drain, so the acknowledgement goes
+        // out in full at the old rate
+        #[expect(clippy::expect_used, reason = "The rate was checked above")]
+        uart.set_baud_rate(baud, UART_CLOCK_HZ)
+            .expect("A rate with valid divisors should be settable");
+        // Confirm the new rate once the server has had time to switch to it
+        timer::wait_at_least(BAUD_SWITCH_DELAY);
+        if let Err(err) = uart.write_byte(0) {
+            fall_back_to_default_baud(uart);
+            return Err(err);
+        }
+    }
+    let mut reply = [MaybeUninit::uninit()];
+    match uart.read_bytes(&mut reply) {
+        // SAFETY: The call to `read_bytes` initialized the reply
+        Ok(()) if unsafe { reply[0].assume_init() } == 0 => Ok(()),
+        // The server falls back as well when it hears nothing, so the next attempt must start
+        // from the default rate
+        Err(IoError::Timeout) => {
+            fall_back_to_default_baud(uart);
+            Err(IoError::Timeout)
+        }
+        // Anything else is the fallback signal, possibly garbled by being read at the wrong rate
+        Ok(()) | Err(IoError::Break | IoError::Frame | IoError::Overrun | IoError::Parity) => {
+            fall_back_to_default_baud(uart);
+            Ok(())
+        }
+    }
+}
+
+/// Switches the UART back to the default baud rate, discarding anything received at the old rate
+fn fall_back_to_default_baud(uart: &mut Uart) {
+    #[expect(clippy::expect_used, reason = "The default rate is always valid")]
+    uart.set_baud_rate(DEFAULT_BAUD, UART_CLOCK_HZ)
+        .expect("The default baud rate should be valid");
+    uart.clear_reads();
+}
+
+/// Computes the CRC-32 (as used by Ethernet and zlib) of the given bytes. This works a bit at a
+/// time, so that no lookup table has to be built or stored
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(u32::MAX, |crc, &byte| {
+        (0_u8..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1_u32) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
 /// Attempts to load a kernel according to the agreed-upon protocol.
 ///
 /// Returns an `Ok` containing the loaded kernel address if successful
-/// Returns an `Error` if an IO error occurs.
+/// Returns an `Error` if an IO error occurs, or if the kernel does not match its checksum
 fn try_load_kernel(uart: &mut Uart, address: usize) -> Result<(), IoError> {
+    negotiate_baud(uart)?;
     // Write an escape character to begin the loading process, and ask for a kernel
     uart.write_byte(SERVER_REQUEST)?;
     // Ask for a kernel
     uart.write_byte(0)?;
     // Read the size of the kernel
-    #[expect(
-        clippy::as_conversions,
-        reason = "No other way to const-convert a `u32` to a `usize`"
-    )]
-    let mut kernel_size = [MaybeUninit::uninit(); (u32::BITS / 8) as usize];
-    uart.read_bytes(&mut kernel_size)?;
-    // SAFETY: The call to `read_bytes` promises to initialize the entire array
-    let kernel_size = unsafe { MaybeUninit::array_assume_init(kernel_size) };
-    let kernel_size = u32::from_le_bytes(kernel_size);
+    let kernel_size = read_u32(uart)?;
     // TODO: Decide upon an address based on server input
     let Some(kernel_addr) = NonNull::new(ptr::from_exposed_addr_mut(address)) else {
         uart.write_byte(1)?;
         return Err(IoError::Frame);
     };
 
+    #[expect(clippy::unwrap_used, reason = "This conversion can never fail")]
+    let kernel_size: usize = kernel_size.try_into().unwrap();
     // SAFETY: The region of memory for the kernel is valid and unused by everything else, and the
     // size of the kernel fits into a `u32` which fits into an `isize`
-    let kernel = unsafe {
-        #[expect(clippy::unwrap_used, reason = "This conversion can never fail")]
-        NonNull::slice_from_raw_parts(kernel_addr, kernel_size.try_into().unwrap())
-            .as_uninit_slice_mut()
-    };
-    uart.read_bytes(kernel)
+    let kernel =
+        unsafe { NonNull::slice_from_raw_parts(kernel_addr, kernel_size).as_uninit_slice_mut() };
+    uart.read_bytes(kernel)?;
+    // The kernel is followed by the CRC-32 of its contents
+    let checksum = read_u32(uart)?;
+    // SAFETY: The call to `read_bytes` initialized the entire kernel region, which nothing else
+    // accesses until the kernel is booted
+    let kernel = unsafe { NonNull::slice_from_raw_parts(kernel_addr, kernel_size).as_ref() };
+    if crc32(kernel) == checksum {
+        Ok(())
+    } else {
+        Err(IoError::Frame)
+    }
 }
 
 /// Panic handler: nothing to do but park the core, since the UART is nonfunctional in this case
@@ -0,0 +1,69 @@
+//! Transmit-only software UART, bit-banged over a GPIO pin. This is a fallback for diagnostics
+//! when the PL011 is unavailable, and so only supports modest baud rates
+
+use crate::gpio::{FunctionSelect, Gpio};
+use crate::timer::{self, counter};
+use core::{fmt, num::NonZeroU64};
+
+/// A bit-banged serial transmitter, using 8 data bits, no parity, and 1 stop bit
+pub struct SoftUart {
+    /// The GPIO driver used to toggle the transmit pin
+    gpio: Gpio,
+    /// The pin to transmit on
+    pin: u8,
+    /// Number of system timer ticks that each bit is held for
+    ticks_per_bit: NonZeroU64,
+}
+
+/// Computes the number of timer ticks per transmitted bit for the given timer frequency and baud
+/// rate, rounded to the nearest tick
+///
+/// Returns `None` if the baud rate is zero, or too fast to be timed at this frequency
+#[inline]
+pub fn ticks_per_bit(timer_frequency: u64, baud: u32) -> Option<NonZeroU64> {
+    let baud = NonZeroU64::new(baud.into())?;
+    NonZeroU64::new(timer_frequency.checked_add(baud.get() / 2)? / baud)
+}
+
+impl SoftUart {
+    /// Configures the given pin as a serial transmitter at the given baud rate, and idles the
+    /// line high
+    ///
+    /// Returns `None` if the baud rate cannot be timed by the system timer
+    #[inline]
+    pub fn new(mut gpio: Gpio, pin: u8, baud: u32) -> Option<Self> {
+        let ticks_per_bit = ticks_per_bit(timer::frequency(), baud)?;
+        gpio.select_function(pin, FunctionSelect::Output);
+        gpio.set_output(pin, true);
+        Some(Self {
+            gpio,
+            pin,
+            ticks_per_bit,
+        })
+    }
+
+    /// Transmits a single byte, blocking until the stop bit has been sent
+    #[inline]
+    pub fn write_byte(&mut self, byte: u8) {
+        // Start bit, the data bits from least significant, then the stop bit
+        let frame = (u16::from(byte) << 1_u8) | (1 << 9_u8);
+        let mut deadline = counter();
+        for bit in 0..10 {
+            self.gpio.set_output(self.pin, frame & (1 << bit) != 0);
+            deadline = deadline.wrapping_add(self.ticks_per_bit.get());
+            while counter().wrapping_sub(deadline) > u64::MAX / 2 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+impl fmt::Write for SoftUart {
+    #[inline]
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for byte in string.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+//! Access to the free-running system timer
+
+use core::{arch::asm, hint, time::Duration};
+
+/// Number of nanoseconds in a second
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Reads the current value of the system timer
+pub fn counter() -> u64 {
+    let count;
+    // SAFETY: This only reads a system register with no other side effects
+    unsafe {
+        asm! {
+            "isb",
+            "mrs {}, CNTPCT_EL0",
+            out(reg) count,
+            options(nomem, nostack, preserves_flags)
+        }
+    }
+    count
+}
+
+/// Reads the frequency of the system timer
+pub fn frequency() -> u64 {
+    let frequency;
+    // SAFETY: This only reads a system register with no other side effects
+    unsafe {
+        asm! {
+            "mrs {}, CNTFRQ_EL0",
+            out(reg) frequency,
+            options(nomem, nostack, preserves_flags)
+        }
+    }
+    frequency
+}
+
+/// Spins until at least the given duration has passed. The duration is converted to timer ticks
+/// rounding up, so that this never returns early
+pub fn wait_at_least(duration: Duration) {
+    // A `Duration` holds under 2^94 nanoseconds and the frequency fits in 32 bits, so the product
+    // cannot overflow 128 bits
+    let ticks = (duration.as_nanos() * u128::from(frequency())).div_ceil(NANOS_PER_SECOND);
+    let end = counter().saturating_add(u64::try_from(ticks).unwrap_or(u64::MAX));
+    while counter() < end {
+        hint::spin_loop();
+    }
+}
@@ -15,6 +15,29 @@ use tock_registers::{
 };
 
 use self::IFLS::RXIFLSEL;
+use crate::timer;
+
+/// Frequency of the clock feeding the UART, as programmed by `config.txt`
+pub const UART_CLOCK_HZ: u32 = 48_000_000;
+
+/// Computes the integral and fractional baud rate divisors for the given baud rate and UART clock
+/// frequency. The divisor is the clock over 16 times the baud rate, split into a 16-bit integer
+/// part and a 6-bit fraction, rounded to the nearest 64th
+///
+/// Returns `None` if the baud rate is zero, or the integer part does not fit in 16 bits or would
+/// be zero
+pub fn baud_divisors(baud: u32, uart_clock_hz: u32) -> Option<(u16, u8)> {
+    // Dividing 64 times the clock by 16 times the baud rate leaves the fraction in the low 6 bits
+    let divisor = u64::from(uart_clock_hz)
+        .checked_mul(4)?
+        .checked_add(u64::from(baud / 2))?
+        .checked_div(u64::from(baud))?;
+    let integral = u16::try_from(divisor.checked_shr(6)?)
+        .ok()
+        .filter(|&integral| integral != 0)?;
+    let fractional = u8::try_from(divisor & 0x3F).ok()?;
+    Some((integral, fractional))
+}
 
 /// IO errors associated with UART
 #[derive(Debug)]
@@ -27,12 +50,16 @@ pub enum IoError {
     Overrun,
     /// A parity error occured on received data
     Parity,
+    /// The deadline set via `set_deadline` passed while waiting on the UART
+    Timeout,
 }
 
 /// A driver to operate a UART's reads and writes
 pub struct Uart<'uart> {
     /// The memory-mapped registers corresponding to this UART
     registers: &'uart mut UartRegisters,
+    /// Timer value after which waiting reads and writes fail, if any
+    deadline: Option<u64>,
 }
 
 register_bitfields! {
@@ -298,47 +325,47 @@ register_bitfields! {
             SevenEights = 0b100,
         ],
     ],
-    /// The interrupt mask set/clear register
+    /// The interrupt mask set/clear register. Setting a bit enables the corresponding interrupt
     IMSC [
         /// Overrun error interrupt mask
         OEIM OFFSET(10) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
         /// Break error interrupt mask
         BEIM OFFSET(9) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
         /// Parity error interrupt mask
         PEIM OFFSET(8) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
         /// Frame error interrupt mask
         FEIM OFFSET(7) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
         /// Receive timeout interrupt mask
         RTIM OFFSET(6) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
         /// Transmit interrupt mask
         TXIM OFFSET(5) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
         /// Receive interrupt mask
         RXIM OFFSET(4) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
         /// nUARTCTS modem interrupt mask
         CTSIMM OFFSET(1) NUMBITS(1) [
-            Unmasked = 0,
-            Masked = 1,
+            Masked = 0,
+            Unmasked = 1,
         ],
     ],
     /// The raw interrupt status register
@@ -590,7 +617,10 @@ impl<'uart> Uart<'uart> {
                 + CR::UARTEN::Enabled,
         );
 
-        Some(Self { registers })
+        Some(Self {
+            registers,
+            deadline: None,
+        })
     }
 
     /// Sets the integral and fractional divisors of the baud rate
@@ -616,11 +646,39 @@ impl<'uart> Uart<'uart> {
         self.registers.cr.modify(CR::UARTEN::Enabled);
     }
 
+    /// Sets the baud rate given the frequency of the clock feeding the UART, rounded to the
+    /// nearest rate that the divisors can represent, and discards any errors received at the
+    /// previous rate
+    ///
+    /// Returns an `Err`, leaving the rate unchanged, if the divisors cannot represent the rate
+    #[expect(
+        clippy::result_unit_err,
+        reason = "The only failure is an unrepresentable rate"
+    )]
+    pub fn set_baud_rate(&mut self, baud: u32, uart_clock_hz: u32) -> Result<(), ()> {
+        let (integral, fractional) = baud_divisors(baud, uart_clock_hz).ok_or(())?;
+        self.set_divider(integral, fractional);
+        self.registers.icr.set(0xFFFF_FFFF);
+        Ok(())
+    }
+
+    /// Sets a timer value after which any read or write still waiting on the UART fails with
+    /// `IoError::Timeout`, or clears it if `None`
+    pub fn set_deadline(&mut self, deadline: Option<u64>) {
+        self.deadline = deadline;
+    }
+
     /// Returns `Ok` if no errors are currently found on the UART, otherwise returns an `Err`
-    /// corresponding to the first error found (arbitrarily decided).
+    /// corresponding to the first error found (arbitrarily decided). Passing the deadline, if
+    /// any, also counts as an error
     fn check_errors(&self) -> Result<(), IoError> {
         let ris = self.registers.ris.extract();
-        if ris.matches_any(RIS::OERIS::Pending) {
+        if self
+            .deadline
+            .is_some_and(|deadline| timer::counter() >= deadline)
+        {
+            Err(IoError::Timeout)
+        } else if ris.matches_any(RIS::OERIS::Pending) {
             Err(IoError::Overrun)
         } else if ris.matches_any(RIS::BERIS::Pending) {
             Err(IoError::Break)
@@ -693,6 +751,59 @@ impl<'uart> Uart<'uart> {
         }))
     }
 
+    /// Enables the receive interrupt, so that incoming data can be collected with
+    /// `take_pending_byte` from an interrupt handler instead of by spinning on the FIFO.
+    ///
+    /// The interrupt-driven and polling APIs share the same receive FIFO, so a byte may be
+    /// consumed by either one. Drain pending bytes with `take_pending_byte` (or `clear_reads`)
+    /// before switching from one to the other
+    #[expect(
+        clippy::arithmetic_side_effects,
+        reason = "These do not have side effects"
+    )]
+    pub fn enable_rx_interrupt(&mut self) {
+        self.registers
+            .imsc
+            .modify(IMSC::RXIM::Unmasked + IMSC::RTIM::Unmasked);
+    }
+
+    /// Takes a single received byte if a receive interrupt is pending, acknowledging the
+    /// interrupt once the receive FIFO has been drained.
+    ///
+    /// Returns `None` if no receive interrupt is pending, so callers should keep taking bytes until
+    /// this returns `None`.
+    ///
+    /// Returns `Some(Err)` if an IO error occurs
+    #[expect(
+        clippy::arithmetic_side_effects,
+        reason = "These do not have side effects"
+    )]
+    pub fn take_pending_byte(&mut self) -> Option<Result<u8, IoError>> {
+        // Data below the FIFO trigger level is only signalled by the receive timeout interrupt
+        if !self
+            .registers
+            .mis
+            .matches_any(MIS::RXMIS::Pending + MIS::RTMIS::Pending)
+        {
+            return None;
+        }
+        if let Err(error) = self.check_errors() {
+            return Some(Err(error));
+        }
+        let result = if self.registers.fr.matches_any(FR::RXFE::Empty) {
+            None
+        } else {
+            #[expect(clippy::unwrap_used, reason = "This conversion can never fail")]
+            Some(Ok(self.registers.dr.read(DR_R::DATA).try_into().unwrap()))
+        };
+        if self.registers.fr.matches_any(FR::RXFE::Empty) {
+            self.registers
+                .icr
+                .write(ICR::RXIC::Clear + ICR::RTIC::Clear);
+        }
+        result
+    }
+
     /// Clears all data from the receive FIFO
     pub fn clear_reads(&mut self) {
         while !self.registers.fr.matches_any(FR::RXFE::Empty) {
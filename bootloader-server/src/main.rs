@@ -10,8 +10,19 @@
 //!
 //! A byte of 0 sends a kernel over the serial connection. A kernel must be selected via the
 //! `--kernel` flag. First, the size of the kernel, in bytes, as a `u32` in little-endian, is sent,
-//! and then the kernel itself is sent. After this, normal operation resumes. Note that the kernel
-//! is loaded only when asked, so that it can be recompiled without having to restart the server.
+//! then the kernel itself, and finally the CRC-32 of the kernel as a little-endian `u32`. After
+//! this, normal operation resumes. Note that the kernel is loaded only when asked, so that it can
+//! be recompiled without having to restart the server.
+//!
+//! A byte of 1 negotiates a baud rate. The bootloader sends the fastest rate it supports, as a
+//! little-endian `u32`, and the server replies with that rate clamped to `--max-baud`. After the
+//! bootloader acknowledges the rate, both sides switch to it and the bootloader sends a 0 byte at
+//! the new rate, which the server confirms with a 0 byte of its own. If any of this fails, the
+//! server returns to the default rate and sends a nonzero fallback byte instead, so that the
+//! bootloader returns to the default rate too.
+//!
+//! If the bootloader rejects a kernel, it asks for the kernel again, and the server sends it afresh
+//! after a short delay. After `--retries` consecutive rejected transfers, the server gives up.
 
 #![warn(clippy::all)]
 #![warn(clippy::restriction)]
@@ -23,11 +34,34 @@
 #![warn(clippy::style)]
 #![deny(clippy::suspicious)]
 #![deny(unsafe_op_in_unsafe_fn)]
-#![allow(clippy::blanket_clippy_restriction_lints)]
-#![allow(clippy::implicit_return)]
-#![allow(clippy::question_mark_used)]
-#![allow(clippy::shadow_reuse)]
-#![allow(clippy::separated_literal_suffix)]
+#![allow(
+    clippy::blanket_clippy_restriction_lints,
+    reason = "This is intentionally enabled"
+)]
+#![allow(clippy::implicit_return, reason = "This is the desired format")]
+#![allow(clippy::question_mark_used, reason = "This is the desired format")]
+#![allow(clippy::shadow_reuse, reason = "This is the desired format")]
+#![allow(
+    clippy::separated_literal_suffix,
+    reason = "This is the desired format"
+)]
+#![allow(
+    clippy::arbitrary_source_item_ordering,
+    reason = "Items are grouped by purpose instead"
+)]
+#![allow(
+    clippy::doc_paragraphs_missing_punctuation,
+    reason = "This is the desired format"
+)]
+#![allow(
+    clippy::little_endian_bytes,
+    reason = "The bootloader protocol is little-endian"
+)]
+#![allow(clippy::missing_trait_methods, reason = "Defaults are acceptable here")]
+#![allow(
+    clippy::single_call_fn,
+    reason = "Steps of the protocol are split out for readability"
+)]
 
 use clap::Parser;
 use core::slice;
@@ -35,14 +69,29 @@ use core::time::Duration;
 use serialport::SerialPortType;
 use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
 
-use std::error::Error;
+use core::error::Error;
 use std::fs::File;
 use std::io::{self, ErrorKind, Read, Write};
+use std::thread;
 
 /// The default baud rate when opening a connection; is clamped to the maximum rate passed as an
 /// argument
 const DEFAULT_BAUD_RATE: u32 = 921_600;
 
+/// Byte sent to the bootloader at the default baud rate, in place of confirming a negotiated rate,
+/// to tell it to return to the default rate as well
+const BAUD_FALLBACK: u8 = 0xFE;
+
+/// Number of times to attempt reading a response from the bootloader before giving up
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry of a failed exchange; each later retry waits twice as long as the
+/// one before it
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Default number of times to resend a rejected kernel
+const DEFAULT_RETRIES: u32 = 3;
+
 /// Arguments to control the server conection and operations
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -58,6 +107,10 @@ struct Args {
     /// Maximum baud rate to use over the connection
     #[arg(short, long, default_value_t = DEFAULT_BAUD_RATE)]
     max_baud: u32,
+
+    /// Number of times to resend a kernel that the bootloader rejects before giving up
+    #[arg(short, long, default_value_t = DEFAULT_RETRIES)]
+    retries: u32,
 }
 
 /// Reads a single byte from the given reader. See `Read::read` for more information on error
@@ -67,47 +120,240 @@ struct Args {
 /// bytes
 fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
     let mut byte = 0_u8;
-    reader.read(slice::from_mut(&mut byte)).and_then(|written| {
-        if written == 0 {
-            Err(ErrorKind::UnexpectedEof.into())
-        } else {
-            Ok(byte)
-        }
-    })
+    if reader.read(slice::from_mut(&mut byte))? == 0 {
+        Err(ErrorKind::UnexpectedEof.into())
+    } else {
+        Ok(byte)
+    }
 }
 
-/// Reads a little-endian `u32` over the connection.
+/// Reads a little-endian `u32` over the connection, retrying each byte that is slow to arrive.
 ///
-/// Propogates any errors from reading the connection
+/// The bytes are read one at a time, so a retry never discards the bytes already read. Propogates
+/// any errors from reading the connection
 fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
     let mut bytes = [0; 4];
-    reader.read_exact(&mut bytes)?;
+    for byte in &mut bytes {
+        *byte = retry_with_backoff(|| read_byte(reader), MAX_ATTEMPTS)?;
+    }
     Ok(u32::from_le_bytes(bytes))
 }
 
-/// Checks for an OK signal over the connection.
+/// Runs `op` until it succeeds, at most `max_attempts` times, sleeping for exponentially
+/// increasing delays between attempts.
+///
+/// Only timeouts and interruptions are considered transient: other errors are returned
+/// immediately, without retrying. If every attempt fails, the last error is returned. A failed
+/// attempt must not have consumed any input, or the retry would resume partway through it
+fn retry_with_backoff<T>(
+    mut op: impl FnMut() -> io::Result<T>,
+    max_attempts: u32,
+) -> io::Result<T> {
+    let mut delay = INITIAL_BACKOFF;
+    let mut attempt = 1_u32;
+    loop {
+        match op() {
+            Err(err)
+                if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted)
+                    && attempt < max_attempts =>
+            {
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt = attempt.saturating_add(1);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Folds the given bytes into a running CRC-32 (as used by Ethernet and zlib). The running value
+/// starts at `u32::MAX`, and the final checksum is its bitwise complement
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        (0_u8..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1_u32) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Writer that passes everything through to `inner`, while keeping a running CRC-32 of the bytes
+/// that were actually written
+struct Crc32Writer<W> {
+    /// Writer that bytes are passed through to
+    inner: W,
+    /// Running CRC-32, before the final complement
+    crc: u32,
+}
+
+impl<W> Crc32Writer<W> {
+    /// Wraps the given writer, with no bytes checksummed yet
+    const fn new(inner: W) -> Self {
+        Self {
+            inner,
+            crc: u32::MAX,
+        }
+    }
+
+    /// Returns the CRC-32 of all bytes written so far
+    const fn checksum(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc = crc32_update(self.crc, buf.get(..written).unwrap_or(buf));
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Checks for an OK signal over the connection, retrying if the signal is slow to arrive.
 ///
-/// Propogates any errors from reading the connection
-fn check_ok(reader: &mut impl Read) {
-    #[allow(clippy::print_stderr)]
-    match read_byte(reader) {
+/// Returns whether the operation was acknowledged as successful
+fn check_ok(reader: &mut impl Read) -> bool {
+    #[expect(clippy::print_stderr, reason = "Logs are written to standard error")]
+    match retry_with_backoff(|| read_byte(reader), MAX_ATTEMPTS) {
         Ok(0) => {
-            eprintln!("[LOG] Operation successful!")
+            eprintln!("[LOG] Operation successful!");
+            true
         }
         Ok(code) => {
-            eprintln!("[WARN] Did not receive acknowledgement of operation, error code {code}")
+            eprintln!("[WARN] Did not receive acknowledgement of operation, error code {code}");
+            false
         }
-        Err(err) => match err.kind() {
-            ErrorKind::TimedOut => eprintln!(
-                "[WARN] Did not receive acknowledgement of operation, operation timed out"
-            ),
-            _ => {}
-        },
+        Err(err) => {
+            if err.kind() == ErrorKind::TimedOut {
+                eprintln!(
+                    "[WARN] Did not receive acknowledgement of operation, operation timed out"
+                );
+            }
+            false
+        }
+    }
+}
+
+/// A connection whose baud rate can be changed, which is separated from `SerialPort` so that
+/// negotiation can run over something other than a real port
+trait ChangeBaudRate {
+    /// Switches the connection to the given baud rate
+    fn change_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()>;
+}
+
+impl<T: SerialPort> ChangeBaudRate for T {
+    fn change_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.set_baud_rate(baud_rate)
+    }
+}
+
+/// Runs the rest of a baud configuration exchange requested by the bootloader, using the fastest
+/// rate supported by both sides up to `max_baud`. If the connection cannot be switched to that
+/// rate, or the bootloader cannot be heard at it, both sides fall back to the default rate.
+///
+/// Returns the baud rate now in use, or an error if the exchange could not be carried out
+#[expect(clippy::print_stderr, reason = "Logs are written to standard error")]
+fn negotiate_baud(
+    uart: &mut (impl Read + Write + ChangeBaudRate),
+    max_baud: u32,
+) -> io::Result<u32> {
+    // 1. The connection sends its maximum supported baud rate
+    let max_supported_baud_rate = read_u32(uart)?;
+    // 2. We respond with the actual baud rate to use
+    let baud_rate = max_baud.min(max_supported_baud_rate);
+    #[expect(
+        clippy::float_arithmetic,
+        reason = "Only used to report the transfer rate"
+    )]
+    let throughput = f64::from(baud_rate) * 0.8_f64 / 1_024.0_f64 / 8.0_f64;
+    eprintln!("[LOG] Setting baud rate to {baud_rate} baud ({throughput} KiB/s)");
+    uart.write_all(&baud_rate.to_le_bytes())?;
+    // 3. Once the connection acknowledges the rate, both sides switch to it, and the connection
+    //    confirms that it can be heard at the new rate
+    let switched = check_ok(uart)
+        && match uart.change_baud_rate(baud_rate) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("[WARN] Unable to set baud rate to {baud_rate} baud: {err}");
+                false
+            }
+        }
+        && check_ok(uart);
+    // 4. We confirm the new rate in turn, or otherwise tell the connection to fall back
+    if switched {
+        uart.write_all(&[0])?;
+        eprintln!("[LOG] Baud rate is now {baud_rate} baud");
+        Ok(baud_rate)
+    } else {
+        eprintln!("[WARN] Falling back to {DEFAULT_BAUD_RATE} baud");
+        uart.change_baud_rate(DEFAULT_BAUD_RATE)?;
+        uart.write_all(&[BAUD_FALLBACK])?;
+        Ok(DEFAULT_BAUD_RATE)
+    }
+}
+
+/// Counts consecutive rejected kernel transfers, to pace resends and decide when to stop
+struct TransferRetries {
+    /// Number of resends allowed after a rejected transfer
+    limit: u32,
+    /// Number of transfers rejected since the last successful one
+    failures: u32,
+}
+
+impl TransferRetries {
+    /// Starts counting, allowing up to `limit` resends
+    const fn new(limit: u32) -> Self {
+        Self { limit, failures: 0 }
+    }
+
+    /// Records an acknowledged transfer, resetting the count of failures
+    const fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Records a rejected transfer. Returns how long to wait before resending, with each
+    /// consecutive failure waiting twice as long as the one before, or `None` if no resends remain
+    fn failed(&mut self) -> Option<Duration> {
+        self.failures = self.failures.saturating_add(1);
+        (self.failures <= self.limit).then(|| {
+            INITIAL_BACKOFF.saturating_mul(
+                1_u32
+                    .checked_shl(self.failures.saturating_sub(1))
+                    .unwrap_or(u32::MAX),
+            )
+        })
     }
 }
 
-#[allow(clippy::print_stdout)]
-#[allow(clippy::print_stderr)]
+/// Sends the kernel at the given path over the connection, starting from the beginning of the
+/// file, and waits for the bootloader to acknowledge it.
+///
+/// Returns whether the bootloader accepted the kernel, or an error if the kernel could not be read
+/// or sent
+fn send_kernel(uart: &mut (impl Write + Read), path: &str) -> Result<bool, Box<dyn Error>> {
+    // 1. We send the kernel file size, in bytes. The file is opened afresh for every request, so
+    //    that each transfer sends the current kernel from its start
+    let mut kernel = File::open(path)?;
+    let kernel_size: u32 = kernel.metadata()?.len().try_into()?;
+    uart.write_all(&kernel_size.to_le_bytes())?;
+    // 2. The contents of the kernel are sent, with the amount of bytes as specified above
+    let mut writer = Crc32Writer::new(&mut *uart);
+    io::copy(&mut kernel, &mut writer)?;
+    // 3. The CRC-32 of the kernel is sent, so that corruption is detected
+    let checksum = writer.checksum();
+    uart.write_all(&checksum.to_le_bytes())?;
+    // 4. Wait for a confirmation response
+    Ok(check_ok(uart))
+}
+
+#[expect(
+    clippy::print_stdout,
+    reason = "Port selection is reported to the user on standard output"
+)]
+#[expect(clippy::print_stderr, reason = "Logs are written to standard error")]
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
     let port_name = if let Some(port_name) = args.port {
@@ -150,6 +396,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         .timeout(Duration::from_secs(1))
         .open_native()?;
 
+    let mut kernel_retries = TransferRetries::new(args.retries);
     loop {
         match read_byte(&mut uart) {
             Ok(b'\x1B') => {
@@ -158,37 +405,31 @@ fn main() -> Result<(), Box<dyn Error>> {
                     0 => {
                         eprintln!("[LOG] Kernel requested");
                         // Kernel loading mode
-                        // 1. We send the kernel file size, in bytes
-                        let mut kernel =
-                            File::open(args.kernel.as_ref().ok_or("No kernel provided")?)?;
-                        let kernel_size: u32 = kernel.metadata()?.len().try_into()?;
-                        uart.write_all(&kernel_size.to_le_bytes())?;
-                        // 3. The contents of the kernel are sent, with the amount of bytes as
-                        //    specified above
-                        io::copy(&mut kernel, &mut uart)?;
-                        // 4. Wait for a confirmation response
-                        check_ok(&mut uart);
+                        let path = args.kernel.as_ref().ok_or("No kernel provided")?;
+                        if send_kernel(&mut uart, path)? {
+                            kernel_retries.succeeded();
+                        } else if let Some(delay) = kernel_retries.failed() {
+                            // The bootloader asks for the kernel again after rejecting it
+                            eprintln!(
+                                "[LOG] Kernel rejected, resending in {} ms",
+                                delay.as_millis()
+                            );
+                            thread::sleep(delay);
+                        } else {
+                            return Err(format!(
+                                "Kernel rejected {} times in a row, giving up",
+                                args.retries.saturating_add(1)
+                            )
+                            .into());
+                        }
                     }
                     1 => {
                         eprintln!("[LOG] Baud configuration requested");
                         // Clock configuration mode
-                        // 1. The connection sends its maximum supported baud rate
-                        let max_supported_baud_rate = read_u32(&mut uart)?;
-                        // 2. We respond with the actual baud rate to use
-                        let baud_rate = max_supported_baud_rate; // args.max_baud.min(max_supported_baud_rate);
-                        eprintln!(
-                            "[LOG] Setting baud rate to {baud_rate} baud ({} KiB/s)",
-                            f64::from(baud_rate) * 0.8 / 1024.0 / 8.0
-                        );
-                        uart.write_all(&baud_rate.to_le_bytes())?;
-                        // 3. Wait for a confirmation response
-                        check_ok(&mut uart);
-                        // 4. Now, we can set the baud rate of the connection
-                        uart.set_baud_rate(baud_rate)?;
+                        negotiate_baud(&mut uart, args.max_baud)?;
                     }
                     byte => {
                         eprintln!("[WARN] Bad opcode received: {byte}");
-                        continue;
                     }
                 }
             }
@@ -198,12 +439,213 @@ fn main() -> Result<(), Box<dyn Error>> {
                 // We want to flush every byte to ensure as accurate printing as possible
                 stdout.flush()?;
             }
-            Err(err) => {
-                match err.kind() {
-                    ErrorKind::TimedOut => {}
-                    _ => Err(err)?,
-                };
+            Err(err) if err.kind() == ErrorKind::TimedOut => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        negotiate_baud, read_u32, retry_with_backoff, ChangeBaudRate, Crc32Writer, ErrorKind,
+        TransferRetries, BAUD_FALLBACK, DEFAULT_BAUD_RATE, INITIAL_BACKOFF,
+    };
+    use std::io::{self, Read, Write};
+
+    /// A connection that replays scripted bytes from the bootloader and records everything else
+    struct ScriptedPort {
+        incoming: io::Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+        baud_rates: Vec<u32>,
+        /// Whether changing to a rate other than the default fails
+        unsupported: bool,
+    }
+
+    impl ScriptedPort {
+        fn new(incoming: Vec<u8>, unsupported: bool) -> Self {
+            Self {
+                incoming: io::Cursor::new(incoming),
+                outgoing: Vec::new(),
+                baud_rates: Vec::new(),
+                unsupported,
+            }
+        }
+    }
+
+    impl Read for ScriptedPort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    impl Write for ScriptedPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ChangeBaudRate for ScriptedPort {
+        fn change_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+            if self.unsupported && baud_rate != DEFAULT_BAUD_RATE {
+                return Err(serialport::Error::new(
+                    serialport::ErrorKind::InvalidInput,
+                    "unsupported baud rate",
+                ));
+            }
+            self.baud_rates.push(baud_rate);
+            Ok(())
+        }
+    }
+
+    /// The bytes of a baud request offering `baud`, followed by the given replies
+    fn baud_request(baud: u32, replies: &[u8]) -> Vec<u8> {
+        baud.to_le_bytes()
+            .into_iter()
+            .chain(replies.iter().copied())
+            .collect()
+    }
+
+    #[test]
+    fn clamps_negotiated_baud_to_maximum() {
+        let mut port = ScriptedPort::new(baud_request(3_000_000, &[0, 0]), false);
+        assert_eq!(negotiate_baud(&mut port, 1_500_000).ok(), Some(1_500_000));
+        assert_eq!(port.baud_rates, [1_500_000]);
+        let mut expected = 1_500_000_u32.to_le_bytes().to_vec();
+        expected.push(0);
+        assert_eq!(port.outgoing, expected);
+    }
+
+    #[test]
+    fn falls_back_when_new_rate_is_not_confirmed() {
+        let mut port = ScriptedPort::new(baud_request(3_000_000, &[0]), false);
+        assert_eq!(
+            negotiate_baud(&mut port, 3_000_000).ok(),
+            Some(DEFAULT_BAUD_RATE)
+        );
+        assert_eq!(port.baud_rates, [3_000_000, DEFAULT_BAUD_RATE]);
+        assert_eq!(port.outgoing.last(), Some(&BAUD_FALLBACK));
+    }
+
+    #[test]
+    fn falls_back_when_rate_cannot_be_set() {
+        let mut port = ScriptedPort::new(baud_request(3_000_000, &[0, 0]), true);
+        assert_eq!(
+            negotiate_baud(&mut port, 3_000_000).ok(),
+            Some(DEFAULT_BAUD_RATE)
+        );
+        assert_eq!(port.baud_rates, [DEFAULT_BAUD_RATE]);
+        assert_eq!(port.outgoing.last(), Some(&BAUD_FALLBACK));
+    }
+
+    #[test]
+    fn retries_recoverable_errors_until_success() {
+        let mut attempts = 0_u32;
+        let result = retry_with_backoff(
+            || {
+                attempts += 1;
+                if attempts < 3 {
+                    Err(io::Error::from(ErrorKind::TimedOut))
+                } else {
+                    Ok(attempts)
+                }
+            },
+            4,
+        );
+        assert_eq!(result.ok(), Some(3));
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut attempts = 0_u32;
+        let result: io::Result<()> = retry_with_backoff(
+            || {
+                attempts += 1;
+                Err(ErrorKind::Interrupted.into())
+            },
+            3,
+        );
+        assert_eq!(
+            result.map_err(|err| err.kind()),
+            Err(ErrorKind::Interrupted)
+        );
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn does_not_retry_unrecoverable_errors() {
+        let mut attempts = 0_u32;
+        let result: io::Result<()> = retry_with_backoff(
+            || {
+                attempts += 1;
+                Err(ErrorKind::BrokenPipe.into())
+            },
+            4,
+        );
+        assert_eq!(result.map_err(|err| err.kind()), Err(ErrorKind::BrokenPipe));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn keeps_bytes_read_before_a_timeout() {
+        /// Times out once after the first two bytes of a word
+        struct StallingReader {
+            bytes: io::Cursor<Vec<u8>>,
+            stalled: bool,
+        }
+
+        impl Read for StallingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.bytes.position() == 2 && !self.stalled {
+                    self.stalled = true;
+                    return Err(ErrorKind::TimedOut.into());
+                }
+                self.bytes.read(buf)
             }
         }
+
+        let mut reader = StallingReader {
+            bytes: io::Cursor::new(0x1234_5678_u32.to_le_bytes().to_vec()),
+            stalled: false,
+        };
+        assert_eq!(read_u32(&mut reader).ok(), Some(0x1234_5678));
+        assert!(reader.stalled);
+    }
+
+    #[test]
+    fn checksums_match_crc32_check_value() {
+        let mut writer = Crc32Writer::new(Vec::new());
+        assert_eq!(writer.write_all(b"1234").map_err(|err| err.kind()), Ok(()));
+        assert_eq!(writer.write_all(b"56789").map_err(|err| err.kind()), Ok(()));
+        assert_eq!(writer.checksum(), 0xCBF4_3926);
+        assert_eq!(writer.inner, b"123456789");
+    }
+
+    #[test]
+    fn checksum_of_nothing_is_zero() {
+        assert_eq!(Crc32Writer::new(io::sink()).checksum(), 0);
+    }
+
+    #[test]
+    fn resends_with_doubling_delays_until_limit() {
+        let mut retries = TransferRetries::new(3);
+        assert_eq!(retries.failed(), Some(INITIAL_BACKOFF));
+        assert_eq!(retries.failed(), Some(INITIAL_BACKOFF * 2));
+        assert_eq!(retries.failed(), Some(INITIAL_BACKOFF * 4));
+        assert_eq!(retries.failed(), None);
+    }
+
+    #[test]
+    fn success_resets_retry_count() {
+        let mut retries = TransferRetries::new(1);
+        assert_eq!(retries.failed(), Some(INITIAL_BACKOFF));
+        retries.succeeded();
+        assert_eq!(retries.failed(), Some(INITIAL_BACKOFF));
+        assert_eq!(retries.failed(), None);
     }
 }
@@ -1,5 +1,5 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{parse_macro_input, Data, DeriveInput, Fields, Type};
 
 #[proc_macro_derive(AsBits)]
@@ -24,7 +24,7 @@ pub fn as_bits(input: TokenStream) -> TokenStream {
         })
         .expect("Enum should specify a primitive representation");
 
-    let arms: Box<_> = data_enum
+    let variants: Box<_> = data_enum
         .variants
         .iter()
         .map(|variant| {
@@ -32,20 +32,32 @@ pub fn as_bits(input: TokenStream) -> TokenStream {
                 Fields::Unit => {}
                 _ => panic!("Cannot apply `AsBits` to an enum with a non-unit variant"),
             }
-
-            let variant_name = &variant.ident;
-            let discriminant = &variant
-                .discriminant
-                .as_ref()
-                .expect("All enum variants should specify their discriminant")
-                .1;
-
-            quote! {
-                #discriminant => Self::#variant_name,
-            }
+            &variant.ident
         })
         .collect();
 
+    // Variants may omit their discriminants, or specify them with expressions that are not valid
+    // patterns, so each discriminant is matched through a constant that the compiler evaluates
+    let constants: Box<_> = (0..variants.len())
+        .map(|index| format_ident!("DISCRIMINANT_{index}"))
+        .collect();
+    let discriminants = variants
+        .iter()
+        .zip(constants.iter())
+        .map(|(variant_name, constant)| {
+            quote! { const #constant: #repr_size = #enum_name::#variant_name as #repr_size; }
+        });
+    let discriminants = quote! { #(#discriminants)* };
+
+    let arms = variants
+        .iter()
+        .zip(constants.iter())
+        .map(|(variant_name, constant)| quote! { #constant => Self::#variant_name, });
+    let try_arms = variants
+        .iter()
+        .zip(constants.iter())
+        .map(|(variant_name, constant)| quote! { #constant => ::core::result::Result::Ok(Self::#variant_name), });
+
     quote! {
         impl #enum_name {
             pub const fn into_bits(self) -> #repr_size {
@@ -53,12 +65,27 @@ pub fn as_bits(input: TokenStream) -> TokenStream {
             }
 
             pub const fn from_bits(value: #repr_size) -> Self {
+                #discriminants
                 match value {
                     #(#arms)*
                     _ => panic!("Unexpected value for enum")
                 }
             }
         }
+
+        impl ::core::convert::TryFrom<#repr_size> for #enum_name {
+            type Error = #repr_size;
+
+            /// Converts the raw value into the enum, returning the value back if it does not
+            /// correspond to any variant
+            fn try_from(value: #repr_size) -> ::core::result::Result<Self, Self::Error> {
+                #discriminants
+                match value {
+                    #(#try_arms)*
+                    _ => ::core::result::Result::Err(value)
+                }
+            }
+        }
     }
     .into()
 }
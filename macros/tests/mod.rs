@@ -26,8 +26,23 @@ mod tests {
         assert_eq!(Enum::from_bits(THIRD_VALUE), Enum::Third);
     }
 
+    #[test]
+    fn try_from_valid() {
+        assert_eq!(Enum::try_from(FIRST_VALUE), Ok(Enum::First));
+        assert_eq!(Enum::try_from(SECOND_VALUE), Ok(Enum::Second));
+        assert_eq!(Enum::try_from(THIRD_VALUE), Ok(Enum::Third));
+    }
+
+    #[test]
+    fn try_from_invalid() {
+        for i in [1, SECOND_VALUE + 1, THIRD_VALUE - 1, u16::MAX] {
+            assert_eq!(Enum::try_from(i), Err(i));
+        }
+    }
+
     #[test]
     #[should_panic]
+    #[allow(clippy::manual_contains)]
     fn from_bits_invalid() {
         for i in 0.. {
             if ![FIRST_VALUE, SECOND_VALUE, THIRD_VALUE]
@@ -38,4 +53,60 @@ mod tests {
             }
         }
     }
+
+    #[derive(macros::AsBits, PartialEq, Debug, Clone, Copy)]
+    #[repr(u8)]
+    enum Implicit {
+        Zero,
+        One,
+        Two,
+    }
+
+    #[derive(macros::AsBits, PartialEq, Debug, Clone, Copy)]
+    #[repr(u16)]
+    enum Mixed {
+        Zero,
+        Ten = 10,
+        Eleven,
+        Shifted = 1 << 8,
+        AfterShifted,
+    }
+
+    #[test]
+    fn implicit_discriminants() {
+        assert_eq!(Implicit::Zero.into_bits(), 0);
+        assert_eq!(Implicit::One.into_bits(), 1);
+        assert_eq!(Implicit::Two.into_bits(), 2);
+        assert_eq!(Implicit::try_from(3), Err(3));
+    }
+
+    #[test]
+    fn mixed_discriminants() {
+        assert_eq!(Mixed::Zero.into_bits(), 0);
+        assert_eq!(Mixed::Ten.into_bits(), 10);
+        assert_eq!(Mixed::Eleven.into_bits(), 11);
+        assert_eq!(Mixed::Shifted.into_bits(), 256);
+        assert_eq!(Mixed::AfterShifted.into_bits(), 257);
+        for i in [1, 9, 12, 255, 258] {
+            assert_eq!(Mixed::try_from(i), Err(i));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for variant in [Implicit::Zero, Implicit::One, Implicit::Two] {
+            assert_eq!(Implicit::from_bits(variant.into_bits()), variant);
+            assert_eq!(Implicit::try_from(variant.into_bits()), Ok(variant));
+        }
+        for variant in [
+            Mixed::Zero,
+            Mixed::Ten,
+            Mixed::Eleven,
+            Mixed::Shifted,
+            Mixed::AfterShifted,
+        ] {
+            assert_eq!(Mixed::from_bits(variant.into_bits()), variant);
+            assert_eq!(Mixed::try_from(variant.into_bits()), Ok(variant));
+        }
+    }
 }
@@ -0,0 +1,69 @@
+//! Typed wrappers around the AArch64 memory barrier instructions
+//!
+//! Prefer these to inline `dsb`/`dmb`/`isb` so that the barrier domain and access type at each
+//! site are explicit.
+
+use core::arch::asm;
+
+/// Generates a safe wrapper around a barrier instruction. Barriers have no effect other than
+/// ordering, so they are always safe to execute
+macro_rules! barrier {
+    ($(#[$meta:meta])* $name:ident, $instruction:literal) => {
+        $(#[$meta])*
+        #[inline(always)]
+        pub fn $name() {
+            // SAFETY: Barriers only constrain ordering, and have no other side effects
+            unsafe { asm!($instruction, options(nostack, preserves_flags)) }
+        }
+    };
+}
+
+barrier!(
+    /// Full-system data synchronization barrier. Use before operations whose effects must be
+    /// visible to every observer, such as before a `wfi`/`wfe` hand-off to other agents or when
+    /// the required shareability domain is not known
+    dsb_sy,
+    "dsb sy"
+);
+barrier!(
+    /// Inner-shareable data synchronization barrier. Use after TLB maintenance so that the
+    /// invalidation completes across all cores before continuing
+    dsb_ish,
+    "dsb ish"
+);
+barrier!(
+    /// Inner-shareable store data synchronization barrier. Use after writing translation table
+    /// descriptors so that the table walker observes the new descriptors
+    dsb_ishst,
+    "dsb ishst"
+);
+barrier!(
+    /// Instruction synchronization barrier. Use after changing system registers, translation
+    /// tables (following a `dsb`), or code, so that subsequent instructions observe the change
+    isb,
+    "isb"
+);
+barrier!(
+    /// Inner-shareable data memory barrier. Orders accesses to normal memory shared between
+    /// cores
+    dmb_ish,
+    "dmb ish"
+);
+barrier!(
+    /// Inner-shareable store data memory barrier. Orders stores to normal memory shared between
+    /// cores
+    dmb_ishst,
+    "dmb ishst"
+);
+barrier!(
+    /// Outer-shareable data memory barrier. Use around peripheral accesses, so that accesses to
+    /// different peripherals are not reordered with respect to each other
+    dmb_osh,
+    "dmb osh"
+);
+barrier!(
+    /// Outer-shareable store data memory barrier. Use before handing a buffer to a peripheral
+    /// (e.g. the mailbox or DMA) so that its contents are written out first
+    dmb_oshst,
+    "dmb oshst"
+);
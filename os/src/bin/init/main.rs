@@ -11,6 +11,7 @@
 #![feature(const_mut_refs)]
 #![feature(never_type)]
 
+use common::boot_image;
 use common::os::vm::{self, AddressSpace, ADDRESS_SPACE};
 use common::println;
 use common::sync::SpinLock;
@@ -19,7 +20,6 @@ mod syscalls;
 use core::sync::atomic::Ordering;
 use core::{
     alloc::GlobalAlloc,
-    arch::asm,
     fmt::Write,
     hint, mem,
     panic::PanicInfo,
@@ -30,6 +30,9 @@ use exception::CONTEXT;
 
 use crate::syscalls::getpid;
 
+/// Magic number at the start of every device tree blob, stored big endian
+const DTB_MAGIC: u32 = 0xD00D_FEED;
+
 const INIT_TABLE_ENTRY_BASE: u64 = (1 << 53) // Privileged execute-never
 | (1    << 11) // Non-global entry
 | (1    << 10) // Access flag
@@ -129,22 +132,13 @@ impl Write for Stdout {
     }
 }
 
-fn temporary_map(va: usize, pa: u64) {
-    let index = va >> 16;
-    unsafe {
-        asm! {
-            "str {}, [{}]",
-            in(reg) INIT_TABLE_ENTRY_BASE | pa,
-            in(reg) index * 8,
-        }
-    }
-}
-
 /// The entry point of the init program. Spawns all the other programs before exiting
 /// # Safety
-/// `next_part` and `next_len` must describe a valid, accessible ELF in memory, including padding bytes to the nearest `u64` boundary.
+/// `next_part` and `next_len` must describe a valid, accessible, writable boot image table in
+/// memory, as laid out by `common::boot_image`, including padding bytes to the nearest `u64`
+/// boundary.
 /// # Panics
-/// Panics if `next_part` is not page aligned
+/// Panics if `next_part` is not page aligned, or if the table does not contain the pipe server
 unsafe extern "C" fn main(next_part: *mut u64, next_len: u16, pa: usize) -> ! {
     ADDRESS_SPACE.set(SpinLock::new(unsafe {
         AddressSpace::new(NonNull::new(0x1FF_0000 as *mut _).expect("Received a null page table"))
@@ -152,29 +146,52 @@ unsafe extern "C" fn main(next_part: *mut u64, next_len: u16, pa: usize) -> ! {
     let mut uart = Stdout {};
     syscalls::write("Hello from usermode!\n".as_bytes());
     println!("PID: {:X}", getpid());
+
+    // The kernel hands the device tree to init, so that it can be passed on to drivers
+    let (dtb_pa, dtb_size) = syscalls::get_dtb_info();
+    // SAFETY: The kernel maps the device tree page read-only at this address before starting init
+    let dtb_magic = unsafe { (vm::DEVICE_TREE_ADDRESS as *const u32).read_volatile() };
+    assert_eq!(
+        u32::from_be(dtb_magic),
+        DTB_MAGIC,
+        "Device tree should be mapped at its well-known address"
+    );
+    println!("Device tree: {dtb_size:#X} bytes at {dtb_pa:#X}");
     assert!(!next_part.is_null());
     assert_eq!(pa & 0xFFFF, 0);
 
-    // SAFETY: The caller promises that the arguments refer to a valid ELF, padding included
+    // SAFETY: The caller promises that the arguments refer to a valid boot image table
+    let table =
+        unsafe { core::slice::from_raw_parts_mut(next_part.cast::<u8>(), usize::from(next_len)) };
+    let pipe = boot_image::find(table, "pipe").expect("Boot image should contain the pipe server");
+    let (offset, len) = (pipe.offset, pipe.len);
+    // Loading requires the ELF to be page aligned, so move it to the start of the page
+    table.copy_within(offset..offset + len, 0);
+
+    // SAFETY: The ELF now begins at `next_part`, and the page holds the padding after it
     let elf = unsafe {
-        core::slice::from_raw_parts(
-            next_part,
-            usize::from(next_len).div_ceil(mem::size_of::<usize>()),
-        )
+        core::slice::from_raw_parts_mut(next_part, len.div_ceil(mem::size_of::<usize>()))
     };
 
     // alloc new pd
     let new_pd = syscalls::alloc_page().unwrap();
     writeln!(&mut uart, "got {:X}\n", new_pd);
-    temporary_map(0x2_0000, new_pd);
-    let virt_new_pd = 0x2_0000 as *mut _;
-    let mut address_space: AddressSpace<16, 25> = unsafe {
-        AddressSpace::new(NonNull::new(virt_new_pd).expect("Received a null page table"))
-    };
 
     //elf load
-    let (entry, bss_start, bss_end, ctx, sp) =
-        vm::load_elf(&mut address_space, new_pd, elf, pa.try_into().unwrap(), &[]).unwrap();
+    let (entry, bss_start, bss_end, ctx, sp) = vm::with_temp_map(0x2_0000, new_pd, |table| {
+        let mut address_space: AddressSpace<16, 25> =
+            unsafe { AddressSpace::new(NonNull::from(table).cast()) };
+        vm::load_elf(
+            &mut address_space,
+            new_pd,
+            elf,
+            pa.try_into().unwrap(),
+            &["pipe"],
+            &[],
+        )
+    })
+    .expect("The new table's scratch address should not be mapped")
+    .unwrap();
 
     // fork+exec into it
 
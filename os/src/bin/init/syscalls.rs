@@ -50,6 +50,7 @@ pub fn exit() -> ! {
         unsafe {
             core::arch::asm! {
                 "svc 0x2000",
+                in("x0") 0_u64,
                 options(nostack, readonly),
                 clobber_abi("C"),
             }
@@ -78,6 +79,27 @@ pub fn exec(context: *mut (), ttbr0: u64, tcr_el1: u64, sp: usize) -> Result<!,
     }
 }
 
+/// Returns the physical address and size, in bytes, of the device tree page. The page is owned
+/// read-only by this program, and mapped at `common::os::vm::DEVICE_TREE_ADDRESS`
+#[inline]
+pub fn get_dtb_info() -> (u64, u16) {
+    let info: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for a device tree information syscall
+    unsafe {
+        core::arch::asm! {
+            "svc 0xC000",
+            out("x0") _,
+            out("x1") info,
+            options(nomem, nostack),
+            clobber_abi("C"),
+        }
+    };
+    (
+        info & u64::from(u32::MAX),
+        u16::try_from(info >> 32).expect("Device tree size should fit in 16 bits"),
+    )
+}
+
 #[inline]
 pub fn getpid() -> u16 {
     let pid: u64;
@@ -149,3 +171,52 @@ pub fn fork() -> Option<u16> {
         None
     }
 }
+
+/// Gives up the remainder of this program's timeslice, returning once it is scheduled again
+#[inline]
+pub fn sched_yield() {
+    let ra_location = CONTEXT.exception_stack.fetch_ptr_add(1, Ordering::Relaxed);
+    unsafe {
+        core::arch::asm! {
+            "stp x19, x29, [sp, -16]!",
+            "sub x1, sp, 0x100",
+            "adr x2, {saved_sp}",
+            "str x1, [x2]",
+            "adr x2, 0f",
+            "str x2, [x0]",
+            "svc 0x9000",
+            "0: ldp x19, x29, [sp], 16",
+            saved_sp = sym crate::exception::SP,
+            inlateout("x0") ra_location => _,
+            lateout("x1") _,
+            lateout("x2") _,
+            lateout("x3") _,
+            lateout("x4") _,
+            lateout("x5") _,
+            lateout("x6") _,
+            lateout("x7") _,
+            lateout("x8") _,
+            lateout("x9") _,
+            lateout("x10") _,
+            lateout("x11") _,
+            lateout("x12") _,
+            lateout("x13") _,
+            lateout("x14") _,
+            lateout("x15") _,
+            lateout("x16") _,
+            lateout("x17") _,
+            lateout("x18") _,
+            lateout("x20") _,
+            lateout("x21") _,
+            lateout("x22") _,
+            lateout("x23") _,
+            lateout("x24") _,
+            lateout("x25") _,
+            lateout("x26") _,
+            lateout("x27") _,
+            lateout("x28") _,
+            lateout("x30") _,
+            clobber_abi("C"),
+        }
+    };
+}
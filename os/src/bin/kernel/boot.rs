@@ -2,7 +2,11 @@
 //! necessary virtual memory, stack, BSS, and anything else necessary for safe Rust execution to
 //! begin
 
+use common::barrier;
+use common::os::memory_layout;
+use core::arch::asm;
 use core::mem::MaybeUninit;
+use core::ptr::{self, addr_of_mut, NonNull};
 
 /// Page size for the kernel, # bits. These are huge pages, 2MB each
 const PAGE_BITS: u32 = 21;
@@ -12,10 +16,23 @@ const ADDRESS_BITS: u32 = 25;
 /// Physical address that the kernel is loaded to
 const PHYSICAL_LOAD_ADDR: usize = 0x8_0000;
 /// Virtual address that the kernel is linked to
-const VIRTUAL_LINK_ADDR: usize = 0xFFFF_FFFF_FE08_0000;
+const VIRTUAL_LINK_ADDR: usize = memory_layout::KERNEL_VA_BASE + PHYSICAL_LOAD_ADDR;
 /// Shift to transform from physical to virtual addresses
 const VIRTUAL_OFFSET: usize = VIRTUAL_LINK_ADDR - PHYSICAL_LOAD_ADDR;
 
+/// Descriptor bits for a block of normal memory in the kernel's translation table
+const BLOCK_ENTRY_BASE: u64 = (1_u64 << 54) // Unprivileged execute-never
+    | (1    << 10) // Access flag
+    | (0b11 << 8)  // Shareability
+    |  0b01; // Valid entry (Block descriptor)
+
+/// Index of the first kernel translation table entry reserved as a scratch window, for transiently
+/// accessing arbitrary physical memory. The entries before it map the kernel image, UART, mailbox
+/// and GIC, so the windows begin at `0xFFFF_FFFF_FE80_0000`
+const SCRATCH_ENTRY_START: usize = 4;
+/// Number of scratch windows, each one 2MB block
+pub const SCRATCH_WINDOWS: usize = 2;
+
 /// Kernel translation table struct
 #[repr(C)]
 #[repr(align(4096))]
@@ -37,14 +54,14 @@ core::arch::global_asm! {
     "rev w1, w1",      // Convert to host (little) endianness
     "and x1, x1, 0xFFFF", // Only handle sizes under 64K for simplicity
                           // (fits into a single page that can be passed to the init program)
-    "mov x2, 0x10000",
+    "ldr x2, ={DEVICE_TREE_ADDRESS}",
     "mov x3, x1",
     "0: subs x3, x3, 8",
     "ldr x4, [x0], 8",
     "str x4, [x2], 8",
     "b.pl 0b",
 
-    "add x0, x8, 0x10000", // Put the VA into x0 for the main init sequence
+    "add x0, x8, {DEVICE_TREE_ADDRESS}", // Put the VA into x0 for the main init sequence
 
     // Move the init program to a suitable location
     "adr x2, __init_start", // The end of the kernel data, and where init data begins
@@ -170,6 +187,7 @@ core::arch::global_asm! {
           (0b11 << 24) // Disable SME trapping
         | (0b11 << 20) // Disable FP trapping
         | (0b11 << 16), // Disable SVE trapping,
+    DEVICE_TREE_ADDRESS = const crate::DEVICE_TREE_PHYSICAL_ADDRESS,
     HCR_EL2 = const
           (1_u64 << 56) // Allow allocation tag access
         | (1     << 41) // Disables pointer authentication trapping
@@ -224,11 +242,7 @@ core::arch::global_asm! {
         | (1     << 2), // Data caching,
     SPSR_EL2 = const (0b1111 << 6) | 0b0101, // Use SP_EL1 with interrupts disabled
     STACK_SIZE = const STACK_SIZE,
-    TABLE_ENTRY_BASE = const
-          (1_u64 << 54)  // Unprivileged execute-never
-        | (1    << 10) // Access flag
-        | (0b11 << 8)  // Shareability
-        |  0b01, // Valid entry (Block descriptor)
+    TABLE_ENTRY_BASE = const BLOCK_ENTRY_BASE,
     TCR_EL1 = const
     #[expect(
         clippy::as_conversions,
@@ -260,3 +274,59 @@ core::arch::global_asm! {
     UART_ADDRESS = const 0x4_7E20_0000_u64,
     VIRTUAL_OFFSET = const VIRTUAL_OFFSET,
 }
+
+/// Gets the virtual address covered by the given kernel translation table entry
+const fn entry_address(index: usize) -> usize {
+    // The first entry maps physical address 0
+    VIRTUAL_OFFSET + (index << PAGE_BITS)
+}
+
+/// Maps the 2MB block containing `pa` into the given scratch window, and returns the virtual
+/// address at which `pa` is then accessible
+///
+/// # Safety
+///
+/// The window must be unmapped, and must not be used by anyone else until it is unmapped again
+/// with `unmap_scratch`. The physical memory must be normal memory
+pub unsafe fn map_scratch(window: usize, pa: u64) -> NonNull<u8> {
+    assert!(window < SCRATCH_WINDOWS, "Scratch window should exist");
+    let index = SCRATCH_ENTRY_START + window;
+    let block_mask = (1_u64 << PAGE_BITS) - 1;
+    // SAFETY: The caller promises exclusive use of this window's entry, which the boot sequence
+    // left unmapped
+    unsafe {
+        addr_of_mut!(TRANSLATION_TABLE.0[index])
+            .write_volatile(MaybeUninit::new(BLOCK_ENTRY_BASE | (pa & !block_mask)));
+    }
+    barrier::dsb_ishst();
+    barrier::isb();
+    let offset = usize::try_from(pa & block_mask).expect("Block offsets should fit in a `usize`");
+    NonNull::new(ptr::from_exposed_addr_mut(entry_address(index) + offset))
+        .expect("Scratch windows should not be at null")
+}
+
+/// Unmaps the given scratch window, invalidating any stale translations for it
+///
+/// # Safety
+///
+/// No references into the window may remain
+pub unsafe fn unmap_scratch(window: usize) {
+    assert!(window < SCRATCH_WINDOWS, "Scratch window should exist");
+    let index = SCRATCH_ENTRY_START + window;
+    // SAFETY: The caller promises exclusive use of this window's entry, and that nothing refers
+    // to it anymore
+    unsafe {
+        addr_of_mut!(TRANSLATION_TABLE.0[index]).write_volatile(MaybeUninit::new(0));
+    }
+    barrier::dsb_ishst();
+    // SAFETY: TLB invalidations are always safe
+    unsafe {
+        asm! {
+            "tlbi VAAE1IS, {}",
+            in(reg) (entry_address(index) >> 12) & ((1 << 44) - 1),
+            options(nomem, nostack, preserves_flags)
+        };
+    }
+    barrier::dsb_ish();
+    barrier::isb();
+}
@@ -7,6 +7,7 @@ use macros::AsBits;
 
 use crate::{
     exception::page_fault::{AccessType, StatusCode},
+    execution::{self, EXECUTIONS},
     machine::{self, faulting_address},
 };
 
@@ -136,8 +137,21 @@ impl DataAbortIS {
         (!self.far_not_valid()).then(machine::faulting_address)
     }
 }
-/// Handles a data abort
+/// Handles a data abort. A write to a shared read-only page is resolved as copy-on-write and
+/// retried; every other abort is a page fault
 pub fn handle(iss: DataAbortIS, x0: usize, x1: usize) -> (usize, usize) {
+    if let (StatusCode::PermissionFault, true, Some(address)) =
+        (iss.status_code(), iss.was_write(), iss.faulting_address())
+    {
+        let copied = EXECUTIONS
+            .read()
+            .get(execution::current())
+            .expect("Data aborts should not trigger outside the context of a valid `Execution`")
+            .break_copy_on_write(address);
+        if copied {
+            return (x0, x1);
+        }
+    }
     page_fault::resolve_page_fault(
         &PageFaultInfo {
             access_type: if iss.was_write() {
@@ -10,10 +10,12 @@ use bitfield_struct::bitfield;
 /// The instruction syndrome whenever an Instruction Abort is taken
 #[bitfield(u32)]
 pub struct InstructionAbortIS {
-    /// Level of translation at which the data abort occurred. Not always meaningful.
+    /// Level of translation at which the instruction abort occurred. Not always meaningful.
     #[bits(2)]
     level: u8,
-    /// Status code indicating the cause of the data abort
+    /// Status code indicating the cause of the instruction abort. A translation fault means the
+    /// fetched address had no valid translation, while a permission fault means the address is
+    /// mapped but not executable from EL0
     #[bits(4)]
     status_code: StatusCode,
     _res0: bool,
@@ -73,7 +75,12 @@ impl InstructionAbortIS {
     }
 }
 
-/// Handles an instruciton abort
+/// Handles an instruction abort
+///
+/// There is no backing store to page code in from, so no page is ever mapped here. A translation
+/// fault is only retried if the execution's own tables translate the address by the time the
+/// kernel checks them. Every other fault, including permission faults, is delivered to the
+/// execution's page fault handler, which is told whether it was a permission fault
 pub fn handle(iss: InstructionAbortIS, x0: usize, x1: usize) -> (usize, usize) {
     // assert!(iss.instruction_syndrome_valid());
     page_fault::resolve_page_fault(
@@ -0,0 +1,73 @@
+//! Runtime registration of IRQ handlers, and control over which IRQs are delivered
+//!
+//! Registration and dispatch share a lock, so handlers must only be registered while IRQs are
+//! masked on the current core (as is the case throughout the kernel), and handlers must not
+//! themselves register further handlers.
+
+use super::gic::GICD_START;
+use common::sync::RwLock;
+use core::ptr;
+
+/// A handler for an IRQ, given the ID of the interrupt that occurred.
+///
+/// Returns whether the interrupt was handled; if not, the next handler registered for the same ID
+/// is tried
+pub type Handler = fn(u32) -> bool;
+
+/// Maximum number of handlers that may be registered across all IRQs
+const MAX_HANDLERS: usize = 16;
+
+/// Registered handlers, in order of registration. This is a fixed-size table rather than a heap
+/// allocation, since the timer handler is registered before the kernel heap is set up
+static HANDLERS: RwLock<[Option<(u32, Handler)>; MAX_HANDLERS]> = RwLock::new([None; MAX_HANDLERS]);
+
+/// Offset of the distributor's set-enable registers
+const GICD_ISENABLER: usize = 0x100;
+/// Offset of the distributor's clear-enable registers
+const GICD_ICENABLER: usize = 0x180;
+
+/// Registers a handler for the given IRQ. Handlers for the same IRQ are chained, and tried in
+/// the order that they were registered
+///
+/// # Panics
+///
+/// Panics if `MAX_HANDLERS` handlers are already registered
+pub fn register(id: u32, handler: Handler) {
+    *HANDLERS
+        .write()
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("There should be room for another IRQ handler") = Some((id, handler));
+}
+
+/// Writes the bit for the given IRQ to the given bank of distributor enable registers
+fn write_enable_bit(bank: usize, id: u32) {
+    let register = GICD_START
+        + bank
+        + usize::try_from(id / 32).expect("IRQ register indices should fit into a `usize`") * 4;
+    // SAFETY: The distributor's enable registers are permanently mapped, and writing a single set
+    // bit only affects the selected IRQ
+    unsafe { ptr::write_volatile(register as *mut u32, 1 << (id % 32)) };
+}
+
+/// Enables delivery of the given IRQ from the distributor
+pub fn enable(id: u32) {
+    write_enable_bit(GICD_ISENABLER, id);
+}
+
+/// Disables delivery of the given IRQ from the distributor
+pub fn disable(id: u32) {
+    write_enable_bit(GICD_ICENABLER, id);
+}
+
+/// Invokes the handlers registered for the given IRQ until one handles it.
+///
+/// Returns whether any handler handled the interrupt
+pub(super) fn dispatch(id: u32) -> bool {
+    HANDLERS
+        .read()
+        .iter()
+        .map_while(Option::as_ref)
+        .filter(|&&(handler_id, _)| handler_id == id)
+        .any(|&(_, handler)| handler(id))
+}
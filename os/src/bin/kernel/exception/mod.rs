@@ -1,10 +1,12 @@
 //! Primary exception handlers
 
 use crate::exception::svc::CallCode;
-use crate::println;
+use crate::{execution, log, timer};
 use bitfield_struct::bitfield;
-use core::arch::{asm, global_asm};
-use core::fmt;
+use common::sync::SpinLock;
+use core::arch::global_asm;
+use core::fmt::{self, Write};
+use core::time::Duration;
 use macros::AsBits;
 
 use svc::Return;
@@ -12,6 +14,7 @@ use svc::Return;
 mod data_abort;
 mod gic;
 mod instruction_abort;
+pub mod irq;
 pub mod page_fault;
 mod svc;
 
@@ -278,6 +281,36 @@ pub fn init() {
         };
     };
     gic::init();
+    irq::register(TIMER_IRQ, handle_timer);
+    irq::enable(TIMER_IRQ);
+}
+
+/// IRQ ID of the EL1 physical timer, which drives preemption
+const TIMER_IRQ: u32 = 30;
+
+/// How soon to retry waking sleepers when the execution map was locked for writing. The writer may
+/// be the code that the timer interrupted, so this leaves it time to finish
+const SLEEPER_RETRY: Duration = Duration::from_millis(1);
+
+/// Wakes any sleepers whose deadlines have passed, and rearms the timer for the next timeslice
+fn handle_timer(interrupt: u32) -> bool {
+    let now = timer::counter();
+    let next_tick = now.saturating_add(timer::frequency());
+    // Fire again at the next tick, or sooner if a sleeper needs to be woken before then
+    let deadline = match execution::wake_sleepers(now) {
+        Ok(next_sleeper) => next_sleeper.map_or(next_tick, |deadline| deadline.min(next_tick)),
+        Err(execution::ExecutionsLocked) => now
+            .saturating_add(timer::duration_to_ticks(SLEEPER_RETRY))
+            .min(next_tick),
+    };
+    timer::set_deadline(deadline);
+    // The interrupted code may hold the UART lock, so waiting on it could deadlock this core
+    if log::enabled(log::Level::Debug) {
+        if let Some(mut uart) = crate::UART.get().and_then(SpinLock::try_lock) {
+            writeln!(&mut uart, "Handle IRQ {interrupt}").unwrap();
+        }
+    }
+    true
 }
 
 /// Handles any IRQ exceptions
@@ -285,23 +318,13 @@ extern "C" fn irq_exception() {
     let interrupt_info =
         unsafe { core::ptr::read_volatile((0xFFFF_FFFF_FE64_2000_usize + 0x000C) as *mut u32) };
 
-    // preemption
-    if interrupt_info & ((1 << 10) - 1) == 30 {
-        let freq: u64;
-        unsafe {
-            asm!("mrs {}, CNTFRQ_EL0", out(reg) freq);
-        }
-        unsafe {
-            asm!("msr CNTP_TVAL_EL0, {}", in(reg) freq);
-        }
-
+    if irq::dispatch(interrupt_info & ((1 << 10) - 1)) {
         unsafe {
             core::ptr::write_volatile(
                 (0xFFFF_FFFF_FE64_2000_usize + 0x0010) as *mut u32,
                 interrupt_info,
             )
         }; // eoir
-        println!("Handle IRQ {}", interrupt_info);
     } else {
         todo!("Handle IRQ {:X}", interrupt_info);
     }
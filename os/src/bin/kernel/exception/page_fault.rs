@@ -3,9 +3,9 @@ use core::{arch::asm, ptr};
 use macros::AsBits;
 
 use crate::{
-    execution::{self, ExceptionCode, EXECUTIONS},
+    debug,
+    execution::{self, ExceptionCode, Execution, EXECUTIONS},
     machine::faulting_address,
-    println,
 };
 
 /// Access type that caused the page fault
@@ -48,9 +48,30 @@ pub(super) struct PageFaultInfo {
     pub access_bytes: u8,
 }
 
+impl PageFaultInfo {
+    /// Bit of the delivered faulting information that is set for permission faults, as opposed to
+    /// faults on addresses without a valid translation
+    const PERMISSION_FAULT: u64 = 1 << 63;
+    /// Bit of the delivered faulting information that is set for instruction fetches
+    const INSTRUCTION_FETCH: u64 = 1 << 62;
+
+    /// Encodes this fault for the execution's page fault handler. User addresses never reach the
+    /// top bits, so those carry the kind of fault above the faulting address
+    fn delivered(&self) -> u64 {
+        let mut info = faulting_address();
+        if let StatusCode::PermissionFault = self.code {
+            info |= Self::PERMISSION_FAULT;
+        }
+        if let AccessType::Instruction = self.access_type {
+            info |= Self::INSTRUCTION_FETCH;
+        }
+        info
+    }
+}
+
 /// Resolves a page fault by either autofilling the translation, or invoking the execution's page fault handler
 pub(super) fn resolve_page_fault(info: &PageFaultInfo, x0: usize, x1: usize) -> (usize, usize) {
-    println!("PAGE FAULT: {:X?}", info.faulting_address);
+    debug!("PAGE FAULT: {:X?}", info.faulting_address);
     let executions = EXECUTIONS.read();
     let current = executions
         .get(execution::current())
@@ -87,11 +108,15 @@ pub(super) fn resolve_page_fault(info: &PageFaultInfo, x0: usize, x1: usize) ->
         }
     };
     if call_signal {
-        println!("Call signal handler!");
-        unsafe { current.prepare_synchronous_jump(x0, x1) };
+        debug!("Call signal handler!");
+        if unsafe { current.prepare_synchronous_jump(x0, x1) }.is_none() {
+            let pid = current.pid;
+            drop(executions);
+            Execution::exit(pid, execution::FAULT_STATUS);
+        }
         (
             ExceptionCode::PageFault as usize,
-            faulting_address().try_into().unwrap(),
+            info.delivered().try_into().unwrap(),
         )
     } else {
         (x0, x1)
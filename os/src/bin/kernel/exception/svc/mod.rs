@@ -1,15 +1,18 @@
 //! System call handlers
 
-use core::{arch::asm, ptr};
+use core::{arch::asm, mem, ptr, time::Duration};
 
 use alloc::sync::Arc;
 use bitfield_struct::bitfield;
 use macros::AsBits;
 
 use crate::{
-    execution::{self, ContextError, ExceptionCode, Execution, EXECUTIONS},
-    memory::PAGE_ALLOCATOR,
-    println, UART,
+    execution::{
+        self, AffinityError, ContextError, ExceptionCode, Execution, ForkError, MapError,
+        PriorityError, TimeoutStatus, WaitStatus, EXECUTIONS,
+    },
+    memory::{device, PAGE_ALLOCATOR, PAGE_SIZE},
+    println, random, timer, UART,
 };
 
 use super::ExceptionSyndrome;
@@ -27,6 +30,20 @@ pub(super) enum CallCode {
     Block = 0x6000,
     SendSignal = 0x7000,
     Fork = 0x8000,
+    Yield = 0x9000,
+    Sleep = 0xA000,
+    MemInfo = 0xB000,
+    GetDtbInfo = 0xC000,
+    WaitPid = 0xD000,
+    SetPriority = 0xE000,
+    MapDevice = 0xF000,
+    GetRandom = 0x1100,
+    Uptime = 0x1200,
+    ListProcesses = 0x1300,
+    BlockTimeout = 0x1400,
+    SetAffinity = 0x1500,
+    Mmap = 0x1600,
+    InvalidateTranslation = 0x1700,
     Eret = 0x0,
 }
 
@@ -94,6 +111,77 @@ enum SetContextFailure {
     InvalidTcrBits = 0b100,
     InaccessibleUserContext = 0b110,
     MisalignedUserContext = 0b111,
+    ConcurrentUpdate = 0b1000,
+}
+
+/// Failure codes for `fork`
+#[derive(Debug)]
+enum ForkFailure {
+    /// Not enough memory to duplicate the execution
+    NoMem = 0b10,
+    /// The maximum number of executions are already alive; usermode reports this as `EAGAIN`
+    ProcessLimit = 0b11,
+}
+
+/// Failure codes for `waitpid`
+#[derive(Debug)]
+enum WaitFailure {
+    /// The PID is not a child of the caller, or its exit status was already collected
+    NotChild = 0b10,
+    /// The child has not exited yet; usermode blocks and retries
+    Running = 0b11,
+}
+
+/// Failure codes for `block_timeout`
+#[derive(Debug)]
+enum BlockTimeoutFailure {
+    /// Neither an unblock nor the deadline has arrived yet; usermode blocks and retries
+    Pending = 0b11,
+}
+
+/// Failure codes for `set_priority`
+#[derive(Debug)]
+enum PriorityFailure {
+    /// The requested level does not exist
+    InvalidLevel = 0b10,
+    /// The requested level is more urgent than the caller is permitted
+    AboveCeiling = 0b11,
+}
+
+/// Failure codes for `set_affinity`
+#[derive(Debug)]
+enum AffinityFailure {
+    /// The mask excludes every core; usermode reports this as `EINVAL`
+    NoCores = 0b10,
+}
+
+/// Failure codes for `mmap`
+#[derive(Debug)]
+enum MmapFailure {
+    /// Not every requested page could be allocated, within the quota or otherwise
+    NoMem = 0b10,
+    /// The range is empty, misaligned, already mapped or outside of the caller's address space,
+    /// or the protection flags are invalid
+    InvalidArgument = 0b11,
+}
+
+/// `mmap` protection flag allowing reads, as in POSIX. Mapped memory is always readable, so this
+/// must always be given
+const PROT_READ: u64 = 1 << 0;
+/// `mmap` protection flag allowing writes, as in POSIX
+const PROT_WRITE: u64 = 1 << 1;
+/// `mmap` protection flag allowing execution, as in POSIX
+const PROT_EXEC: u64 = 1 << 2;
+
+/// Failure codes for `map_device`
+#[derive(Debug)]
+enum DeviceFailure {
+    /// The range does not start and end on page boundaries
+    Misaligned = 0b10,
+    /// The range is not within a single known peripheral; usermode reports this as `EPERM`
+    NotPermitted = 0b11,
+    /// Part of the virtual range is already mapped, or outside of the caller's address space
+    InvalidAddress = 0b100,
 }
 
 /// Handles an `eret`
@@ -102,7 +190,11 @@ pub fn handle_eret() {
     let current = executions
         .get(execution::current())
         .expect("Page faults should not trigger outside the context of a valid `Execution`");
-    let return_address = current.user_context().pop();
+    let Some(return_address) = current.user_context().pop(current) else {
+        let pid = current.pid;
+        drop(executions);
+        Execution::exit(pid, execution::FAULT_STATUS);
+    };
     unsafe {
         asm! {
             "msr ELR_EL1, {}",
@@ -128,35 +220,46 @@ pub extern "C" fn handle(arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> Return {
     let esr = ExceptionSyndrome::from(esr_el1);
     let iss = unsafe { esr.instruction_syndrome().svc };
     match iss.code() {
-        CallCode::Exit => Execution::exit(execution::current()),
+        // Only the low byte of the status is kept, as with POSIX exit statuses
+        CallCode::Exit => Execution::exit(execution::current(), arg0.to_le_bytes()[0]),
         CallCode::Print => {
             let data_ptr: *const u8 = ptr::from_exposed_addr(
                 usize::try_from(arg0).expect("usizes and u64s should be interchangeable"),
             );
             let data_len =
                 usize::try_from(arg1).expect("usizes and u64s should be interchangeable");
-            // TODO: actually validate pointers
-            let uart = UART.get().expect("UART should be initialized by now");
-            for offset in 0..data_len {
-                let byte = unsafe { data_ptr.byte_add(offset).read() };
-                uart.lock().write_byte(byte).expect("UART should not fail");
+            let executions = EXECUTIONS.read();
+            let current = executions
+                .get(execution::current())
+                .expect("Syscalls should not occur outside the context of a valid `Execution`");
+            if let Some(data) = current.validate_user_slice(data_ptr, data_len) {
+                let uart = UART.get().expect("UART should be initialized by now");
+                for &byte in data {
+                    uart.lock().write_byte(byte).expect("UART should not fail");
+                }
+                success!()
+            } else {
+                fail!()
             }
-            success!()
         }
         CallCode::AllocPage => {
-            if let Some(result) = PAGE_ALLOCATOR
+            let executions = EXECUTIONS.read();
+            let current = executions
+                .get(execution::current())
+                .expect("Syscalls should not occur outside the context of a valid `Execution`");
+            // Once the quota runs out, usermode sees the same failure as an out-of-memory system
+            if !current.take_page_quota() {
+                fail!()
+            } else if let Some(result) = PAGE_ALLOCATOR
                 .get()
                 .expect("Page allocator should be initialized")
-                .alloc()
+                .alloc_zeroed()
             {
                 let addr = result.addr();
-                EXECUTIONS
-                    .read()
-                    .get(execution::current())
-                    .unwrap()
-                    .add_writable_page(result);
+                current.add_writable_page(result);
                 success!(addr)
             } else {
+                current.return_page_quota();
                 fail!()
             }
         }
@@ -176,6 +279,7 @@ pub extern "C" fn handle(arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> Return {
                         execution::current(),
                         ExceptionCode::Resumption,
                         arg3,
+                        0,
                     );
                 }
                 #[expect(clippy::as_conversions)]
@@ -195,6 +299,9 @@ pub extern "C" fn handle(arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> Return {
                     ContextError::InaccessibleUserContext => {
                         SetContextFailure::InaccessibleUserContext
                     }
+                    ContextError::ConcurrentUpdate => {
+                        SetContextFailure::ConcurrentUpdate
+                    }
                 } as u64),
             }
         }
@@ -216,18 +323,261 @@ pub extern "C" fn handle(arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> Return {
         }
         CallCode::SendSignal => {
             if let Some(target) = EXECUTIONS.read().get(arg0.try_into().unwrap()) {
-                target.add_signal(execution::current());
+                // The word rides along with the signal, for lightweight commands between programs
+                target.send_message(execution::current(), arg1);
                 success!()
             } else {
                 fail!()
             }
         }
         CallCode::Fork => {
-            if let Ok(new_execution) = EXECUTIONS.write().fork(execution::current()) {
-                execution::add_to_running(new_execution);
-                success!(new_execution.into())
+            // The write lock is released before scheduling the child, which reads the map
+            let forked = EXECUTIONS.write().fork(execution::current());
+            match forked {
+                Ok(new_execution) => {
+                    execution::add_to_running(
+                        EXECUTIONS
+                            .read()
+                            .get(new_execution)
+                            .expect("The new execution cannot exit before it first runs"),
+                    );
+                    success!(new_execution.into())
+                }
+                #[expect(clippy::as_conversions)]
+                Err(ForkError::ProcessLimit) => fail!(ForkFailure::ProcessLimit as u64),
+                #[expect(clippy::as_conversions)]
+                Err(ForkError::NoMem) => fail!(ForkFailure::NoMem as u64),
+                Err(ForkError::NoPid | ForkError::SrcNotValid) => {
+                    unreachable!("The current execution should be valid, and PIDs are bounded by the process limit")
+                }
+            }
+        }
+        // The blocking token is left alone, so that an `unblock` racing with this yield is still
+        // observed by a later `block`
+        CallCode::Yield => execution::yield_now(),
+        // Sleeping for no time at all is just giving up the timeslice
+        CallCode::Sleep if arg0 == 0 => execution::yield_now(),
+        CallCode::Sleep => {
+            let ticks = timer::duration_to_ticks(Duration::from_nanos(arg0));
+            // Always wait at least one tick, so that short sleeps still block
+            let deadline = timer::counter().saturating_add(ticks.max(1));
+            let pid = execution::current();
+            execution::sleep_until(pid, deadline);
+            if deadline < timer::deadline() {
+                timer::set_deadline(deadline);
+            }
+            // Like `block`, a pending `unblock` token (including one from an earlier sleep)
+            // ends the sleep early
+            Execution::block(pid);
+            success!()
+        }
+        CallCode::MemInfo => {
+            let allocator = PAGE_ALLOCATOR
+                .get()
+                .expect("Page allocator should be initialized");
+            // Page counts fit comfortably in 32 bits, so both are packed into the return value
+            let free = u32::try_from(allocator.free_page_count()).unwrap_or(u32::MAX);
+            let total = u32::try_from(allocator.total_page_count()).unwrap_or(u32::MAX);
+            success!(u64::from(total) << 32 | u64::from(free))
+        }
+        CallCode::GetDtbInfo => {
+            let size = crate::DEVICE_TREE_SIZE
+                .get()
+                .expect("Device tree should have been validated at boot");
+            // The device tree lives in low memory, so its size can be packed above its address
+            success!(u64::from(*size) << 32 | crate::DEVICE_TREE_PHYSICAL_ADDRESS)
+        }
+        CallCode::WaitPid => {
+            let status = u16::try_from(arg0).map_or(WaitStatus::NotChild, |child| {
+                execution::try_wait(execution::current(), child)
+            });
+            match status {
+                WaitStatus::Exited(status) => success!(status.into()),
+                #[expect(clippy::as_conversions)]
+                WaitStatus::Running => fail!(WaitFailure::Running as u64),
+                #[expect(clippy::as_conversions)]
+                WaitStatus::NotChild => fail!(WaitFailure::NotChild as u64),
+            }
+        }
+        CallCode::SetPriority => {
+            let level = u8::try_from(arg0).unwrap_or(u8::MAX);
+            let result = EXECUTIONS
+                .read()
+                .get(execution::current())
+                .expect("The current execution should be valid")
+                .set_priority(level);
+            match result {
+                Ok(()) => success!(),
+                #[expect(clippy::as_conversions)]
+                Err(PriorityError::InvalidLevel) => fail!(PriorityFailure::InvalidLevel as u64),
+                #[expect(clippy::as_conversions)]
+                Err(PriorityError::AboveCeiling) => fail!(PriorityFailure::AboveCeiling as u64),
+            }
+        }
+        CallCode::MapDevice => {
+            let (pa, va, size) = (arg0, arg1, arg2);
+            if pa % PAGE_SIZE != 0 || va % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+                #[expect(clippy::as_conversions)]
+                fail!(DeviceFailure::Misaligned as u64)
+            } else if size == 0 || !device::is_peripheral(pa, size) {
+                #[expect(clippy::as_conversions)]
+                fail!(DeviceFailure::NotPermitted as u64)
+            } else if EXECUTIONS
+                .read()
+                .get(execution::current())
+                .expect("The current execution should be valid")
+                .map_device(va, pa, size)
+            {
+                success!()
             } else {
-                todo!("out of mem")
+                #[expect(clippy::as_conversions)]
+                fail!(DeviceFailure::InvalidAddress as u64)
+            }
+        }
+        CallCode::GetRandom => {
+            let buffer_ptr: *mut u8 = ptr::from_exposed_addr_mut(
+                usize::try_from(arg0).expect("usizes and u64s should be interchangeable"),
+            );
+            let buffer_len =
+                usize::try_from(arg1).expect("usizes and u64s should be interchangeable");
+            let executions = EXECUTIONS.read();
+            let current = executions
+                .get(execution::current())
+                .expect("Syscalls should not occur outside the context of a valid `Execution`");
+            if current
+                .with_user_slice_writeable(buffer_ptr, buffer_len, random::fill)
+                .is_some()
+            {
+                success!()
+            } else {
+                fail!()
+            }
+        }
+        CallCode::Uptime => {
+            let buffer_ptr: *mut u8 = ptr::from_exposed_addr_mut(
+                usize::try_from(arg0).expect("usizes and u64s should be interchangeable"),
+            );
+            // Both are sampled at the same instant, so that they are consistent with each other
+            let (elapsed, idle) = execution::uptime_ticks();
+            let executions = EXECUTIONS.read();
+            let current = executions
+                .get(execution::current())
+                .expect("Syscalls should not occur outside the context of a valid `Execution`");
+            let written = current.with_user_slice_writeable(
+                buffer_ptr,
+                2 * mem::size_of::<u64>(),
+                |buffer| {
+                    let (elapsed_bytes, idle_bytes) = buffer.split_at_mut(mem::size_of::<u64>());
+                    elapsed_bytes.copy_from_slice(&elapsed.to_ne_bytes());
+                    idle_bytes.copy_from_slice(&idle.to_ne_bytes());
+                },
+            );
+            if written.is_some() {
+                success!()
+            } else {
+                fail!()
+            }
+        }
+        CallCode::ListProcesses => {
+            let buffer_ptr: *mut u8 = ptr::from_exposed_addr_mut(
+                usize::try_from(arg0).expect("usizes and u64s should be interchangeable"),
+            );
+            let capacity =
+                usize::try_from(arg1).expect("usizes and u64s should be interchangeable");
+            let executions = EXECUTIONS.read();
+            let current = executions
+                .get(execution::current())
+                .expect("Syscalls should not occur outside the context of a valid `Execution`");
+            // The PIDs are copied while the read lock is held, so the list is a consistent
+            // snapshot. As many as fit are copied, and the full count is returned so that the
+            // caller can tell whether its buffer was large enough
+            let copied = capacity
+                .checked_mul(mem::size_of::<u16>())
+                .and_then(|len| {
+                    current.with_user_slice_writeable(buffer_ptr, len, |buffer| {
+                        for (slot, (pid, _)) in buffer.chunks_exact_mut(2).zip(executions.iter()) {
+                            slot.copy_from_slice(&pid.to_ne_bytes());
+                        }
+                    })
+                })
+                .is_some();
+            if copied {
+                success!(executions.count().try_into().unwrap())
+            } else {
+                fail!()
+            }
+        }
+        CallCode::BlockTimeout => {
+            // The deadline is absolute, so that retrying after a spurious wakeup does not extend it
+            let deadline = timer::duration_to_ticks(Duration::from_nanos(arg0));
+            let pid = execution::current();
+            let status = EXECUTIONS
+                .read()
+                .get(pid)
+                .expect("The current execution should be valid")
+                .block_timeout(deadline, timer::counter());
+            match status {
+                TimeoutStatus::Unblocked => success!(0),
+                TimeoutStatus::TimedOut => success!(1),
+                TimeoutStatus::Pending => {
+                    // Queued only once the execution map is unlocked, since waking sleepers locks
+                    // the queue before the map
+                    execution::timeout_at(pid, deadline);
+                    if deadline < timer::deadline() {
+                        timer::set_deadline(deadline);
+                    }
+                    #[expect(clippy::as_conversions)]
+                    fail!(BlockTimeoutFailure::Pending as u64)
+                }
+            }
+        }
+        CallCode::SetAffinity => {
+            let mask = u8::try_from(arg0).unwrap_or(0);
+            let result = EXECUTIONS
+                .read()
+                .get(execution::current())
+                .expect("The current execution should be valid")
+                .set_affinity(mask);
+            match result {
+                Ok(()) => success!(),
+                #[expect(clippy::as_conversions)]
+                Err(AffinityError::NoCores) => fail!(AffinityFailure::NoCores as u64),
+            }
+        }
+        CallCode::InvalidateTranslation => {
+            // Dropping a translation never grants access to anything, so any address is allowed
+            execution::invalidate_translation(arg0);
+            success!()
+        }
+        CallCode::Mmap => {
+            let (va, len, prot) = (arg0, arg1, arg2);
+            if len == 0
+                || va % PAGE_SIZE != 0
+                || len % PAGE_SIZE != 0
+                || prot & PROT_READ == 0
+                || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+            {
+                #[expect(clippy::as_conversions)]
+                fail!(MmapFailure::InvalidArgument as u64)
+            } else {
+                let result = EXECUTIONS
+                    .read()
+                    .get(execution::current())
+                    .expect("The current execution should be valid")
+                    .map_anonymous(
+                        va,
+                        usize::try_from(len / PAGE_SIZE)
+                            .expect("usizes and u64s should be interchangeable"),
+                        prot & PROT_WRITE != 0,
+                        prot & PROT_EXEC != 0,
+                    );
+                match result {
+                    Ok(()) => success!(va),
+                    #[expect(clippy::as_conversions)]
+                    Err(MapError::NoMem) => fail!(MmapFailure::NoMem as u64),
+                    #[expect(clippy::as_conversions)]
+                    Err(MapError::InvalidAddress) => fail!(MmapFailure::InvalidArgument as u64),
+                }
             }
         }
     }
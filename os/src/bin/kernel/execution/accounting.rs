@@ -0,0 +1,83 @@
+//! Accounting of how long each core spends idling versus running executions
+//!
+//! Time is only sampled when a core switches between idling and running, never while it spins in
+//! `idle_loop`, so that the accounting adds no work to the idle path itself
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{
+    machine::{self, CORE_COUNT},
+    timer,
+};
+
+/// Time accounting for a single core, in system counter ticks
+struct CoreTicks {
+    /// Ticks spent in `idle_loop`, up to the last switch. Any other time is spent running
+    /// executions, or the kernel on their behalf
+    idle: AtomicU64,
+    /// Counter value at the last switch
+    since: AtomicU64,
+    /// Whether the core is currently idling
+    is_idle: AtomicBool,
+}
+
+impl CoreTicks {
+    /// Creates the accounting for a core that has not switched yet
+    const fn new() -> Self {
+        Self {
+            idle: AtomicU64::new(0),
+            since: AtomicU64::new(0),
+            is_idle: AtomicBool::new(false),
+        }
+    }
+
+    /// Charges the ticks since the last switch to the idle time if the core was idling, and
+    /// records that the core is now in the other state, or stays in the same state if `idle` is
+    /// unchanged
+    fn switch(&self, idle: bool) {
+        let now = timer::counter();
+        let elapsed = now.saturating_sub(self.since.swap(now, Ordering::Relaxed));
+        if self.is_idle.swap(idle, Ordering::Relaxed) {
+            self.idle.fetch_add(elapsed, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the ticks this core has spent idling, including any idle period in progress
+    fn idle_ticks(&self, now: u64) -> u64 {
+        let idle = self.idle.load(Ordering::Relaxed);
+        if self.is_idle.load(Ordering::Relaxed) {
+            idle.saturating_add(now.saturating_sub(self.since.load(Ordering::Relaxed)))
+        } else {
+            idle
+        }
+    }
+}
+
+/// The accounting for each core, indexed by core ID
+static CORE_TICKS: [CoreTicks; CORE_COUNT] = [const { CoreTicks::new() }; CORE_COUNT];
+
+/// Returns the accounting for the current core
+fn this_core() -> &'static CoreTicks {
+    &CORE_TICKS[usize::from(machine::core_id())]
+}
+
+/// Records that the current core has started idling
+pub fn start_idling() {
+    this_core().switch(true);
+}
+
+/// Records that the current core has started running an execution
+pub fn start_running() {
+    this_core().switch(false);
+}
+
+/// Returns the total ticks elapsed since the system counter started, and the ticks spent idling
+/// up to that point, summed across all cores, in that order
+pub fn uptime_ticks() -> (u64, u64) {
+    let now = timer::counter();
+    let idle = CORE_TICKS
+        .iter()
+        .map(|core| core.idle_ticks(now))
+        .fold(0, u64::saturating_add);
+    (now, idle)
+}
@@ -1,72 +1,142 @@
 use super::{Execution, UserContext};
 use alloc::vec::Vec;
+use common::collections::PidBitSet;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-pub struct ExecutionMap(Vec<Option<Execution>>);
+/// The maximum number of executions that may be alive at once, so that runaway forking fails
+/// cleanly instead of exhausting kernel memory
+pub const MAX_EXECUTIONS: usize = 256;
+
+/// The executions alive, indexed by PID. PIDs are `u16`s, but since the lowest free PID is always
+/// reused, no PID ever reaches `MAX_EXECUTIONS`
+///
+/// An execution that exits with a status for its parent to collect keeps its PID reserved until
+/// the status is collected, so that the PID cannot be reused by an unrelated execution in the
+/// meantime. Reserved PIDs count towards `MAX_EXECUTIONS`
+///
+/// Lookups and removals index directly into the backing storage. Creation takes the lowest free
+/// PID from a bitset, so it does not scan the executions
+pub struct ExecutionMap {
+    /// The executions, indexed by PID
+    executions: Vec<Option<Execution>>,
+    /// The PIDs of the executions alive, and of exited executions whose PIDs are still reserved
+    pids: PidBitSet,
+}
 
 #[derive(Debug)]
 pub enum ForkError {
     NoMem,
     NoPid,
     SrcNotValid,
+    /// Creating another execution would exceed `MAX_EXECUTIONS`
+    ProcessLimit,
 }
 
 impl ExecutionMap {
     /// Creates a new, unpopulated `ExecutionMap`
     pub const fn new() -> Self {
-        Self(Vec::new())
+        Self {
+            executions: Vec::new(),
+            pids: PidBitSet::new(),
+        }
+    }
+
+    /// Allocates space for at least `additional` more executions beyond those already allocated,
+    /// so that creating them does not reallocate the backing storage while the map is locked
+    pub fn reserve(&mut self, additional: usize) {
+        self.executions.reserve(additional);
+    }
+
+    /// Inserts the execution that `make` creates for the lowest free PID, and returns that PID
+    ///
+    /// Returns `None` if every PID is in use
+    fn insert(&mut self, make: impl FnOnce(u16) -> Execution) -> Option<u16> {
+        let pid = self.pids.alloc()?;
+        let execution = Some(make(pid));
+        match self.executions.get_mut(usize::from(pid)) {
+            Some(slot) => *slot = execution,
+            // Every lower PID is in use, so the new PID is exactly one past the end
+            None => self.executions.push(execution),
+        }
+        Some(pid)
+    }
+
+    /// Returns the number of executions currently alive, plus the number of reserved PIDs
+    pub fn count(&self) -> usize {
+        self.pids.len()
     }
 
-    /// Returns an `Ok` with an unused PID with space already allocated, else returns `Err` with a pid corresponding to one past the end of the current allocation (i.e., can be reached with a single `push`)
-    fn find_available_pid(&self) -> Result<u16, u16> {
-        self.0
+    /// Iterates over the live executions in increasing order of PID, along with their PIDs
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Execution)> {
+        self.executions
             .iter()
-            .enumerate()
-            .find_map(|(id, arc)| arc.is_none().then_some(id))
-            .map(|pid| u16::try_from(pid).expect("PID should be at most 16 bits"))
-            .ok_or_else(|| self.0.len().try_into().unwrap())
+            .filter_map(Option::as_ref)
+            .map(|execution| (execution.pid, execution))
     }
 
     /// Creates an execution with the given information, and defaults for all other values, at the lowest available PID
-    pub fn create(&mut self, tcr_el1: u64, ttbr0: u64, user_context: *const UserContext) -> u16 {
-        match self.find_available_pid() {
-            Ok(pid) => {
-                self.0[usize::from(pid)] = Some(Execution::new(tcr_el1, ttbr0, user_context, pid));
-                pid
-            }
-            Err(pid) => {
-                self.0
-                    .push(Some(Execution::new(tcr_el1, ttbr0, user_context, pid)));
-                pid
-            }
+    ///
+    /// Returns `None` if this would exceed `MAX_EXECUTIONS`
+    pub fn create(
+        &mut self,
+        tcr_el1: u64,
+        ttbr0: u64,
+        user_context: *const UserContext,
+    ) -> Option<u16> {
+        if self.count() >= MAX_EXECUTIONS {
+            return None;
         }
+        self.insert(|pid| Execution::new(tcr_el1, ttbr0, user_context, pid))
     }
 
     /// Returns the execution corresponding to the given PID, if present
     pub fn get(&self, pid: u16) -> Option<&Execution> {
-        self.0.get(usize::from(pid)).and_then(Option::as_ref)
+        self.executions
+            .get(usize::from(pid))
+            .and_then(Option::as_ref)
     }
 
     /// Removes and returns the execution correspodning to the given PID, if present
     pub fn remove(&mut self, pid: u16) -> Option<Execution> {
-        self.0.get_mut(usize::from(pid)).and_then(Option::take)
+        let execution = self
+            .executions
+            .get_mut(usize::from(pid))
+            .and_then(Option::take)?;
+        self.pids.free(pid);
+        Some(execution)
+    }
+
+    /// Removes and returns the execution corresponding to the given PID, if present, but keeps
+    /// the PID reserved until it is passed to `release`
+    pub fn retire(&mut self, pid: u16) -> Option<Execution> {
+        self.executions
+            .get_mut(usize::from(pid))
+            .and_then(Option::take)
+    }
+
+    /// Frees a PID reserved by `retire`, so that it can be reused
+    pub fn release(&mut self, pid: u16) {
+        assert!(
+            self.get(pid).is_none(),
+            "Only the PIDs of retired executions should be released"
+        );
+        self.pids.free(pid);
     }
 
     /// Duplicates the execution at `src_pid` into the next available pid
     pub fn fork(&mut self, src_pid: u16) -> Result<u16, ForkError> {
         let src_exec = self.get(src_pid).ok_or(ForkError::SrcNotValid)?;
-        match self.find_available_pid() {
-            Ok(pid) => {
-                let mut new_execution = src_exec.clone();
-                new_execution.pid = pid;
-                self.0[usize::from(pid)] = Some(new_execution);
-                Ok(pid)
-            }
-            Err(pid) => {
-                let mut new_execution = src_exec.clone();
-                new_execution.pid = pid;
-                self.0.push(Some(new_execution));
-                Ok(pid)
-            }
+        if self.count() >= MAX_EXECUTIONS {
+            return Err(ForkError::ProcessLimit);
         }
+        let mut new_execution = src_exec.clone();
+        new_execution.parent = Some(src_pid);
+        new_execution.priority_ceiling = new_execution.priority.load(Ordering::Relaxed);
+        new_execution.page_quota = AtomicU32::new(src_exec.split_page_quota());
+        self.insert(|pid| {
+            new_execution.pid = pid;
+            new_execution
+        })
+        .ok_or(ForkError::NoPid)
     }
 }
@@ -3,19 +3,26 @@
 //! These are the kernel's description of running user programs and their associated (physical memory) resources
 
 use crate::{
-    machine::to_physical_addr,
-    memory::{ReadablePage, WriteablePage},
-    println,
+    debug,
+    machine::{self, to_physical_addr},
+    memory::{self, ReadablePage, WriteablePage, PAGE_ALLOCATOR},
+};
+use alloc::{
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    sync::Arc,
+    vec::Vec,
 };
-use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
 use bitfield_struct::bitfield;
-use common::sync::{MutexGuard, ReadGuard, RwLock, SpinLock};
+use common::barrier;
+use common::os::memory_layout;
+use common::sync::{MutexGuard, ReadGuard, RwLock, SpinLock, WriteGuard};
 use core::{
     arch::asm,
+    cmp::Reverse,
     hint,
-    mem::transmute,
+    mem::{self, transmute},
     ptr::{self, NonNull},
-    sync::atomic::{AtomicI8, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+    sync::atomic::{AtomicI8, AtomicPtr, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
 };
 
 #[bitfield(u64, debug = false)]
@@ -39,6 +46,21 @@ impl AtomicOptionPointer {
     fn store(&self, value: u64, ordering: Ordering) {
         self.0.store(value, ordering)
     }
+
+    /// Stores `new` if the current value is `current`, with the same semantics as
+    /// `AtomicU64::compare_exchange`. Returns the previous value either way
+    fn compare_exchange(
+        &self,
+        current: OptionPointer,
+        new: u64,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<OptionPointer, OptionPointer> {
+        self.0
+            .compare_exchange(current.into(), new, success, failure)
+            .map(Into::into)
+            .map_err(Into::into)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -60,9 +82,44 @@ pub struct Execution {
     tcr_el1: AtomicU64,
     token: AtomicI8,
     pub pid: u16,
-    pending_messages: SpinLock<Vec<u16>>,
+    /// The PID of the execution that forked this one, if any
+    pub parent: Option<u16>,
+    /// Signals waiting to be delivered, as the PID of the sender and the word it sent along
+    pending_messages: SpinLock<Vec<(u16, u64)>>,
+    /// The run queue this execution is scheduled on; lower levels run first
+    priority: AtomicU8,
+    /// The most urgent priority this execution may request for itself
+    priority_ceiling: u8,
+    /// The cores this execution may run on, as a bit per core ID
+    affinity_mask: AtomicU8,
+    /// Number of further pages this execution may allocate
+    page_quota: AtomicU32,
+    /// The user context that `set_context` is installing, if any. Claiming this first keeps the
+    /// stores of two cores setting the context at once from interleaving
+    context_update: AtomicOptionPointer,
+    /// System counter value at which the pending `block_timeout` of this execution expires, or
+    /// `NO_TIMEOUT` if none is pending
+    timeout: AtomicU64,
+    /// How the last `block_timeout` of this execution ended, if that has not been reported yet
+    wake_reason: AtomicU8,
 }
 
+/// Affinity mask that allows an execution to run on every core
+const ALL_CORES: u8 = (1 << machine::CORE_COUNT) - 1;
+
+/// Exit status of an execution that was killed because the kernel could not use its exception
+/// stack, matching the conventional status of a shell whose child was killed by `SIGSEGV`
+pub const FAULT_STATUS: u8 = 139;
+
+/// Value of `Execution::timeout` when no timeout is pending
+const NO_TIMEOUT: u64 = u64::MAX;
+/// Value of `Execution::wake_reason` when there is nothing to report
+const WAKE_NONE: u8 = 0;
+/// Value of `Execution::wake_reason` when an unblock ended the wait
+const WAKE_UNBLOCKED: u8 = 1;
+/// Value of `Execution::wake_reason` when the deadline ended the wait
+const WAKE_TIMED_OUT: u8 = 2;
+
 impl Clone for Execution {
     fn clone(&self) -> Self {
         Self {
@@ -73,7 +130,17 @@ impl Clone for Execution {
             tcr_el1: AtomicU64::new(self.tcr_el1.load(Ordering::Relaxed)),
             token: AtomicI8::new(self.token.load(Ordering::Relaxed)),
             pid: self.pid,
+            parent: self.parent,
             pending_messages: SpinLock::new(self.pending_messages.lock().clone()),
+            priority: AtomicU8::new(self.priority.load(Ordering::Relaxed)),
+            priority_ceiling: self.priority_ceiling,
+            affinity_mask: AtomicU8::new(self.affinity_mask.load(Ordering::Relaxed)),
+            page_quota: AtomicU32::new(self.page_quota.load(Ordering::Relaxed)),
+            context_update: AtomicOptionPointer::new(),
+            // A pending timeout belongs to the original, which is the only one that the sleeper
+            // queue wakes
+            timeout: AtomicU64::new(NO_TIMEOUT),
+            wake_reason: AtomicU8::new(WAKE_NONE),
         }
     }
 }
@@ -85,19 +152,29 @@ pub struct UserContext {
 }
 
 impl UserContext {
-    pub fn pop(&self) -> u64 {
-        let popped_sp = unsafe {
-            self.exception_stack
-                .fetch_ptr_sub(1, Ordering::Relaxed)
-                .sub(1)
-        };
-        unsafe { UserPointer(popped_sp).read() }
+    /// Decrements the `exception_stack` pointer, then reads the `u64` it points to with user
+    /// privileges
+    ///
+    /// Returns `None` if `execution` may not read that `u64`, as `exception_stack` is under the
+    /// control of usermode
+    pub fn pop(&self, execution: &Execution) -> Option<u64> {
+        let popped_sp = self
+            .exception_stack
+            .fetch_ptr_sub(1, Ordering::Relaxed)
+            .wrapping_sub(1);
+        // SAFETY: The pointer was just validated as readable by the execution
+        UserPointer::validated(execution, popped_sp).map(|pointer| unsafe { pointer.read() })
     }
 
     /// Writes a `u64` to the memory pointed to by `exception_stack`, with user privileges, then increments the `exception_stack` pointer
-    pub fn push(&self, val: u64) {
+    ///
+    /// Returns `None`, writing nothing, if `execution` may not write that `u64`
+    pub fn push(&self, execution: &Execution, val: u64) -> Option<()> {
         let pushed_sp = self.exception_stack.fetch_ptr_add(1, Ordering::SeqCst);
-        unsafe { UserPointer(pushed_sp).write(val) }
+        let mut pointer = UserPointer::validated_writeable(execution, pushed_sp)?;
+        // SAFETY: The pointer was just validated as writeable by the execution
+        unsafe { pointer.write(val) };
+        Some(())
     }
 }
 
@@ -106,6 +183,25 @@ impl UserContext {
 pub struct UserPointer(*mut u64);
 
 impl UserPointer {
+    /// Wraps `ptr` if it is aligned and lies in a page that `execution` may read
+    pub fn validated(execution: &Execution, ptr: *mut u64) -> Option<Self> {
+        (ptr.is_aligned()
+            && execution
+                .with_autotranslate(|| execution.validate_user_pointer(ptr.cast_const()).is_some()))
+        .then_some(Self(ptr))
+    }
+
+    /// Wraps `ptr` if it is aligned and lies in a page that `execution` may write
+    pub fn validated_writeable(execution: &Execution, ptr: *mut u64) -> Option<Self> {
+        (ptr.is_aligned()
+            && execution.with_autotranslate(|| {
+                execution
+                    .validate_user_pointer_writeable(ptr.cast_const())
+                    .is_some()
+            }))
+        .then_some(Self(ptr))
+    }
+
     /// Reads a value from the `UserPointer`
     pub unsafe fn read(&self) -> u64 {
         let val;
@@ -133,16 +229,79 @@ impl UserPointer {
     }
 }
 
+/// Reasons an execution may not move to a requested priority level
+pub enum PriorityError {
+    /// The level is not below `PRIORITY_LEVELS`
+    InvalidLevel,
+    /// The level is more urgent than the execution's ceiling
+    AboveCeiling,
+}
+
+/// Reasons anonymous memory may not be mapped into an execution
+pub enum MapError {
+    /// Not every page could be allocated, within the quota or otherwise
+    NoMem,
+    /// Part of the virtual range is already mapped, or outside of the execution's address space
+    InvalidAddress,
+}
+
+/// Reasons an execution may not be restricted to a requested set of cores
+pub enum AffinityError {
+    /// The mask does not include any core, so the execution could never run again
+    NoCores,
+}
+
 pub enum ContextError {
     MisalignedTtbr0,
     InaccessibleTtbr0,
     InvalidTcrBits,
     MisalignedUserContext,
     InaccessibleUserContext,
+    /// Another core of the execution is setting the context at the same time
+    ConcurrentUpdate,
 }
 
+/// Number of pages the first execution may allocate. Each fork hands half of the parent's
+/// remaining quota to the child, so the whole tree of executions stays within this bound
+const INITIAL_PAGE_QUOTA: u32 = 1 << 14;
+
+/// Bit of a translation table descriptor that marks it as valid
+const DESCRIPTOR_VALID: u64 = 1 << 0;
+/// Bit of a translation table descriptor that marks it as pointing to a page or a next-level
+/// table, rather than a block
+const DESCRIPTOR_TABLE_OR_PAGE: u64 = 1 << 1;
+/// Bit of a page descriptor (AP[2]) that forbids writes to the page
+const DESCRIPTOR_READ_ONLY: u64 = 1 << 7;
+/// Bits of a descriptor holding the output address, with a 64K granule
+const DESCRIPTOR_ADDRESS: u64 = ((1 << 48) - 1) & !((1 << 16) - 1);
+/// Bits of a page descriptor (AttrIndx) selecting device memory, at index 1 of `MAIR_EL1`
+const DESCRIPTOR_DEVICE: u64 = 1 << 2;
+/// Bit of a page descriptor (AP[1]) that allows EL0 to access the page
+const DESCRIPTOR_EL0: u64 = 1 << 6;
+/// Bits of a page descriptor that mark the page as inner shareable
+const DESCRIPTOR_INNER_SHAREABLE: u64 = 0b11 << 8;
+/// Bit of a page descriptor that marks the page as accessed, so that it does not fault
+const DESCRIPTOR_ACCESS: u64 = 1 << 10;
+/// Bit of a page descriptor that tags its TLB entries with the ASID
+const DESCRIPTOR_NOT_GLOBAL: u64 = 1 << 11;
+/// Bit of a page descriptor that forbids EL1 from executing the page
+const DESCRIPTOR_PRIVILEGED_XN: u64 = 1 << 53;
+/// Bit of a page descriptor that forbids EL0 from executing the page
+const DESCRIPTOR_USER_XN: u64 = 1 << 54;
+/// Attributes of every page that the kernel maps into usermode, matching the defaults of the user
+/// `AddressSpace`
+const DESCRIPTOR_USER_PAGE: u64 = DESCRIPTOR_VALID
+    | DESCRIPTOR_TABLE_OR_PAGE
+    | DESCRIPTOR_EL0
+    | DESCRIPTOR_INNER_SHAREABLE
+    | DESCRIPTOR_ACCESS
+    | DESCRIPTOR_NOT_GLOBAL
+    | DESCRIPTOR_PRIVILEGED_XN;
+
+mod accounting;
 mod execution_map;
-pub use execution_map::ExecutionMap;
+pub use accounting::uptime_ticks;
+pub use execution_map::{ExecutionMap, ForkError, MAX_EXECUTIONS};
 pub static EXECUTIONS: RwLock<ExecutionMap> = RwLock::new(ExecutionMap::new());
 
 impl Execution {
@@ -156,15 +315,56 @@ impl Execution {
             ttbr0: AtomicU64::new(ttbr0),
             tcr_el1: AtomicU64::new(tcr_el1),
             pid,
+            parent: None,
             pending_messages: SpinLock::new(Vec::new()),
+            priority: AtomicU8::new(0),
+            priority_ceiling: 0,
+            affinity_mask: AtomicU8::new(ALL_CORES),
+            page_quota: AtomicU32::new(INITIAL_PAGE_QUOTA),
+            context_update: AtomicOptionPointer::new(),
+            timeout: AtomicU64::new(NO_TIMEOUT),
+            wake_reason: AtomicU8::new(WAKE_NONE),
         }
     }
 
-    pub fn add_signal(&self, sender: u16) {
-        self.pending_messages.lock().push(sender);
+    /// Moves this execution to the given priority level, taking effect the next time it is
+    /// scheduled. An execution may freely lower its priority, but may not raise it above its
+    /// ceiling
+    pub fn set_priority(&self, level: u8) -> Result<(), PriorityError> {
+        if usize::from(level) >= PRIORITY_LEVELS {
+            Err(PriorityError::InvalidLevel)
+        } else if level < self.priority_ceiling {
+            Err(PriorityError::AboveCeiling)
+        } else {
+            self.priority.store(level, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    /// Restricts this execution to the cores set in `mask`, taking effect the next time it is
+    /// scheduled. Bits beyond the last core are ignored
+    pub fn set_affinity(&self, mask: u8) -> Result<(), AffinityError> {
+        let mask = mask & ALL_CORES;
+        if mask == 0 {
+            Err(AffinityError::NoCores)
+        } else {
+            self.affinity_mask.store(mask, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    /// Returns whether this execution may run on the given core
+    fn may_run_on(&self, core: u8) -> bool {
+        self.affinity_mask.load(Ordering::Relaxed) & (1 << core) != 0
+    }
+
+    /// Queues a signal from `sender` carrying the given word, to be delivered the next time this
+    /// execution is scheduled
+    pub fn send_message(&self, sender: u16, word: u64) {
+        self.pending_messages.lock().push((sender, word));
     }
 
-    pub fn pop_signal(&self) -> Option<u16> {
+    pub fn pop_signal(&self) -> Option<(u16, u64)> {
         self.pending_messages.lock().pop()
     }
 
@@ -187,13 +387,22 @@ impl Execution {
         if !user_context.is_aligned() {
             return Err(ContextError::MisalignedUserContext);
         }
-        if (user_context.addr() >> 48) & 0xFF != 0 {
+        if !memory_layout::is_user_addr(user_context.addr()) {
             return Err(ContextError::InaccessibleUserContext);
         }
+        self.context_update
+            .compare_exchange(
+                OptionPointer::from(u64::MAX),
+                u64::try_from(user_context.addr()).expect("`usize`s should fit into `u64`s"),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .map_err(|_current| ContextError::ConcurrentUpdate)?;
         // self.tcr_el1.store(tcr_el1, Ordering::Relaxed);
         self.ttbr0.store(ttbr0, Ordering::Relaxed);
         self.user_context
             .store(user_context.cast_mut(), Ordering::Relaxed);
+        self.context_update.store(u64::MAX, Ordering::Release);
         Ok(())
     }
 
@@ -217,6 +426,9 @@ impl Execution {
     }
 
     pub fn validate_user_pointer<T>(&self, ptr: *const T) -> Option<&T> {
+        if !memory_layout::is_user_addr(ptr.addr()) {
+            return None;
+        }
         let pa = to_physical_addr(ptr.addr());
         pa.ok().and_then(|pa| {
             self.contains_pa(pa.pa())
@@ -224,7 +436,48 @@ impl Execution {
         })
     }
 
+    /// Validates that every page touched by the `len` bytes at `ptr` is accessible to this
+    /// execution, and if so returns those bytes as a slice
+    pub fn validate_user_slice(&self, ptr: *const u8, len: usize) -> Option<&[u8]> {
+        let Some(last) = len.checked_sub(1) else {
+            return Some(&[]);
+        };
+        let end = ptr.addr().checked_add(last)?;
+        let page_mask = (1 << self.page_bits()) - 1;
+        let all_accessible = (ptr.addr() & !page_mask..=end)
+            .step_by(1 << self.page_bits())
+            .all(|page| self.validate_user_pointer(ptr.with_addr(page)).is_some());
+        // SAFETY: Every page of the slice was just validated as belonging to this execution
+        all_accessible.then(|| unsafe { core::slice::from_raw_parts(ptr, len) })
+    }
+
+    /// Validates that every page touched by the `len` bytes at `ptr` is writeable by this
+    /// execution, and if so runs `f` on those bytes as a mutable slice
+    pub fn with_user_slice_writeable<T>(
+        &self,
+        ptr: *mut u8,
+        len: usize,
+        f: impl FnOnce(&mut [u8]) -> T,
+    ) -> Option<T> {
+        let Some(last) = len.checked_sub(1) else {
+            return Some(f(&mut []));
+        };
+        let end = ptr.addr().checked_add(last)?;
+        let page_mask = (1 << self.page_bits()) - 1;
+        let all_writeable = (ptr.addr() & !page_mask..=end)
+            .step_by(1 << self.page_bits())
+            .all(|page| {
+                self.validate_user_pointer_writeable(ptr.with_addr(page).cast_const())
+                    .is_some()
+            });
+        // SAFETY: Every page of the slice was just validated as writeable by this execution
+        all_writeable.then(|| f(unsafe { core::slice::from_raw_parts_mut(ptr, len) }))
+    }
+
     pub fn validate_user_pointer_writeable<T>(&self, ptr: *const T) -> Option<&T> {
+        if !memory_layout::is_user_addr(ptr.addr()) {
+            return None;
+        }
         let pa = to_physical_addr(ptr.addr());
         pa.ok().and_then(|pa| {
             self.contains_pa_writeable(pa.pa())
@@ -259,7 +512,12 @@ impl Execution {
         result
     }
 
-    pub unsafe fn prepare_synchronous_jump(&self, x0: usize, x1: usize) {
+    /// Sets up a jump into this execution's exception vector upon returning to usermode, saving
+    /// the faulting instruction and the given arguments onto its exception stack
+    ///
+    /// Returns `None` if the exception stack is not writeable by this execution, in which case
+    /// the execution cannot be resumed
+    pub unsafe fn prepare_synchronous_jump(&self, x0: usize, x1: usize) -> Option<()> {
         let context = self.user_context();
         let ev_addr = context.exception_vector.as_ptr().cast();
 
@@ -276,17 +534,20 @@ impl Execution {
             }
         }
 
-        context.push(faulting_instruction);
-        context.push(x1.try_into().unwrap());
-        context.push(x0.try_into().unwrap());
+        context.push(self, faulting_instruction)?;
+        context.push(self, x1.try_into().unwrap())?;
+        context.push(self, x0.try_into().unwrap())
     }
 
-    /// Jumps into usermode by calling the exception vector with the given code and arguments
+    /// Switches to the given execution and enters its exception vector with `code` in `x0`,
+    /// `argument` in `x1` and `payload` in `x3`. `x2` is left alone, since the vector may use it
+    /// to restore its stack pointer
     pub fn jump_into_async(
         guard: ReadGuard<ExecutionMap>,
         pid: u16,
         code: ExceptionCode,
         argument: u64,
+        payload: u64,
     ) -> ! {
         let execution = guard.get(pid).unwrap();
         let ttbr0 = execution.ttbr0.load(Ordering::Relaxed);
@@ -309,7 +570,7 @@ impl Execution {
 
         let return_point = unsafe { UserPointer(ev_addr).read() };
 
-        println!("RUNNING EXECUTION: {}", pid);
+        debug!("RUNNING EXECUTION: {}", pid);
         // SAFETY: This correctly sets up a return into user mode, after which entry into the kernel is only possible via exception/IRQ
         unsafe {
             asm! {
@@ -318,6 +579,7 @@ impl Execution {
                 "eret",
                 in("x0") code as u64,
                 in("x1") argument,
+                in("x3") payload,
                 ELR_EL1 = in(reg) return_point,
                 options(noreturn, nostack),
             }
@@ -326,6 +588,31 @@ impl Execution {
         unreachable!()
     }
 
+    /// Uses up one page of this execution's quota, ahead of allocating a page for it.
+    ///
+    /// Returns `false`, leaving the quota untouched, if the quota is exhausted
+    pub fn take_page_quota(&self) -> bool {
+        self.page_quota
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |quota| {
+                quota.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Returns one page to this execution's quota, when a page taken for it was not allocated
+    pub fn return_page_quota(&self) {
+        self.page_quota.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Hands half of this execution's remaining page quota to a newly forked child, and returns
+    /// the child's share
+    fn split_page_quota(&self) -> u32 {
+        // Forks hold the write lock on all executions, so no allocation can race with this
+        let share = self.page_quota.load(Ordering::Relaxed) / 2;
+        self.page_quota.fetch_sub(share, Ordering::Relaxed);
+        share
+    }
+
     /// Adds a page to the write set of an `Execution`
     pub fn add_writable_page(&self, page: WriteablePage) {
         let mut pages = self.writeable_pages.lock();
@@ -335,10 +622,211 @@ impl Execution {
         pages.insert(insertion, page);
     }
 
+    /// Adds a page to the read set of an `Execution`
+    pub fn add_readable_page(&self, page: ReadablePage) {
+        let mut pages = self.readable_pages.lock();
+        let insertion = pages
+            .binary_search(&page)
+            .expect_err("Should not add a duplicate page to an execution's readable set");
+        pages.insert(insertion, page);
+    }
+
+    /// Maps the `size` bytes of peripheral registers at `pa` to `va` in this execution, as
+    /// writeable, non-executable device memory. The registers are not reference counted, since
+    /// they are never handed out by the page allocator
+    ///
+    /// Returns `false`, changing nothing, if any page of the virtual range is already mapped or
+    /// cannot be mapped
+    pub fn map_device(&self, va: u64, pa: u64, size: u64) -> bool {
+        let pages: Vec<u64> = (pa..pa.saturating_add(size))
+            .step_by(1 << self.page_bits())
+            .collect();
+        self.install_pages(
+            va,
+            &pages,
+            DESCRIPTOR_USER_PAGE | DESCRIPTOR_DEVICE | DESCRIPTOR_USER_XN,
+        )
+    }
+
+    /// Maps `count` freshly zeroed pages to consecutive pages starting at `va` in this execution,
+    /// always readable and with the given permissions, and adds them to the write set
+    ///
+    /// Every page is allocated before any is mapped. If any allocation or the mapping fails, the
+    /// pages allocated so far are freed and their quota returned, so nothing changes
+    pub fn map_anonymous(
+        &self,
+        va: u64,
+        count: usize,
+        writeable: bool,
+        executable: bool,
+    ) -> Result<(), MapError> {
+        let allocator = PAGE_ALLOCATOR
+            .get()
+            .expect("Page allocator should be initialized");
+        let mut pages = Vec::new();
+        let allocated = loop {
+            if pages.len() == count {
+                break true;
+            }
+            if !self.take_page_quota() {
+                break false;
+            }
+            if let Some(page) = allocator.alloc_zeroed() {
+                pages.push(page);
+            } else {
+                self.return_page_quota();
+                break false;
+            }
+        };
+        let mut attributes = DESCRIPTOR_USER_PAGE;
+        if !writeable {
+            attributes |= DESCRIPTOR_READ_ONLY;
+        }
+        if !executable {
+            attributes |= DESCRIPTOR_USER_XN;
+        }
+        let addresses: Vec<u64> = pages.iter().map(WriteablePage::addr).collect();
+        let result = if !allocated {
+            Err(MapError::NoMem)
+        } else if self.install_pages(va, &addresses, attributes) {
+            Ok(())
+        } else {
+            Err(MapError::InvalidAddress)
+        };
+        for page in pages {
+            if result.is_ok() {
+                self.add_writable_page(page);
+            } else {
+                // Dropping the page returns it to the allocator
+                self.return_page_quota();
+            }
+        }
+        result
+    }
+
+    /// Maps each of the given physical pages, in order, to consecutive pages starting at `va` in
+    /// this execution, with the given descriptor attributes
+    ///
+    /// Returns `false`, changing nothing, if any page of the virtual range is already mapped, or
+    /// has no final-level descriptor that this execution could have written itself
+    fn install_pages(&self, va: u64, pages: &[u64], attributes: u64) -> bool {
+        let descriptors: Option<Vec<u64>> = (0..)
+            .map(|page| va.checked_add(page << self.page_bits()))
+            .take(pages.len())
+            .map(|page_va| {
+                page_va
+                    .and_then(|page_va| self.descriptor_pa(page_va))
+                    .filter(|&descriptor_pa| {
+                        memory::read_physical(descriptor_pa) & DESCRIPTOR_VALID == 0
+                    })
+            })
+            .collect();
+        let Some(descriptors) = descriptors else {
+            return false;
+        };
+        for (&descriptor_pa, &pa) in descriptors.iter().zip(pages) {
+            memory::write_physical(descriptor_pa, attributes | pa);
+        }
+        // The entries were invalid, so no translations for them can be cached
+        barrier::dsb_ishst();
+        barrier::isb();
+        true
+    }
+
+    /// Returns the physical address of the final-level descriptor that translates `va` in this
+    /// execution's translation tables. Every table on the way must be in the write set, since the
+    /// kernel only updates descriptors that this execution could have written itself
+    fn descriptor_pa(&self, va: u64) -> Option<u64> {
+        let page_bits = u64::from(self.page_bits());
+        let level_bits = page_bits - 3;
+        let address_bits = 64 - (self.tcr_el1.load(Ordering::Relaxed) & 0x3F);
+        if va >> address_bits != 0 {
+            return None;
+        }
+        let levels = (address_bits - page_bits).div_ceil(level_bits);
+        let mut table = self.ttbr0.load(Ordering::Relaxed) & ((1 << 48) - 1) & !1;
+        for level in (0..levels).rev() {
+            if !self.contains_pa_writeable(table) {
+                return None;
+            }
+            let index = (va >> (page_bits + level * level_bits)) & ((1 << level_bits) - 1);
+            let descriptor_pa = table + index * 8;
+            if level == 0 {
+                return Some(descriptor_pa);
+            }
+            let descriptor = memory::read_physical(descriptor_pa);
+            if descriptor & (DESCRIPTOR_VALID | DESCRIPTOR_TABLE_OR_PAGE)
+                != DESCRIPTOR_VALID | DESCRIPTOR_TABLE_OR_PAGE
+            {
+                return None;
+            }
+            table = descriptor & DESCRIPTOR_ADDRESS;
+        }
+        None
+    }
+
+    /// Resolves a write to a copy-on-write page: gives this execution its own writeable copy of the
+    /// shared page mapped read-only at `va`, and remaps `va` to the copy so that the write can be
+    /// retried
+    ///
+    /// Returns `false`, changing nothing, if `va` does not map a shared page from the read set, in
+    /// which case the write fault is genuine. This also fails if the copy would exceed the page
+    /// quota, or no physical page is free to copy into
+    pub fn break_copy_on_write(&self, va: u64) -> bool {
+        let Some(descriptor_pa) = self.descriptor_pa(va) else {
+            return false;
+        };
+        let descriptor = memory::read_physical(descriptor_pa);
+        let read_only_page = DESCRIPTOR_VALID | DESCRIPTOR_TABLE_OR_PAGE | DESCRIPTOR_READ_ONLY;
+        if descriptor & read_only_page != read_only_page {
+            return false;
+        }
+        let pa = descriptor & DESCRIPTOR_ADDRESS;
+        let mut readable_pages = self.readable_pages.lock();
+        let Ok(index) = readable_pages
+            .binary_search_by(|x| (x.addr() >> self.page_bits()).cmp(&(pa >> self.page_bits())))
+        else {
+            return false;
+        };
+        if !readable_pages
+            .get(index)
+            .is_some_and(ReadablePage::is_shared)
+            || !self.take_page_quota()
+        {
+            return false;
+        }
+        let page = match readable_pages.remove(index).into_owned_writeable() {
+            Ok(page) => page,
+            Err(shared) => {
+                // No page is free to copy into, so the write faults as if there were no quota
+                readable_pages.insert(index, shared);
+                self.return_page_quota();
+                return false;
+            }
+        };
+        drop(readable_pages);
+        if page.addr() == pa {
+            // Every other reference was dropped in the meantime, so no copy was needed
+            self.return_page_quota();
+        }
+        let new_descriptor =
+            (descriptor & !DESCRIPTOR_ADDRESS & !DESCRIPTOR_READ_ONLY) | page.addr();
+        self.add_writable_page(page);
+        memory::write_physical(descriptor_pa, new_descriptor);
+        barrier::dsb_ishst();
+        invalidate_translation(va);
+        true
+    }
+
     pub fn unblock(&self) {
+        // The first wakeup of a `block_timeout` wins, so an unblock cancels any pending timeout.
+        // This pairs with `block_timeout` arming the timeout before checking the token
+        if self.timeout.swap(NO_TIMEOUT, Ordering::SeqCst) != NO_TIMEOUT {
+            self.wake_reason.store(WAKE_UNBLOCKED, Ordering::Release);
+        }
         let result = self
             .token
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |token| match token {
+            .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |token| match token {
                 0 => {
                     // 0 = was previously blocked, so schedule self
                     Some(1)
@@ -355,7 +843,7 @@ impl Execution {
             })
             .unwrap();
         if result == 0 {
-            add_to_running(self.pid);
+            add_to_running(self);
         }
     }
 
@@ -387,25 +875,189 @@ impl Execution {
         }
     }
 
-    pub fn exit(pid: u16) -> ! {
-        EXECUTIONS.write().remove(pid).unwrap();
+    /// Reports how the last wait of this execution with a timeout ended, or otherwise arms a
+    /// timeout expiring once the system counter reaches `deadline`. The caller should then queue
+    /// the timeout with `timeout_at`, block, and ask again once woken
+    ///
+    /// An unblock that is already pending ends the wait immediately, as with `block`
+    pub fn block_timeout(&self, deadline: u64, now: u64) -> TimeoutStatus {
+        match self.wake_reason.swap(WAKE_NONE, Ordering::Acquire) {
+            WAKE_UNBLOCKED => return TimeoutStatus::Unblocked,
+            WAKE_TIMED_OUT => return TimeoutStatus::TimedOut,
+            _ => {}
+        }
+        if now >= deadline {
+            return if self
+                .token
+                .compare_exchange(2, 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                TimeoutStatus::Unblocked
+            } else {
+                TimeoutStatus::TimedOut
+            };
+        }
+        // Armed before the token is checked, so that an unblock racing with this either leaves a
+        // token that is found here, or sees the timeout and cancels it
+        self.timeout.store(deadline, Ordering::SeqCst);
+        if self
+            .token
+            .compare_exchange(2, 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            return TimeoutStatus::Pending;
+        }
+        if self.timeout.swap(NO_TIMEOUT, Ordering::AcqRel) != NO_TIMEOUT {
+            return TimeoutStatus::Unblocked;
+        }
+        // The timeout was already taken by an unblock or its expiry, which reports why the wait
+        // ended once it has done so
+        loop {
+            match self.wake_reason.swap(WAKE_NONE, Ordering::Acquire) {
+                WAKE_UNBLOCKED => return TimeoutStatus::Unblocked,
+                WAKE_TIMED_OUT => return TimeoutStatus::TimedOut,
+                _ => hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Ends the pending wait with a timeout of this execution, if it expires at `deadline` and
+    /// has not already been ended by an unblock
+    fn expire_timeout(&self, deadline: u64) {
+        if self
+            .timeout
+            .compare_exchange(deadline, NO_TIMEOUT, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            self.wake_reason.store(WAKE_TIMED_OUT, Ordering::Release);
+            self.unblock();
+        }
+    }
+
+    /// Tears down the given execution, recording its exit status for its parent to collect with
+    /// `try_wait`, crediting its unspent page quota back to the parent, and waking the parent in
+    /// case it is waiting. The PID stays reserved until the status is collected, so that the
+    /// parent never mistakes a later execution for this one
+    pub fn exit(pid: u16, status: u8) -> ! {
+        let mut executions = EXECUTIONS.write();
+        let parent = executions
+            .get(pid)
+            .unwrap()
+            .parent
+            .filter(|&parent| executions.get(parent).is_some());
+        let execution = if parent.is_some() {
+            executions.retire(pid)
+        } else {
+            executions.remove(pid)
+        }
+        .unwrap();
+        {
+            let mut exited = EXITED_CHILDREN.lock();
+            // Recorded while the execution map is still locked, so that a waiting parent always
+            // sees the child as either running or exited
+            if let Some(parent) = parent {
+                exited.insert((parent, pid), status);
+            }
+            // Nobody is left to collect this execution's own children, so their PIDs are freed
+            exited.retain(|&(parent, child), _| {
+                if parent == pid {
+                    executions.release(child);
+                }
+                parent != pid
+            });
+        }
+        let executions = WriteGuard::downgrade(executions);
+        if let Some(parent) = execution.parent.and_then(|parent| executions.get(parent)) {
+            // The child's quota was split off from the parent's at fork, so it goes back there
+            parent.page_quota.fetch_add(
+                execution.page_quota.load(Ordering::Relaxed),
+                Ordering::Relaxed,
+            );
+            parent.unblock();
+        }
+        drop(executions);
+        drop(execution);
         idle_loop();
     }
 }
 
 impl Drop for Execution {
     fn drop(&mut self) {
-        println!("Execution {} died!", self.pid);
+        // Nothing else can reach a dropped execution, so its page sets are released without locking
+        let writeable_pages = mem::take(self.writeable_pages.get_mut());
+        let readable_pages = mem::take(self.readable_pages.get_mut());
+        debug!(
+            "Execution {} died, releasing {} pages",
+            self.pid,
+            writeable_pages.len() + readable_pages.len()
+        );
     }
 }
 
-/// Reads the value of `TPIDR_EL1`, as a pointer
+/// Exit statuses of executions that have not yet been collected by their parents, keyed by
+/// `(parent, child)` PIDs
+static EXITED_CHILDREN: SpinLock<BTreeMap<(u16, u16), u8>> = SpinLock::new(BTreeMap::new());
+
+/// The state of a child execution, as observed by `try_wait`
+pub enum WaitStatus {
+    /// The child exited with the given status, which has now been collected
+    Exited(u8),
+    /// The child is still alive
+    Running,
+    /// The PID does not refer to a living or uncollected child of the parent
+    NotChild,
+}
+
+/// The state of a wait with a timeout, as observed by `Execution::block_timeout`
+pub enum TimeoutStatus {
+    /// An unblock ended the wait
+    Unblocked,
+    /// The deadline passed before any unblock arrived
+    TimedOut,
+    /// The wait is armed, and neither has happened yet
+    Pending,
+}
+
+/// Collects the exit status of the given child of `parent`, if it has exited, and frees the
+/// child's PID. Each exit status can only be collected once
+/// Invalidates any cached translation of the given user virtual address on every core, and waits
+/// for the invalidation to complete. Updates to the descriptor must already be visible
+pub fn invalidate_translation(va: u64) {
+    // SAFETY: TLB invalidations are always safe
+    unsafe {
+        asm! {
+            "tlbi VAE1IS, {}",
+            in(reg) (va >> 12) & ((1 << 44) - 1),
+            options(nomem, nostack, preserves_flags)
+        };
+    }
+    barrier::dsb_ish();
+    barrier::isb();
+}
+
+pub fn try_wait(parent: u16, child: u16) -> WaitStatus {
+    let mut executions = EXECUTIONS.write();
+    if executions
+        .get(child)
+        .is_some_and(|execution| execution.parent == Some(parent))
+    {
+        WaitStatus::Running
+    } else {
+        let status = EXITED_CHILDREN.lock().remove(&(parent, child));
+        status.map_or(WaitStatus::NotChild, |status| {
+            executions.release(child);
+            WaitStatus::Exited(status)
+        })
+    }
+}
+
+/// Reads the value of `TPIDR_EL1`
 fn get_tpidr() -> u64 {
     let tpidr;
     // SAFETY: This touches only a system register with no other side effects
     unsafe {
         asm! {
-            "mrs {}, TPIDRRO_EL0",
+            "mrs {}, TPIDR_EL1",
             out(reg) tpidr,
             options(nomem, nostack, preserves_flags)
         };
@@ -413,12 +1065,14 @@ fn get_tpidr() -> u64 {
     tpidr
 }
 
-/// Writes a value to `TPIDR_EL1`
+/// Writes a value to `TPIDR_EL1`, and mirrors it into `TPIDRRO_EL0` so that usermode can read
+/// (but not modify) it, e.g. for `getpid`
 fn set_tpidr(tpidr: u64) {
-    // SAFETY: This touches only a system register with no other side effects
+    // SAFETY: This touches only system registers with no other side effects
     unsafe {
         asm! {
-            "msr TPIDR_EL1, {}",
+            "msr TPIDR_EL1, {0}",
+            "msr TPIDRRO_EL0, {0}",
             in(reg) tpidr,
             options(nomem, nostack, preserves_flags)
         };
@@ -434,16 +1088,85 @@ pub fn set_current(pid: u16) {
     set_tpidr(pid.into())
 }
 
-/// The queue for all executions that are ready to run
-static RUN_QUEUE: SpinLock<VecDeque<u16>> = SpinLock::new(VecDeque::new());
+/// Number of priority levels, each with its own run queue. Level 0 is the most urgent
+pub const PRIORITY_LEVELS: usize = 4;
+
+/// An empty run queue, for initializing `RUN_QUEUES`
+const EMPTY_RUN_QUEUE: VecDeque<u16> = VecDeque::new();
+
+/// The queues for all executions that are ready to run, indexed by priority level. An execution
+/// is only run once every more urgent queue is empty
+static RUN_QUEUES: SpinLock<[VecDeque<u16>; PRIORITY_LEVELS]> =
+    SpinLock::new([EMPTY_RUN_QUEUE; PRIORITY_LEVELS]);
+
+/// Schedules an `Execution` to run, at the back of the queue for its priority
+pub fn add_to_running(execution: &Execution) {
+    let level = usize::from(execution.priority.load(Ordering::Relaxed));
+    RUN_QUEUES.lock()[level].push_back(execution.pid);
+}
+
+/// Gives up the rest of the current execution's timeslice, placing it at the back of the run
+/// queue for its priority
+pub fn yield_now() -> ! {
+    add_to_running(
+        EXECUTIONS
+            .read()
+            .get(current())
+            .expect("The current execution should be valid"),
+    );
+    idle_loop()
+}
+
+/// Executions waiting for a deadline to pass, as `(deadline, pid, is_timeout)` with the earliest
+/// first. Deadlines are system counter values. Timeouts only wake their execution if it is still
+/// waiting for that deadline, whereas sleeps always unblock it
+static SLEEPERS: SpinLock<BinaryHeap<Reverse<(u64, u16, bool)>>> = SpinLock::new(BinaryHeap::new());
+
+/// Queues an execution to be unblocked once the system counter reaches `deadline`. The execution
+/// is responsible for blocking itself afterwards
+pub fn sleep_until(pid: u16, deadline: u64) {
+    SLEEPERS.lock().push(Reverse((deadline, pid, false)));
+}
 
-/// Schedules an `Execution` to run
-pub fn add_to_running(pid: u16) {
-    RUN_QUEUE.lock().push_back(pid);
+/// Queues the timeout armed by `Execution::block_timeout` to expire once the system counter
+/// reaches `deadline`
+pub fn timeout_at(pid: u16, deadline: u64) {
+    SLEEPERS.lock().push(Reverse((deadline, pid, true)));
+}
+
+/// The error returned by `wake_sleepers` when the execution map is locked for writing
+#[derive(Debug)]
+pub struct ExecutionsLocked;
+
+/// Unblocks every sleeping execution whose deadline is at or before `now`
+///
+/// Returns the earliest deadline still pending, if any. This runs from the timer interrupt, which
+/// may have preempted a writer of the execution map on this core, so if the map is locked for
+/// writing then no sleepers are woken and `ExecutionsLocked` is returned, so that the caller can
+/// retry shortly
+pub fn wake_sleepers(now: u64) -> Result<Option<u64>, ExecutionsLocked> {
+    let executions = EXECUTIONS.try_read().ok_or(ExecutionsLocked)?;
+    let mut sleepers = SLEEPERS.lock();
+    while let Some(&Reverse((deadline, pid, is_timeout))) = sleepers.peek() {
+        if deadline > now {
+            return Ok(Some(deadline));
+        }
+        sleepers.pop();
+        // The execution may have exited while asleep
+        if let Some(execution) = executions.get(pid) {
+            if is_timeout {
+                execution.expire_timeout(deadline);
+            } else {
+                execution.unblock();
+            }
+        }
+    }
+    Ok(None)
 }
 
 /// Sets a new `Execution` to be the running `Execution` for the core.
 pub fn idle_loop() -> ! {
+    accounting::start_idling();
     loop {
         unsafe {
             asm! {
@@ -451,28 +1174,48 @@ pub fn idle_loop() -> ! {
                 options(nomem, nostack, preserves_flags)
             }
         }
-        let mut queue = RUN_QUEUE.lock();
-        if let Some(pid) = queue.pop_front() {
-            unsafe {
-                asm! {
-                    "msr DAIFSet, 0b1111",
-                    options(nomem, nostack, preserves_flags)
-                }
-            }
+        let core = machine::core_id();
+        let mut queues = RUN_QUEUES.lock();
+        // The execution map is only locked when there is something to run, so that idle cores do
+        // not keep writers out of it
+        if queues.iter().any(|queue| !queue.is_empty()) {
             let executions = EXECUTIONS.read();
-            let execution = executions.get(pid).unwrap();
-            if let Some(sender) = execution.pop_signal() {
-                Execution::jump_into_async(
-                    executions,
-                    pid,
-                    ExceptionCode::UserSignal,
-                    sender.into(),
-                );
-            } else {
-                Execution::jump_into_async(executions, pid, ExceptionCode::Resumption, 0);
+            // Executions that may not run on this core are left in place for the cores they may
+            // run on, while executions that exited since they were queued are dropped
+            let next = queues.iter_mut().find_map(|queue| {
+                queue.retain(|&pid| executions.get(pid).is_some());
+                let index = queue.iter().position(|&pid| {
+                    executions
+                        .get(pid)
+                        .is_some_and(|execution| execution.may_run_on(core))
+                })?;
+                queue.remove(index)
+            });
+            if let Some(pid) = next {
+                unsafe {
+                    asm! {
+                        "msr DAIFSet, 0b1111",
+                        options(nomem, nostack, preserves_flags)
+                    }
+                }
+                let execution = executions
+                    .get(pid)
+                    .expect("Queued executions that exited should have been dropped");
+                accounting::start_running();
+                if let Some((sender, word)) = execution.pop_signal() {
+                    Execution::jump_into_async(
+                        executions,
+                        pid,
+                        ExceptionCode::UserSignal,
+                        sender.into(),
+                        word,
+                    );
+                } else {
+                    Execution::jump_into_async(executions, pid, ExceptionCode::Resumption, 0, 0);
+                }
             }
         }
-        drop(queue);
+        drop(queues);
         unsafe {
             asm! {
                 "msr DAIFClr, 0b1111",
@@ -0,0 +1,96 @@
+//! Leveled logging over the UART, so that routine tracing can be compiled out while errors are
+//! still reported. Each line is prefixed with the core that logged it and the level
+
+/// Severity of a log message, from most to least severe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Something has gone wrong
+    Error,
+    /// Something looks wrong, but the kernel can carry on
+    Warn,
+    /// Notable events, such as steps of the boot sequence
+    Info,
+    /// Detailed tracing, such as every switch into usermode
+    Debug,
+}
+
+impl Level {
+    /// Returns the tag that prefixes messages at this level
+    pub const fn tag(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        }
+    }
+
+    /// Parses a level from its lowercase name
+    const fn parse(name: &str) -> Option<Self> {
+        match name.as_bytes() {
+            b"error" => Some(Self::Error),
+            b"warn" => Some(Self::Warn),
+            b"info" => Some(Self::Info),
+            b"debug" => Some(Self::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// The least severe level that is printed. This is set at build time by the `LOG_LEVEL`
+/// environment variable, as one of `error`, `warn`, `info`, or `debug`, and defaults to `info`
+pub const LOG_LEVEL: Level = match option_env!("LOG_LEVEL") {
+    Some(name) => match Level::parse(name) {
+        Some(level) => level,
+        None => panic!("`LOG_LEVEL` should be one of `error`, `warn`, `info`, or `debug`"),
+    },
+    None => Level::Info,
+};
+
+/// Returns whether messages at the given level are printed
+pub fn enabled(level: Level) -> bool {
+    level <= LOG_LEVEL
+}
+
+/// Prints a line at the given level, if that level is enabled
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {{
+        let level: $crate::log::Level = $level;
+        if $crate::log::enabled(level) {
+            use core::fmt::Write;
+            writeln!(
+                &mut $crate::UART.get().expect("UART should be initialized").lock(),
+                "[{} {}] {}",
+                $crate::machine::core_id(),
+                level.tag(),
+                format_args!($($arg)*),
+            )
+            .unwrap();
+        }
+    }};
+}
+
+/// Prints a line at the error level
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Error, $($arg)*) };
+}
+
+/// Prints a line at the warning level
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Warn, $($arg)*) };
+}
+
+/// Prints a line at the info level
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Info, $($arg)*) };
+}
+
+/// Prints a line at the debug level
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Debug, $($arg)*) };
+}
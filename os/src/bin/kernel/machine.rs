@@ -84,7 +84,10 @@ pub fn faulting_address() -> u64 {
     far
 }
 
-/// Returns a unique numeric ID for the current core
+/// Number of cores on the machine
+pub const CORE_COUNT: usize = 4;
+
+/// Returns a unique numeric ID for the current core, less than `CORE_COUNT`
 pub fn core_id() -> u8 {
     let mpidr_el1: u64;
     unsafe {
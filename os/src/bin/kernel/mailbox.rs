@@ -3,7 +3,6 @@
 //! See <https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface> for more
 //! information
 use bitfield_struct::bitfield;
-use core::arch::aarch64::OSHST;
 use core::arch::asm;
 use core::mem;
 use core::mem::MaybeUninit;
@@ -16,6 +15,7 @@ use tock_registers::registers::ReadWrite;
 use tock_registers::{register_bitfields, register_structs};
 
 use crate::machine::to_physical_addr;
+use common::barrier;
 
 register_bitfields! {
     u32,
@@ -266,8 +266,7 @@ impl Mailbox<'_> {
         }
 
         // Make sure the buffer writes are fully complete, and the cache operations complete
-        // SAFETY: This is defined on the Raspberry Pi
-        unsafe { core::arch::aarch64::__dmb(OSHST) }
+        barrier::dmb_oshst();
 
         // Wait for the mailbox to be available
         while self.registers.status.matches_any(&[STATUS::FULL::Full]) {
@@ -302,8 +301,7 @@ impl Mailbox<'_> {
         let data = self.registers.data.extract();
 
         // Make sure the buffer writes are fully complete, and the cache operations complete
-        // SAFETY: This is defined on the Raspberry Pi
-        unsafe { core::arch::aarch64::__dmb(OSHST) }
+        barrier::dmb_oshst();
 
         // Since we only use one channel, we expect the channel of the response to always match
         // Since we only use synchronous responses, we expect the returned buffer to always match
@@ -368,7 +366,7 @@ impl Mailbox<'_> {
         buffer.region_size.write(0x456);
 
         if self.send(&mut buffer) {
-            unsafe { core::arch::aarch64::__dmb(OSHST) }
+            barrier::dmb_oshst();
             // SAFETY: The pointer is appropriately constructed from the buffer
             let base_addr = unsafe { ptr::addr_of!(buffer.base_address).read_volatile() };
             let size = unsafe { ptr::addr_of!(buffer.region_size).read_volatile() };
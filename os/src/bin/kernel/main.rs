@@ -31,10 +31,10 @@
 #![feature(allocator_api)]
 #![feature(alloc_layout_extra)]
 #![feature(asm_const)]
+#![feature(const_binary_heap_constructor)]
 #![feature(const_mut_refs)]
 #![feature(exposed_provenance)]
 #![feature(generic_arg_infer)]
-#![feature(iterator_try_collect)]
 #![feature(lint_reasons)]
 #![feature(naked_functions)]
 #![feature(panic_info_message)]
@@ -46,15 +46,17 @@
 #![feature(strict_provenance_atomic_ptr)]
 
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use bump_allocator::BumpAllocator;
 use common::cell::OnceLock;
-use common::sync::{SpinLock, WriteGuard};
+use common::os::memory_layout;
+use common::sync::{Barrier, SpinLock, WriteGuard};
 use core::arch::asm;
 use core::fmt::Write;
 use core::num::NonZeroUsize;
 use core::panic::PanicInfo;
 use core::ptr::{self, addr_of_mut, NonNull};
-use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicPtr, AtomicUsize};
 use core::{hint, mem};
 use device_tree::dtb::DeviceTree;
 
@@ -62,21 +64,47 @@ mod boot;
 mod bump_allocator;
 mod exception;
 mod execution;
+mod log;
 mod machine;
 mod mailbox;
 mod memory;
+mod random;
+mod timer;
 mod uart;
 use uart::Uart;
 
 extern crate alloc;
 
 use crate::boot::STACK_SIZE;
-use crate::execution::{ExceptionCode, Execution, UserContext, EXECUTIONS};
+use crate::execution::{ExceptionCode, Execution, UserContext, EXECUTIONS, MAX_EXECUTIONS};
 use crate::memory::PAGE_ALLOCATOR;
 
+/// Physical address of the start of the kernel image
+const KERNEL_IMAGE_START: u64 = 0x8_0000;
+/// Size of the physical memory occupied by the kernel image, including its stacks and heap
+const KERNEL_IMAGE_SIZE: u64 = 0x18_0000;
+
 /// Physical address of the init program's top-level translation table
 const INIT_TRANSLATION_ADDRESS: u64 = 0x0;
 
+/// Physical address of the page that the boot sequence moves the device tree into
+const DEVICE_TREE_PHYSICAL_ADDRESS: u64 = 0x1_0000;
+
+/// Descriptor mapping the device tree page into the init program: readable from usermode, but
+/// neither writeable nor executable
+const DEVICE_TREE_ENTRY: u64 = (1_u64 << 54) // Unprivileged execute-never
+    | (1    << 53) // Privileged execute-never
+    | (1    << 11) // Non-global entry
+    | (1    << 10) // Access flag
+    | (0b11 << 8)  // Shareability
+    | (1    << 7)  // Read-only
+    | (1    << 6)  // EL0 accessible
+    |  0b11        // Valid entry
+    | DEVICE_TREE_PHYSICAL_ADDRESS;
+
+/// Size of the device tree, in bytes, set once it has been validated at boot
+static DEVICE_TREE_SIZE: OnceLock<u16> = OnceLock::new();
+
 /// The global UART for all prints
 static UART: OnceLock<SpinLock<Uart>> = OnceLock::new();
 
@@ -105,11 +133,9 @@ const STACK_ALIGNMENT: usize = 16;
 
 /// The primary initialization sequence for the kernel in EL1
 extern "C" fn main(device_tree_address: *mut u64, device_tree_size: usize) -> ! {
-    /// Set only when the global initialization sequence (stuff that only runs once total) is
-    /// finished
-    static GLOBAL_SETUP_DONE: AtomicBool = AtomicBool::new(false);
-    static NUM_READY: AtomicU8 = AtomicU8::new(0);
-    NUM_READY.fetch_add(1, Ordering::Relaxed);
+    /// Passed once every core has arrived, and the global initialization sequence (stuff that only
+    /// runs once total) is finished
+    static BOOT_BARRIER: Barrier = Barrier::new(machine::CORE_COUNT);
     if machine::core_id() == 0 {
         extern "C" {
             static mut __bss_end: u8;
@@ -127,6 +153,13 @@ extern "C" fn main(device_tree_address: *mut u64, device_tree_size: usize) -> !
             matches!(UART.set(SpinLock::new(uart)), Ok(())),
             "UART should not already be initialized"
         );
+        assert!(
+            common::sync::set_deadlock_reporter(|owner, waiter| {
+                warn!("possible deadlock: lock held by {owner}, waiter {waiter}");
+            })
+            .is_ok(),
+            "Deadlock reporter should not already be set"
+        );
 
         let stack_end = NonNull::new(
             // SAFETY: This is only used to derive a pointer, and so is always safe
@@ -159,10 +192,8 @@ extern "C" fn main(device_tree_address: *mut u64, device_tree_size: usize) -> !
         // let device_tree_address = device_tree_address
         let device_tree_address =
             NonNull::new(device_tree_address).expect("Device tree addresses should be nonnull");
-        assert!(
-            u16::try_from(device_tree_size).is_ok(),
-            "Device tree should fit into a single 64K page"
-        );
+        let device_tree_size_bytes =
+            u16::try_from(device_tree_size).expect("Device tree should fit into a single 64K page");
         assert!(
             device_tree_address.is_aligned(),
             "Device tree should be aligned to an 8-byte boundary",
@@ -183,8 +214,22 @@ extern "C" fn main(device_tree_address: *mut u64, device_tree_size: usize) -> !
         let device_tree =
             DeviceTree::from_bytes(device_tree_memory).expect("Device tree should be valid");
 
-        // TODO: Incorporate reserved memory regions from the device tree
-        unsafe {
+        // The boot sequence has already placed the device tree and init's tables below the kernel,
+        // so any firmware reservations there are superseded
+        let mut reserved: Vec<_> = memory::reserved::from_device_tree(device_tree_memory)
+            .into_iter()
+            .filter_map(|(start, size)| {
+                let end = start.saturating_add(size);
+                let start = start.max(KERNEL_IMAGE_START);
+                (start < end).then_some((start, end - start))
+            })
+            .collect();
+        reserved.push((KERNEL_IMAGE_START, KERNEL_IMAGE_SIZE));
+        let reserved = memory::reserved::merge(reserved);
+        for &(start, size) in &reserved {
+            info!("Reserved physical memory: {start:#X}..{:#X}", start + size);
+        }
+        if let Err(err) = unsafe {
             memory::init(
                 device_tree.root().memory().iter().flat_map(|region| {
                     region.regions().iter().map(|&(start, size)| {
@@ -196,18 +241,44 @@ extern "C" fn main(device_tree_address: *mut u64, device_tree_size: usize) -> !
                         )
                     })
                 }),
-                &[(0x8_0000, 0x18_0000)].iter().copied(),
+                &reserved.iter().copied(),
+            )
+        } {
+            error!(
+                "Unable to manage physical memory {:#X} (size {:#X}): {:?}",
+                err.start, err.size, err.error
             );
+            panic!("Physical memory regions should be usable by the page allocator");
         }
 
         // TODO: better mechanism...
         let page = PAGE_ALLOCATOR.get().unwrap().alloc().unwrap();
         assert_eq!(page.addr(), 0);
-
-        GLOBAL_SETUP_DONE.store(true, Ordering::Release);
+        // The device tree immediately follows, and is kept alive so that drivers can parse it
+        let device_tree_page = PAGE_ALLOCATOR.get().unwrap().alloc().unwrap();
+        assert_eq!(device_tree_page.addr(), DEVICE_TREE_PHYSICAL_ADDRESS);
+        assert!(
+            DEVICE_TREE_SIZE.set(device_tree_size_bytes).is_ok(),
+            "Device tree size should only be set once"
+        );
+        let device_tree_entry = usize::try_from(INIT_TRANSLATION_ADDRESS)
+            .ok()
+            .and_then(|table| {
+                table.checked_add(
+                    (common::os::vm::DEVICE_TREE_ADDRESS >> 16) * mem::size_of::<u64>(),
+                )
+            })
+            .map(|entry| ptr::from_exposed_addr_mut::<u64>(entry | memory_layout::KERNEL_VA_BASE))
+            .expect("Init's translation table should be in the first 2MB of memory");
+        // SAFETY: Init's translation table lives in the kernel's mapping of the first 2MB of
+        // memory, and init has not started yet, so nothing else is accessing it
+        unsafe {
+            device_tree_entry.write_volatile(DEVICE_TREE_ENTRY);
+        }
+        common::barrier::dsb_ishst();
 
         let ctx_ptr = ptr::from_exposed_addr_mut::<UserContext>(0x10);
-        let ctx_ptr2 = ctx_ptr.map_addr(|x| x | 0xFFFF_FFFF_FE00_0000_usize);
+        let ctx_ptr2 = ctx_ptr.map_addr(|x| x | memory_layout::KERNEL_VA_BASE);
         unsafe {
             ctx_ptr2.write_volatile(UserContext {
                 exception_vector: AtomicUsize::new(0x1000),
@@ -224,21 +295,27 @@ extern "C" fn main(device_tree_address: *mut u64, device_tree_size: usize) -> !
         }
 
         let mut executions = EXECUTIONS.write();
-        let init_pid = executions.create(tcr, 0x0, ctx_ptr);
+        // Every slot is allocated up front, so that forks never reallocate the map while holding
+        // its write lock, which would stall every other core
+        executions.reserve(MAX_EXECUTIONS);
+        let init_pid = executions
+            .create(tcr, 0x0, ctx_ptr)
+            .expect("The first execution should not exceed the process limit");
         let executions = WriteGuard::downgrade(executions);
         let init = executions.get(init_pid).unwrap();
         init.add_writable_page(page);
+        init.add_readable_page(device_tree_page.downgrade());
 
-        let num_cores = device_tree.root().cpus().iter().count();
-        while usize::from(NUM_READY.load(Ordering::Relaxed)) != num_cores {
-            hint::spin_loop();
-        }
+        assert_eq!(
+            device_tree.root().cpus().iter().count(),
+            machine::CORE_COUNT,
+            "The device tree should describe every core"
+        );
+        BOOT_BARRIER.wait();
 
-        Execution::jump_into_async(executions, init_pid, ExceptionCode::Resumption, 0)
+        Execution::jump_into_async(executions, init_pid, ExceptionCode::Resumption, 0, 0)
     } else {
-        while !GLOBAL_SETUP_DONE.load(Ordering::Acquire) {
-            hint::spin_loop();
-        }
+        BOOT_BARRIER.wait();
         execution::idle_loop()
     }
 }
@@ -264,6 +341,8 @@ fn panic(info: &PanicInfo) -> ! {
             uart.write_fmt(args);
         }
         writeln!(&mut uart);
+        // Make sure the report makes it out before this core stops for good
+        uart.flush();
     }
     loop {
         hint::spin_loop();
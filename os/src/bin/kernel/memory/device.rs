@@ -0,0 +1,23 @@
+//! Peripheral register ranges that usermode drivers may be granted access to
+
+use super::PAGE_SIZE;
+
+/// Physical `(start, size)` ranges of the peripherals that drivers may map. Each range is whole
+/// pages, so granting one never exposes memory outside of it
+const PERIPHERALS: [(u64, u64); 3] = [
+    // GPIO, along with the UARTs that share its page
+    (0x4_7E20_0000, PAGE_SIZE),
+    // EMMC
+    (0x4_7E30_0000, PAGE_SIZE),
+    // EMMC2
+    (0x4_7E34_0000, PAGE_SIZE),
+];
+
+/// Returns whether the `size` bytes starting at `pa` lie entirely within a single peripheral range
+pub fn is_peripheral(pa: u64, size: u64) -> bool {
+    pa.checked_add(size).is_some_and(|end| {
+        PERIPHERALS
+            .iter()
+            .any(|&(start, len)| start <= pa && end <= start + len)
+    })
+}
@@ -1,7 +1,13 @@
+use crate::boot;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use common::cell::OnceLock;
-use core::sync::atomic::{AtomicU16, Ordering};
-use core::{iter, mem};
+use common::sync::SpinLock;
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use core::{iter, mem, ptr};
+
+pub mod device;
+pub mod reserved;
 
 pub type ProcessCount = u16;
 pub type AtomicProcessCount = AtomicU16;
@@ -9,6 +15,7 @@ const PROCESS_COUNT_BITS: u32 = mem::size_of::<ProcessCount>() as u32;
 // .expect("Process count size should be a small number of bits");
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[must_use = "Dropping a physical page releases its reference to the page"]
 pub struct PhysicalPage(u64);
 
 impl PhysicalPage {
@@ -17,8 +24,20 @@ impl PhysicalPage {
         Self(page)
     }
 
-    pub fn to_owned(self) -> Self {
-        PAGE_ALLOCATOR.get().unwrap().to_owned(self)
+    /// Returns a page with the same contents as this one that is referenced only by the
+    /// returned value, copying the page if it is shared
+    ///
+    /// Returns this page back as an `Err` if it is shared and no page is free to copy it into
+    pub fn to_owned(self) -> Result<Self, Self> {
+        let allocator = PAGE_ALLOCATOR.get().unwrap();
+        let owned = allocator.to_owned(self)?;
+        // The copy-on-write contract: once split, each side holds the only reference to its page
+        debug_assert_eq!(
+            allocator.refcount(owned.0),
+            1,
+            "An owned page should have exactly one reference"
+        );
+        Ok(owned)
     }
 }
 
@@ -36,6 +55,7 @@ impl Drop for PhysicalPage {
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[must_use = "Dropping a page releases its reference; add it to an execution's page set instead"]
 pub struct WriteablePage(PhysicalPage);
 
 impl WriteablePage {
@@ -50,20 +70,70 @@ impl WriteablePage {
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[must_use = "Dropping a page releases its reference; add it to an execution's page set instead"]
 pub struct ReadablePage(PhysicalPage);
 
 impl ReadablePage {
     pub fn addr(&self) -> u64 {
         self.0 .0
     }
+
+    /// Returns whether any other reference to this page exists, as a snapshot
+    pub fn is_shared(&self) -> bool {
+        PAGE_ALLOCATOR.get().unwrap().refcount(self.0 .0) > 1
+    }
+
+    /// Upgrades this page to writeable access, first copying it if it is shared, so that the
+    /// writes are never visible through any other reference
+    ///
+    /// Returns this page back as an `Err` if it is shared and no page is free to copy it into
+    pub fn into_owned_writeable(self) -> Result<WriteablePage, Self> {
+        self.0.to_owned().map(WriteablePage).map_err(Self)
+    }
+}
+
+/// Reasons a region of physical memory may not be managed by a region allocator
+#[derive(Debug)]
+pub enum RegionError {
+    /// The region holds no pages
+    Empty,
+    /// The start or size of the region is not a multiple of the page size
+    Misaligned,
+    /// The end of the region does not fit in the physical address space
+    Overflow,
+}
+
+/// A region of physical memory that the page allocator was unable to manage
+#[derive(Debug)]
+pub struct InitError {
+    /// Start of the offending region
+    pub start: u64,
+    /// Size of the offending region, in bytes
+    pub size: u64,
+    /// Why the region was rejected
+    pub error: RegionError,
 }
 
 struct RegionAllocator {
     start: u64,
     physical_pages: Box<[AtomicU16]>,
+    /// Coarse bitmap of pages in use, one bit per page, so that fully allocated spans of pages can
+    /// be skipped a word at a time.
+    ///
+    /// This is only a hint: a set bit always corresponds to an in-use page (or padding past the
+    /// end of the region), but a racing free and reallocation may leave an in-use page's bit
+    /// clear. Allocation always confirms availability against the refcounts.
+    in_use: Box<[AtomicU64]>,
+    /// Lengths of the multi-page runs handed out by `alloc_contiguous`, keyed by the index of
+    /// their first page. Only the first page of a run is refcounted; the rest of the run stays
+    /// claimed until the first page is freed
+    runs: SpinLock<BTreeMap<usize, usize>>,
 }
 
-const PAGE_SIZE: u64 = 1 << 16;
+/// Size of a physical page, in bytes
+pub const PAGE_SIZE: u64 = 1 << 16;
+/// Number of pages tracked by each word of a region's coarse bitmap
+const BITMAP_WORD_BITS: usize = u64::BITS as usize;
 
 impl RegionAllocator {
     /// Creates a new physical memory allocator wrapping the given region
@@ -72,19 +142,33 @@ impl RegionAllocator {
         start: u64,
         size: u64,
         reserved: impl Iterator<Item = (u64, u64)>,
-    ) -> Option<Self> {
-        if size % PAGE_SIZE != 0 || start % PAGE_SIZE != 0 || start.checked_add(size).is_none() {
-            None
+    ) -> Result<Self, RegionError> {
+        if size == 0 {
+            Err(RegionError::Empty)
+        } else if size % PAGE_SIZE != 0 || start % PAGE_SIZE != 0 {
+            Err(RegionError::Misaligned)
+        } else if start.checked_add(size).is_none() {
+            Err(RegionError::Overflow)
         } else {
             let num_pages = usize::try_from(size / PAGE_SIZE)
                 .expect("Number of physical pages should fit into a `usize`");
+            let num_words = num_pages.div_ceil(BITMAP_WORD_BITS);
 
             let region_allocator = Self {
                 start,
                 physical_pages: iter::repeat_with(|| AtomicU16::new(0))
                     .take(num_pages)
                     .collect(),
+                in_use: iter::repeat_with(|| AtomicU64::new(0))
+                    .take(num_words)
+                    .collect(),
+                runs: SpinLock::new(BTreeMap::new()),
             };
+            // Mark the bits past the end of the region as in use, so that they are never scanned
+            let tail_bits = num_pages % BITMAP_WORD_BITS;
+            if let Some(last) = region_allocator.in_use.last().filter(|_| tail_bits != 0) {
+                last.store(u64::MAX << tail_bits, Ordering::Relaxed);
+            }
             for (start, size) in reserved {
                 for page in (start
                     ..start
@@ -95,86 +179,145 @@ impl RegionAllocator {
                     region_allocator.other_add_ref(page);
                 }
             }
-            Some(region_allocator)
+            Ok(region_allocator)
         }
     }
 
     /// Increments the reference count
     fn other_add_ref(&self, page: u64) -> bool {
-        self.get_page(page)
-            .map(|page_refcount| {
+        self.page_index(page)
+            .and_then(|index| {
+                self.physical_pages
+                    .get(index)
+                    .map(|refcount| (index, refcount))
+            })
+            .map(|(index, page_refcount)| {
                 page_refcount
                     .fetch_update(Ordering::Release, Ordering::Acquire, |refcount| {
                         assert_eq!(refcount, 0);
                         Some(1)
                     })
-                    .expect("Refcount should not overflow")
+                    .expect("Refcount should not overflow");
+                self.mark_in_use(index, true);
             })
             .is_some()
     }
 
+    /// Sets or clears the coarse bitmap bit for the page at the given index
+    fn mark_in_use(&self, index: usize, in_use: bool) {
+        let word = self
+            .in_use
+            .get(index / BITMAP_WORD_BITS)
+            .expect("Page index should be in bounds of the bitmap");
+        let bit = 1 << (index % BITMAP_WORD_BITS);
+        if in_use {
+            word.fetch_or(bit, Ordering::Relaxed);
+        } else {
+            word.fetch_and(!bit, Ordering::Relaxed);
+        }
+    }
+
     /// Allocates a physical page from this region, if any are available.
+    ///
+    /// Fully allocated spans of pages are skipped using the coarse bitmap, and candidate pages are
+    /// only claimed once their refcount is confirmed to be zero
     fn alloc(&self) -> Option<PhysicalPage> {
-        self.physical_pages
+        self.in_use
             .iter()
             .enumerate()
-            .find_map(|(index, page)| {
-                page.fetch_update(Ordering::Acquire, Ordering::Relaxed, |refcount| {
-                    (refcount == 0).then_some(1)
-                    // (readers == 0).then(|| {
-                    //     assert_eq!(
-                    //         writers, 0,
-                    //         "The number of writers should never exceed the number of readers"
-                    //     );
-                    //     (1, 1)
-                    // })
-                })
-                .ok()
-                .map(|_val| {
-                    let paddr = u64::try_from(index)
+            .find_map(|(word_index, word)| {
+                let mut candidates = word.load(Ordering::Relaxed);
+                while candidates != u64::MAX {
+                    let bit = usize::try_from(candidates.trailing_ones())
+                        .expect("Bit offsets should fit into a `usize`");
+                    candidates |= 1 << bit;
+                    let index = word_index * BITMAP_WORD_BITS + bit;
+                    if self.physical_pages.get(index).is_some_and(|refcount| {
+                        refcount
+                            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+                            .is_ok()
+                    }) {
+                        self.mark_in_use(index, true);
+                        return Some(index);
+                    }
+                }
+                None
+            })
+            .map(|index| {
+                let paddr = u64::try_from(index)
+                    .ok()
+                    .and_then(|index| index.checked_mul(PAGE_SIZE))
+                    .and_then(|offset| offset.checked_add(self.start))
+                    .expect("Physical page should have been verified to be in bounds");
+                unsafe { PhysicalPage::new(paddr) }
+            })
+    }
+
+    /// Allocates a run of `pages` physically contiguous pages from this region, if there is one,
+    /// and returns its first page. Freeing that page frees the whole run
+    fn alloc_contiguous(&self, pages: usize) -> Option<PhysicalPage> {
+        if pages == 0 {
+            return None;
+        }
+        let mut start = 0;
+        while start.checked_add(pages)? <= self.physical_pages.len() {
+            match self.claim_run(start, pages) {
+                Ok(()) => {
+                    if pages > 1 {
+                        self.runs.lock().insert(start, pages);
+                    }
+                    let paddr = u64::try_from(start)
                         .ok()
                         .and_then(|index| index.checked_mul(PAGE_SIZE))
                         .and_then(|offset| offset.checked_add(self.start))
                         .expect("Physical page should have been verified to be in bounds");
-                    unsafe { PhysicalPage::new(paddr) }
-                })
-                // while let local_word = word.load(Ordering::Relaxed)
-                //     && local_word != 0
-                // {
-                //     let offset = local_word.trailing_zeros();
-                //     assert!(offset != usize::BITS);
-                //     assert_eq!(local_word & (1 << offset), 1);
-                //     if word
-                //         .compare_exchange(
-                //             local_word,
-                //             local_word & !(1 << offset),
-                //             Ordering::Acquire,
-                //             Ordering::Relaxed,
-                //         )
-                //         .is_ok()
-                //     {
-                //         let full_index = index as u64 * u64::from(usize::BITS) + u64::from(offset);
-                //         return unsafe {
-                //             Some(PhysicalPage::new(self.start + full_index * PAGE_SIZE))
-                //         };
-                //     }
-                // }
-                // None
-            })
+                    return Some(unsafe { PhysicalPage::new(paddr) });
+                }
+                // No run containing the busy page can succeed, so the scan resumes past it
+                Err(busy) => start = busy + 1,
+            }
+        }
+        None
+    }
+
+    /// Claims the `pages` pages starting at the given index, one at a time. If another core holds
+    /// or grabs one of them first, releases those already claimed and returns the index of the
+    /// page in use
+    fn claim_run(&self, start: usize, pages: usize) -> Result<(), usize> {
+        for index in start..start + pages {
+            if self.physical_pages[index]
+                .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                for claimed in start..index {
+                    self.physical_pages[claimed].store(0, Ordering::Release);
+                }
+                return Err(index);
+            }
+        }
+        for index in start..start + pages {
+            self.mark_in_use(index, true);
+        }
+        Ok(())
+    }
+
+    /// Gets the index of a given physical page within this region
+    ///
+    /// Returns `None` if the page precedes this region
+    fn page_index(&self, page: u64) -> Option<usize> {
+        page.checked_sub(self.start).map(|offset| {
+            assert_eq!(offset % PAGE_SIZE, 0, "Pages should be page aligned");
+            usize::try_from(offset / PAGE_SIZE)
+                .expect("Physical page numbers should fit into a `usize`")
+        })
     }
 
     /// Gets a reference to the refcount of a given physical page
     ///
     /// Returns `None` if the page is not in use by this allocator
     fn get_page(&self, page: u64) -> Option<&AtomicU16> {
-        page.checked_sub(self.start).and_then(|offset| {
-            assert_eq!(offset % PAGE_SIZE, 0, "Pages should be page aligned");
-            let index = usize::try_from(offset / PAGE_SIZE)
-                .expect("Physical page numbers should fit into a `usize`");
-            // let index = full_index / usize::BITS as usize;
-            // let offset = full_index % usize::BITS as usize;
-            self.physical_pages.get(index)
-        })
+        self.page_index(page)
+            .and_then(|index| self.physical_pages.get(index))
     }
 
     /// Increments the reference count
@@ -190,21 +333,12 @@ impl RegionAllocator {
             .is_some()
     }
 
-    fn to_owned(&self, page: PhysicalPage) -> Result<PhysicalPage, PhysicalPage> {
-        if let Some(refcount) = self.get_page(page.0) {
-            match refcount.load(Ordering::Acquire) {
-                0 => unreachable!("Refcount of an in-use page should never be zero"),
-                1 => Ok(page),
-                2.. => {
-                    let new_page = self.alloc().unwrap();
-                    todo!("Implement memcpy across physical pages");
-                    Ok(new_page)
-                }
-            }
-        } else {
-            Err(page)
-        }
-        // .ok_or(page)
+    /// Returns the number of pages in this region that are not currently in use, as a snapshot
+    fn free_page_count(&self) -> usize {
+        self.physical_pages
+            .iter()
+            .filter(|refcount| refcount.load(Ordering::Relaxed) == 0)
+            .count()
     }
 
     /// Decrements the reference count a physical page with this region, freeing it if there are no other accessers.
@@ -215,12 +349,29 @@ impl RegionAllocator {
     /// If the page is in range of this region, the page to deallocate must have been from an allocation or refcount increment from this region.
     /// The page is invalid to read after being deallocated, using this source.
     unsafe fn remove_ref(&self, page: u64) -> bool {
-        self.get_page(page)
-            .map(|page| {
-                page.fetch_update(Ordering::Release, Ordering::Relaxed, |refcount| {
-                    refcount.checked_sub(1)
-                })
-                .expect("Refcount should not overflow")
+        self.page_index(page)
+            .and_then(|index| {
+                self.physical_pages
+                    .get(index)
+                    .map(|refcount| (index, refcount))
+            })
+            .map(|(index, page)| {
+                let previous = page
+                    .fetch_update(Ordering::Release, Ordering::Relaxed, |refcount| {
+                        refcount.checked_sub(1)
+                    })
+                    .expect("Refcount should not overflow");
+                if previous == 1 {
+                    // The rest of a run stays claimed until here, so the first page can only be
+                    // reallocated on its own in the meantime, which never records a run over it
+                    if let Some(pages) = self.runs.lock().remove(&index) {
+                        for tail in index + 1..index + pages {
+                            self.physical_pages[tail].store(0, Ordering::Release);
+                            self.mark_in_use(tail, false);
+                        }
+                    }
+                    self.mark_in_use(index, false);
+                }
                 // let final_readers = readers
                 //     .checked_sub(1)
                 //     .expect("Number of readers should have been at least one");
@@ -245,7 +396,9 @@ pub struct PageAllocator {
 }
 
 impl PageAllocator {
-    /// Creates a new page allocator from some list of ranges
+    /// Creates a new page allocator from some list of ranges. Empty ranges, which the device tree
+    /// may list, hold no pages and are skipped. Returns the first other range that cannot be
+    /// managed, if any
     ///
     /// # Safety
     ///
@@ -254,15 +407,24 @@ impl PageAllocator {
     unsafe fn new(
         ranges: impl Iterator<Item = (u64, u64)>,
         reserved: &(impl Iterator<Item = (u64, u64)> + Clone),
-    ) -> Option<Self> {
+    ) -> Result<Self, InitError> {
         ranges
             .into_iter()
-            .map(|(start, size)| unsafe { RegionAllocator::new(start, size, reserved.clone()) })
-            .try_collect()
+            .filter(|&(_, size)| size != 0)
+            .map(|(start, size)| {
+                unsafe { RegionAllocator::new(start, size, reserved.clone()) }
+                    .map_err(|error| InitError { start, size, error })
+            })
+            .collect::<Result<_, _>>()
             .map(|regions| Self { regions })
     }
 
     /// Allocates an available page, if any are available
+    ///
+    /// The page still holds whatever its previous owner left in it, so this is only for kernel
+    /// uses that overwrite the whole page before anything else can see it. Pages handed to
+    /// usermode should come from `alloc_zeroed` instead
+    #[must_use]
     pub fn alloc(&self) -> Option<WriteablePage> {
         self.regions
             .iter()
@@ -270,6 +432,48 @@ impl PageAllocator {
             .map(WriteablePage)
     }
 
+    /// Allocates an available page, if any are available, and fills it with zeroes so that no
+    /// data from its previous owner leaks to its new one
+    #[must_use]
+    pub fn alloc_zeroed(&self) -> Option<WriteablePage> {
+        let page = self.alloc()?;
+        zero_page(page.addr());
+        Some(page)
+    }
+
+    /// Allocates `pages` physically contiguous pages, if any region has such a run available, and
+    /// returns the first page. Freeing the first page frees the whole run
+    ///
+    /// The pages are not zeroed, and are intended for kernel uses such as DMA buffers, whose
+    /// physical layout matters
+    #[must_use]
+    #[expect(dead_code, reason = "DMA drivers will build upon this")]
+    pub fn alloc_contiguous(&self, pages: usize) -> Option<WriteablePage> {
+        self.regions
+            .iter()
+            .find_map(|region| region.alloc_contiguous(pages))
+            .map(WriteablePage)
+    }
+
+    /// Returns the number of pages not currently in use, across all regions
+    ///
+    /// Refcounts are read one at a time without synchronization, so under concurrent allocations
+    /// and frees this is only an approximate snapshot
+    pub fn free_page_count(&self) -> usize {
+        self.regions
+            .iter()
+            .map(RegionAllocator::free_page_count)
+            .sum()
+    }
+
+    /// Returns the total number of pages managed by this allocator, including reserved pages
+    pub fn total_page_count(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|region| region.physical_pages.len())
+            .sum()
+    }
+
     /// Increments the refcount for a given page
     fn add_ref(&self, page: u64) {
         self.regions
@@ -278,14 +482,38 @@ impl PageAllocator {
             .expect("Physical page should have been allocated prior from some region");
     }
 
-    fn to_owned(&self, mut page: PhysicalPage) -> PhysicalPage {
-        for region in self.regions.iter() {
-            match region.to_owned(page) {
-                Ok(new_page) => return new_page,
-                Err(og_page) => page = og_page,
+    /// Returns the current refcount of the given page, as a snapshot
+    ///
+    /// # Panics
+    ///
+    /// Panics if the page is not managed by this allocator
+    fn refcount(&self, page: u64) -> u16 {
+        self.regions
+            .iter()
+            .find_map(|region| region.get_page(page))
+            .expect("Physical page should have been allocated prior from some region")
+            // Acquiring pairs with the release of dropped references, so that a page found to be
+            // unshared is no longer being accessed through them
+            .load(Ordering::Acquire)
+    }
+
+    /// Returns a page with the same contents as the given one that is referenced only by the
+    /// returned value, copying the page into any free page if it is shared
+    ///
+    /// Returns the given page back as an `Err` if it is shared and no page is free
+    fn to_owned(&self, page: PhysicalPage) -> Result<PhysicalPage, PhysicalPage> {
+        match self.refcount(page.0) {
+            0 => unreachable!("Refcount of an in-use page should never be zero"),
+            1 => Ok(page),
+            2.. => {
+                let Some(new_page) = self.regions.iter().find_map(RegionAllocator::alloc) else {
+                    return Err(page);
+                };
+                copy_page(page.0, new_page.0);
+                // Dropping the shared page releases this reference to it
+                Ok(new_page)
             }
         }
-        unreachable!("Physical page should have been allocated prior from some region")
     }
 
     /// Decreases the refcount of a physical page
@@ -303,11 +531,90 @@ impl PageAllocator {
     }
 }
 
+/// Serializes use of the kernel's scratch windows, which copies of physical pages go through
+static SCRATCH_WINDOWS: SpinLock<()> = SpinLock::new(());
+
+/// Copies the contents of one physical page into another
+///
+/// Physical memory is not generally mapped into the kernel's address space, so both pages are
+/// transiently mapped into the kernel's scratch windows (see `boot::map_scratch`) for the copy
+fn copy_page(source: u64, destination: u64) {
+    let _windows = SCRATCH_WINDOWS.lock();
+    // SAFETY: The lock grants exclusive use of the scratch windows, which are unmapped when not
+    // in use. Physical pages are always normal memory
+    unsafe {
+        let source_ptr = boot::map_scratch(0, source);
+        let destination_ptr = boot::map_scratch(1, destination);
+        ptr::copy_nonoverlapping(
+            source_ptr.as_ptr(),
+            destination_ptr.as_ptr(),
+            usize::try_from(PAGE_SIZE).expect("Page size should fit in a `usize`"),
+        );
+        boot::unmap_scratch(0);
+        boot::unmap_scratch(1);
+    }
+}
+
+/// Reads the `u64` at the given physical address, through a transient mapping in a scratch window
+///
+/// # Panics
+///
+/// Panics if the address is not aligned to 8 bytes
+pub fn read_physical(pa: u64) -> u64 {
+    assert_eq!(pa % 8, 0, "Physical address should be aligned");
+    let _windows = SCRATCH_WINDOWS.lock();
+    // SAFETY: The lock grants exclusive use of the scratch windows, which are unmapped when not
+    // in use. Physical pages are always normal memory, and the address is aligned
+    unsafe {
+        let value = boot::map_scratch(0, pa)
+            .cast::<u64>()
+            .as_ptr()
+            .read_volatile();
+        boot::unmap_scratch(0);
+        value
+    }
+}
+
+/// Writes a `u64` to the given physical address, through a transient mapping in a scratch window
+///
+/// # Panics
+///
+/// Panics if the address is not aligned to 8 bytes
+pub fn write_physical(pa: u64, value: u64) {
+    assert_eq!(pa % 8, 0, "Physical address should be aligned");
+    let _windows = SCRATCH_WINDOWS.lock();
+    // SAFETY: The lock grants exclusive use of the scratch windows, which are unmapped when not
+    // in use. Physical pages are always normal memory, and the address is aligned
+    unsafe {
+        boot::map_scratch(0, pa)
+            .cast::<u64>()
+            .as_ptr()
+            .write_volatile(value);
+        boot::unmap_scratch(0);
+    }
+}
+
+/// Fills a physical page with zeroes, through a transient mapping in a scratch window
+fn zero_page(page: u64) {
+    let _windows = SCRATCH_WINDOWS.lock();
+    // SAFETY: The lock grants exclusive use of the scratch windows, which are unmapped when not
+    // in use. Physical pages are always normal memory
+    unsafe {
+        let page_ptr = boot::map_scratch(0, page);
+        ptr::write_bytes(
+            page_ptr.as_ptr(),
+            0,
+            usize::try_from(PAGE_SIZE).expect("Page size should fit in a `usize`"),
+        );
+        boot::unmap_scratch(0);
+    }
+}
+
 /// The global page allocator for all of physical memory
 pub static PAGE_ALLOCATOR: OnceLock<PageAllocator> = OnceLock::new();
 
-/// Initializes the memory allocator using the given memory regions
-///
+/// Initializes the memory allocator using the given memory regions, skipping any empty ones.
+/// Returns the first region that cannot be managed, if any
 ///
 /// # Safety
 ///
@@ -316,12 +623,12 @@ pub static PAGE_ALLOCATOR: OnceLock<PageAllocator> = OnceLock::new();
 pub unsafe fn init(
     ranges: impl Iterator<Item = (u64, u64)>,
     reserved: &(impl Iterator<Item = (u64, u64)> + Clone),
-) {
+) -> Result<(), InitError> {
     // TODO: explicitly validate that memory regions don't overlap
+    let allocator = unsafe { PageAllocator::new(ranges, reserved) }?;
     assert!(
-        PAGE_ALLOCATOR
-            .set(unsafe { PageAllocator::new(ranges, reserved) }.unwrap())
-            .is_ok(),
+        PAGE_ALLOCATOR.set(allocator).is_ok(),
         "Page allocator should be initialized only once "
     );
+    Ok(())
 }
@@ -0,0 +1,174 @@
+//! Physical memory that the device tree marks as reserved, either through the `/memreserve/`
+//! block or the children of the `/reserved-memory` node
+
+use alloc::vec::Vec;
+
+use super::PAGE_SIZE;
+
+/// Size of a cell, the unit in which property values are measured
+const CELL_SIZE: usize = 4;
+
+/// Token starting a node in the structure block
+const FDT_BEGIN_NODE: u32 = 1;
+/// Token ending a node in the structure block
+const FDT_END_NODE: u32 = 2;
+/// Token starting a property in the structure block
+const FDT_PROP: u32 = 3;
+/// Token to ignore in the structure block
+const FDT_NOP: u32 = 4;
+/// Token ending the structure block
+const FDT_END: u32 = 9;
+
+/// A flattened device tree blob, read with bounds checks
+struct Blob<'dtb>(&'dtb [u8]);
+
+impl Blob<'_> {
+    /// Reads the big-endian `u32` at the given offset
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.0.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    /// Reads the big-endian `u64` at the given offset
+    fn u64(&self, offset: usize) -> Option<u64> {
+        let bytes = self.0.get(offset..offset.checked_add(8)?)?;
+        Some(u64::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    /// Reads a big-endian number spanning the given number of 32-bit cells at the given offset
+    fn cells(&self, offset: usize, cells: u32) -> Option<u64> {
+        (0..cells).try_fold(0_u64, |value, cell| {
+            let cell_offset = offset.checked_add(usize::try_from(cell).ok()? * CELL_SIZE)?;
+            Some((value << 32) | u64::from(self.u32(cell_offset)?))
+        })
+    }
+
+    /// Reads the NUL-terminated string at the given offset
+    fn str(&self, offset: usize) -> Option<&[u8]> {
+        let bytes = self.0.get(offset..)?;
+        bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .map(|end| &bytes[..end])
+    }
+
+    /// Reads a header field, as an offset into the blob
+    fn header_offset(&self, field: usize) -> Option<usize> {
+        self.u32(field)
+            .and_then(|offset| usize::try_from(offset).ok())
+    }
+}
+
+/// Collects the `(start, size)` pairs listed in the `/memreserve/` block
+fn memreserve(blob: &Blob, regions: &mut Vec<(u64, u64)>) -> Option<()> {
+    let mut offset = blob.header_offset(16)?;
+    loop {
+        let start = blob.u64(offset)?;
+        let size = blob.u64(offset.checked_add(8)?)?;
+        if start == 0 && size == 0 {
+            return Some(());
+        }
+        regions.push((start, size));
+        offset = offset.checked_add(16)?;
+    }
+}
+
+/// Collects the `(start, size)` pairs in the `reg` properties of the children of
+/// `/reserved-memory`. Children that only request a `size` are allocated dynamically by the OS,
+/// and so are not reserved here
+fn reserved_memory_node(blob: &Blob, regions: &mut Vec<(u64, u64)>) -> Option<()> {
+    let strings = blob.header_offset(12)?;
+    let mut offset = blob.header_offset(8)?;
+    let mut depth = 0_usize;
+    let mut in_reserved_memory = false;
+    let mut address_cells = 2;
+    let mut size_cells = 1;
+    loop {
+        let token = blob.u32(offset)?;
+        offset = offset.checked_add(4)?;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = blob.str(offset)?;
+                offset = offset
+                    .checked_add(name.len() + 1)?
+                    .checked_next_multiple_of(4)?;
+                depth = depth.checked_add(1)?;
+                if depth == 2 && name == b"reserved-memory" {
+                    in_reserved_memory = true;
+                }
+            }
+            FDT_END_NODE => {
+                if depth == 2 {
+                    in_reserved_memory = false;
+                }
+                depth = depth.checked_sub(1)?;
+            }
+            FDT_PROP => {
+                let len = usize::try_from(blob.u32(offset)?).ok()?;
+                let name_offset = usize::try_from(blob.u32(offset.checked_add(4)?)?).ok()?;
+                let value = offset.checked_add(8)?;
+                offset = value.checked_add(len)?.checked_next_multiple_of(4)?;
+                if !in_reserved_memory {
+                    continue;
+                }
+                let name = blob.str(strings.checked_add(name_offset)?)?;
+                match (depth, name) {
+                    (2, b"#address-cells") => address_cells = blob.u32(value)?,
+                    (2, b"#size-cells") => size_cells = blob.u32(value)?,
+                    (3, b"reg") => {
+                        let entry_len =
+                            usize::try_from(address_cells + size_cells).ok()? * CELL_SIZE;
+                        for entry in (value..value.checked_add(len)?).step_by(entry_len.max(1)) {
+                            let size_offset = entry
+                                .checked_add(usize::try_from(address_cells).ok()? * CELL_SIZE)?;
+                            regions.push((
+                                blob.cells(entry, address_cells)?,
+                                blob.cells(size_offset, size_cells)?,
+                            ));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => return Some(()),
+            _ => return None,
+        }
+    }
+}
+
+/// Sorts the given `(start, size)` regions, widens each to whole pages, and merges any that
+/// overlap or touch, so that no page is reserved twice. Regions reaching the top of the physical
+/// address space are clamped to its last whole page
+pub fn merge(mut regions: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    regions.sort_unstable_by_key(|&(start, _)| start);
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(regions.len());
+    for (start, size) in regions {
+        let end = start
+            .saturating_add(size)
+            .checked_next_multiple_of(PAGE_SIZE)
+            .unwrap_or(u64::MAX & !(PAGE_SIZE - 1));
+        let start = start - start % PAGE_SIZE;
+        if let Some(last) = merged.last_mut().filter(|last| start <= last.0 + last.1) {
+            last.1 = last.1.max(end - last.0);
+        } else {
+            merged.push((start, end - start));
+        }
+    }
+    merged
+}
+
+/// Returns the `(start, size)` pairs of memory that the device tree reserves, without merging
+///
+/// # Panics
+///
+/// Panics if the device tree is malformed
+pub fn from_device_tree(device_tree: &[u64]) -> Vec<(u64, u64)> {
+    // SAFETY: Any initialized `u64`s are also valid as bytes
+    let (_, bytes, _) = unsafe { device_tree.align_to::<u8>() };
+    let blob = Blob(bytes);
+    let mut regions = Vec::new();
+    memreserve(&blob, &mut regions).expect("Device tree memory reservations should be valid");
+    reserved_memory_node(&blob, &mut regions).expect("Device tree structure block should be valid");
+    regions
+}
@@ -0,0 +1,39 @@
+//! A fast pseudorandom generator backing usermode's `getrandom`
+//!
+//! The generator is xorshift64*, and the system counter is folded into its state on every fill, so
+//! that the output depends on exactly when each fill happens. It is **not** cryptographically
+//! secure: its output is predictable to anyone who observes enough of it, so it must only be used
+//! for hashing, seeding, and similar
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::timer;
+
+/// Odd constant, derived from the golden ratio, by which the state advances between fills so that
+/// fills racing on different cores start from different states
+const STATE_INCREMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Multiplier that scrambles the xorshift state into each output word
+const OUTPUT_MULTIPLIER: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// State carried between fills
+static STATE: AtomicU64 = AtomicU64::new(STATE_INCREMENT);
+
+/// Fills the given bytes with pseudorandom data
+pub fn fill(bytes: &mut [u8]) {
+    // The low bits of the counter jitter from call to call, and are spread across the state
+    let mut state = STATE.fetch_add(STATE_INCREMENT, Ordering::Relaxed)
+        ^ timer::counter().wrapping_mul(STATE_INCREMENT);
+    if state == 0 {
+        // xorshift never leaves the all-zeroes state
+        state = STATE_INCREMENT;
+    }
+    for chunk in bytes.chunks_mut(8) {
+        state ^= state >> 12_u8;
+        state ^= state << 25_u8;
+        state ^= state >> 27_u8;
+        let word = state.wrapping_mul(OUTPUT_MULTIPLIER).to_le_bytes();
+        chunk.copy_from_slice(&word[..chunk.len()]);
+    }
+    STATE.store(state, Ordering::Relaxed);
+}
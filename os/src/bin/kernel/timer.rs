@@ -0,0 +1,93 @@
+//! Access to this core's EL1 physical generic timer, which drives timeslices and sleeping
+//! executions
+
+use core::arch::asm;
+use core::time::Duration;
+
+/// Number of nanoseconds in a second
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Returns the frequency of the system counter, in ticks per second
+pub fn frequency() -> u64 {
+    let frequency;
+    // SAFETY: This only reads a system register with no other side effects
+    unsafe {
+        asm! {
+            "mrs {}, CNTFRQ_EL0",
+            out(reg) frequency,
+            options(nomem, nostack, preserves_flags)
+        };
+    }
+    frequency
+}
+
+/// Returns the current value of the system counter
+pub fn counter() -> u64 {
+    let counter;
+    // Keeps the read from being performed early, out of order with preceding instructions
+    common::barrier::isb();
+    // SAFETY: This only reads a system register with no other side effects
+    unsafe {
+        asm! {
+            "mrs {}, CNTPCT_EL0",
+            out(reg) counter,
+            options(nomem, nostack, preserves_flags)
+        };
+    }
+    counter
+}
+
+/// Converts a duration into a number of system counter ticks, rounding down and saturating at
+/// `u64::MAX`
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    // `CNTFRQ_EL0` only holds 32 meaningful bits, and a `Duration` holds under 2^94 nanoseconds,
+    // so the product always fits into 128 bits
+    let ticks = duration.as_nanos() * u128::from(frequency()) / u128::from(NANOS_PER_SECOND);
+    u64::try_from(ticks).unwrap_or(u64::MAX)
+}
+
+/// Converts a number of system counter ticks into a duration, rounding down to the nanosecond
+///
+/// # Panics
+///
+/// Panics if the counter frequency is not set
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let frequency = frequency();
+    assert_ne!(
+        frequency, 0,
+        "Counter frequency should be set by the firmware"
+    );
+    let seconds = ticks / frequency;
+    // The remainder is less than the 32-bit frequency, so this product fits into 64 bits
+    let nanos = (ticks % frequency) * NANOS_PER_SECOND / frequency;
+    Duration::new(
+        seconds,
+        u32::try_from(nanos).expect("Subsecond nanoseconds should fit into a `u32`"),
+    )
+}
+
+/// Returns the counter value at which this core's timer next fires
+pub fn deadline() -> u64 {
+    let deadline;
+    // SAFETY: This only reads a system register with no other side effects
+    unsafe {
+        asm! {
+            "mrs {}, CNTP_CVAL_EL0",
+            out(reg) deadline,
+            options(nomem, nostack, preserves_flags)
+        };
+    }
+    deadline
+}
+
+/// Sets the counter value at which this core's timer next fires
+pub fn set_deadline(deadline: u64) {
+    // SAFETY: The timer only raises an interrupt, which is handled by the kernel
+    unsafe {
+        asm! {
+            "msr CNTP_CVAL_EL0, {}",
+            in(reg) deadline,
+            options(nomem, nostack, preserves_flags)
+        };
+    }
+}
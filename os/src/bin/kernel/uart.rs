@@ -1,6 +1,6 @@
 //! Driver for the Raspberry Pi's UART. See items for more information
 
-use core::arch::aarch64::{self, OSH};
+use common::barrier;
 use core::fmt::{self, Write};
 use core::hint;
 use core::num::NonZeroUsize;
@@ -64,7 +64,24 @@ register_bitfields! {
             Nonfull = 0,
             Full = 1
         ],
-        TXFE OFFSET(7) NUMBITS(1) [],
+        /// Transmit FIFO empty. The meaning of this bit depends on the state of the `FEN` bit in
+        /// the `UART_LCRH` Register.
+        ///
+        /// If the FIFO is disabled, this bit is set when the transmit holding register is empty.
+        ///
+        /// If the FIFO is enabled, the `TXFE` bit is set when the transmit FIFO is empty. This
+        /// does not indicate that the data in the transmit shift register has been sent
+        TXFE OFFSET(7) NUMBITS(1) [
+            Nonempty = 0,
+            Empty = 1
+        ],
+        /// UART busy. If this bit is set, the UART is busy transmitting data. This bit remains
+        /// set until the complete byte, including all the stop bits, has been sent from the shift
+        /// register
+        BUSY OFFSET(3) NUMBITS(1) [
+            Idle = 0,
+            Busy = 1
+        ],
     ],
     /// The raw interrupt status register
     RIS [
@@ -179,22 +196,30 @@ impl<'uart> Uart<'uart> {
     ///
     /// Returns an `Err` if an IO error occurs
     pub fn write_byte(&mut self, byte: u8) -> Result<(), IoError> {
-        // SAFETY: This is well defined on the Raspberry Pi
-        unsafe {
-            aarch64::__dmb(OSH);
-        }
+        barrier::dmb_osh();
         while self.registers.fr.matches_any(&[FR::TXFF::Full]) {
             self.check_errors()?;
             hint::spin_loop();
         }
         self.registers.dr.write(DR_W::DATA.val(byte.into()));
-        // SAFETY: This is well defined on the Raspberry Pi
-        unsafe {
-            aarch64::__dmb(OSH);
-        }
+        barrier::dmb_osh();
         Ok(())
     }
 
+    /// Waits until every byte written to the UART has been fully transmitted, so that nothing is
+    /// lost if the system stops immediately afterwards
+    pub fn flush(&mut self) {
+        barrier::dmb_osh();
+        while !self
+            .registers
+            .fr
+            .matches_all(FR::TXFE::Empty + FR::BUSY::Idle)
+        {
+            hint::spin_loop();
+        }
+        barrier::dmb_osh();
+    }
+
     /// Writes multiple bytes to the UART
     ///
     /// Returns `Ok` if all bytes are written
@@ -0,0 +1,63 @@
+//! Layout of the named binaries appended to the boot image, after the init program
+//!
+//! The table is a sequence of entries, each consisting of a header followed by the binary itself,
+//! padded so that the next header (and so every binary) is aligned to `ALIGN` bytes relative to
+//! the start of the table. The table ends at the first header without the correct magic, or when
+//! too few bytes remain. This layout is mirrored by the image builder in `xtask`.
+
+/// Magic marking the start of an entry header
+pub const MAGIC: [u8; 4] = *b"BIMG";
+/// Maximum length of an entry's name, in bytes. Shorter names are padded with NULs
+pub const NAME_LEN: usize = 24;
+/// Size of an entry header: the magic, the length of the binary as a little-endian `u32`, and
+/// then the name
+pub const HEADER_SIZE: usize = MAGIC.len() + 4 + NAME_LEN;
+/// Alignment of each entry, relative to the start of the table
+pub const ALIGN: usize = 8;
+
+/// A binary located within the table
+#[derive(Debug)]
+pub struct Entry<'table> {
+    /// Name of the binary, without padding
+    pub name: &'table [u8],
+    /// Offset of the binary from the start of the table
+    pub offset: usize,
+    /// Length of the binary, in bytes
+    pub len: usize,
+}
+
+/// Iterates over the entries of the given table
+#[inline]
+pub fn entries(table: &[u8]) -> impl Iterator<Item = Entry> {
+    let mut offset = 0;
+    core::iter::from_fn(move || {
+        let header = table.get(offset..offset.checked_add(HEADER_SIZE)?)?;
+        let (magic, rest) = header.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return None;
+        }
+        let (len, name) = rest.split_at(4);
+        let len = usize::try_from(u32::from_le_bytes(len.try_into().ok()?)).ok()?;
+        let name_len = name.iter().position(|&byte| byte == 0).unwrap_or(NAME_LEN);
+
+        let data_offset = offset.checked_add(HEADER_SIZE)?;
+        // Reject truncated binaries
+        table.get(data_offset..data_offset.checked_add(len)?)?;
+        offset = data_offset
+            .checked_add(len)?
+            .checked_next_multiple_of(ALIGN)?;
+        Some(Entry {
+            name: name.get(..name_len)?,
+            offset: data_offset,
+            len,
+        })
+    })
+}
+
+/// Finds the binary with the given name in the table
+///
+/// Returns `None` if no such binary exists
+#[inline]
+pub fn find<'table>(table: &'table [u8], name: &str) -> Option<Entry<'table>> {
+    entries(table).find(|entry| entry.name == name.as_bytes())
+}
@@ -16,7 +16,7 @@ pub struct OnceLock<T> {
 }
 
 impl<T> OnceLock<T> {
-    /// Creates a new, empty `InitCell`
+    /// Creates a new, empty `OnceLock`
     #[inline]
     pub const fn new() -> Self {
         Self {
@@ -26,10 +26,11 @@ impl<T> OnceLock<T> {
         }
     }
 
-    /// Sets the `InitCell` to the given value, if the value is not already set or being set
+    /// Sets the `OnceLock` to the given value, if the value is not already set or being set
     ///
     /// Returns whether or not the setting operation was successful. Fails if already set or there
-    /// are concurrent setters
+    /// are concurrent setters: of any number of racing calls, exactly one claims `is_setting` and
+    /// succeeds, and the rest return their value untouched
     #[inline]
     pub fn set(&self, value: T) -> Result<(), T> {
         if self.is_setting.swap(true, Ordering::Relaxed) {
@@ -0,0 +1,179 @@
+//! Collections that are usable without allocating, and so from any context in the kernel
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A fixed-capacity queue of bytes, shared between a single producer and a single consumer without
+/// locking. The capacity `N` must be a power of two
+///
+/// Only one context may push at a time, and only one context may pop at a time, though the
+/// producer and consumer may run concurrently with each other. Pushing or popping from more than
+/// one context at once is undefined behavior, which is why `push` and `pop` are `unsafe`
+pub struct RingBuffer<const N: usize> {
+    /// Storage for the bytes in the queue, indexed by the counters modulo `N`
+    buf: [UnsafeCell<u8>; N],
+    /// Total number of bytes ever popped, wrapping on overflow. Only written by the consumer
+    head: AtomicUsize,
+    /// Total number of bytes ever pushed, wrapping on overflow. Only written by the producer
+    tail: AtomicUsize,
+}
+
+// SAFETY: The producer only writes slots that the consumer has released, and vice versa, with the
+// counters' acquire/release ordering publishing the bytes between them. Callers of `push` and `pop`
+// promise that there is at most one of each at a time
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+    /// Creates a new, empty ring buffer
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is not a power of two, since the wrapping counters would otherwise skip
+    /// slots when they overflow
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two(), "Capacity should be a power of two");
+        Self {
+            buf: [const { UnsafeCell::new(0) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of bytes in the buffer. This may be stale by the time it is returned
+    /// unless called from the producer or consumer
+    #[inline]
+    pub fn len(&self) -> usize {
+        // The head is loaded first: `head <= tail` always holds, and the tail only grows, so a
+        // later tail can never be behind the head, whereas a later head could pass an earlier tail
+        // and wrap the subtraction
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Acquire).wrapping_sub(head)
+    }
+
+    /// Returns whether the buffer holds no bytes, with the same staleness as `len`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a byte to the back of the buffer. Returns whether there was room for it
+    ///
+    /// # Safety
+    ///
+    /// No other call to `push` on this buffer may run concurrently
+    #[inline]
+    pub unsafe fn push(&self, byte: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        // Acquiring the head ensures that the consumer is done reading the slot about to be reused
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == N {
+            return false;
+        }
+        // SAFETY: The slot is outside the consumer's readable range until `tail` is released, and
+        // the caller promises that no other producer is writing it
+        unsafe { *self.buf[tail % N].get() = byte };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Removes the byte at the front of the buffer, if any
+    ///
+    /// # Safety
+    ///
+    /// No other call to `pop` on this buffer may run concurrently
+    #[inline]
+    pub unsafe fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        // Acquiring the tail ensures that the producer's write of the slot is visible
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // SAFETY: The producer does not write this slot again until `head` is released, and the
+        // caller promises that no other consumer is reading it
+        let byte = unsafe { *self.buf[head % N].get() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+}
+
+/// Number of `u64` words needed for one bit per `u16` PID
+const PID_WORDS: usize = (1 << 16) / 64;
+
+/// A set of PIDs in use, which hands out the lowest free PID on each allocation. Allocation is
+/// amortized constant time, since the words below the lowest free PID are never rescanned
+pub struct PidBitSet {
+    /// One bit per PID, set if the PID is in use
+    words: [u64; PID_WORDS],
+    /// Index of the lowest word that may have a clear bit. Every word before it is full
+    first_free: usize,
+    /// Number of PIDs in use
+    len: usize,
+}
+
+impl PidBitSet {
+    /// Creates a set with every PID free
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            words: [0; PID_WORDS],
+            first_free: 0,
+            len: 0,
+        }
+    }
+
+    /// Marks the lowest free PID as in use, and returns it. Returns `None` if every PID is in use
+    #[inline]
+    pub fn alloc(&mut self) -> Option<u16> {
+        let (index, word) = self
+            .words
+            .iter_mut()
+            .enumerate()
+            .skip(self.first_free)
+            .find(|(_, word)| **word != u64::MAX)?;
+        let bit = word.trailing_ones();
+        *word |= 1 << bit;
+        self.first_free = index;
+        self.len += 1;
+        let pid = index * 64 + usize::try_from(bit).expect("`u32`s should fit in a `usize`");
+        Some(u16::try_from(pid).expect("There should only be a bit for each `u16`"))
+    }
+
+    /// Marks the given PID as free. Returns whether it was in use
+    #[inline]
+    pub fn free(&mut self, pid: u16) -> bool {
+        let index = usize::from(pid) / 64;
+        let mask = 1 << (pid % 64);
+        let was_used = self.words[index] & mask != 0;
+        if was_used {
+            self.words[index] &= !mask;
+            self.first_free = self.first_free.min(index);
+            self.len -= 1;
+        }
+        was_used
+    }
+
+    /// Returns whether the given PID is in use
+    #[inline]
+    #[must_use]
+    pub fn contains(&self, pid: u16) -> bool {
+        self.words[usize::from(pid) / 64] & (1 << (pid % 64)) != 0
+    }
+
+    /// Returns the number of PIDs in use
+    #[inline]
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether no PIDs are in use
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
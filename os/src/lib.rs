@@ -78,7 +78,12 @@ use core::{
     ptr::NonNull,
 };
 
+extern crate alloc;
+
+pub mod barrier;
+pub mod boot_image;
 pub mod cell;
+pub mod collections;
 // pub mod heap;
 pub mod os;
 pub mod sync;
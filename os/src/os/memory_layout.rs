@@ -0,0 +1,35 @@
+//! The split of the virtual address space between usermode and the kernel. Both halves span 25
+//! bits: usermode addresses are translated through `TTBR0_EL1` from the bottom of the address
+//! space, and kernel addresses through `TTBR1_EL1` from the top
+
+/// The highest virtual address accessible to usermode
+pub const USER_VA_MAX: usize = (1 << 25) - 1;
+
+/// The lowest virtual address belonging to the kernel
+pub const KERNEL_VA_BASE: usize = !USER_VA_MAX;
+
+/// Virtual address of a loaded program's initial stack page. The program's image must lie below
+/// this
+pub const USER_STACK_VA: u64 = 0x100_0000;
+
+/// Virtual address at which a loaded program's own translation table is mapped, in the page just
+/// above its stack
+pub const USER_TABLE_VA: u64 = USER_STACK_VA + (1 << 16);
+
+/// The lowest virtual address that the loader leaves unused in a loaded program, above its image,
+/// stack and translation table. Programs may map devices or memory from here upwards
+pub const USER_FREE_VA_BASE: u64 = USER_TABLE_VA + (1 << 16);
+
+/// Returns whether the given virtual address lies in the usermode half of the address space
+#[inline]
+#[must_use]
+pub const fn is_user_addr(va: usize) -> bool {
+    va <= USER_VA_MAX
+}
+
+/// Returns whether the given virtual address lies in the kernel half of the address space
+#[inline]
+#[must_use]
+pub const fn is_kernel_addr(va: usize) -> bool {
+    va >= KERNEL_VA_BASE
+}
@@ -1 +1,2 @@
+pub mod memory_layout;
 pub mod vm;
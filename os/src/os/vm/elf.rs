@@ -1,13 +1,16 @@
 //! ELF loading capabilities
 
-use crate::os::vm::ADDRESS_SPACE;
+use crate::os::memory_layout;
+use crate::os::vm::with_temp_map;
 use crate::println;
+use crate::sync::SpinLock;
 
 use super::AddressSpace;
+use alloc::vec::Vec;
 use bitfield_struct::bitfield;
 use core::cmp::Ordering;
 use core::mem;
-use core::ptr::NonNull;
+use core::ptr::{self, NonNull};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
@@ -72,6 +75,7 @@ enum Isa {
 #[derive(Debug, FromPrimitive)]
 enum ProgramHeaderType {
     Load = 1,
+    Dynamic = 2,
     Phdr = 6,
     GnuEhFrame = 0x6474_E550,
     GNUStack = 0x6474_E551,
@@ -135,6 +139,7 @@ struct ProgramHeaderFlags {
 }
 
 /// ELF Program Headers, 64 bit version
+#[derive(Clone, Copy)]
 #[repr(C)]
 struct ProgramHeader {
     /// Type of program header
@@ -164,11 +169,274 @@ pub enum ElfLoadError {
     HeaderSize,
     BitVersion,
     HeaderType,
+    ObjectFile,
     MemSz,
+    UnsupportedReloc,
+    /// A loadable segment's virtual address and file offset are at different offsets into a page,
+    /// so the segment cannot be mapped directly from the ELF image
+    SegmentAlignment,
+    /// No page could be allocated to hold a segment's zero-filled tail, or the initial stack
+    OutOfMemory,
+    /// The arguments and environment do not fit on the initial stack
+    ArgumentsTooLarge,
+}
+
+/// Virtual address at which position-independent executables are loaded
+const PIE_LOAD_BASE: u64 = 0x1_0000;
+
+/// Tag marking the end of the dynamic section
+const DT_NULL: u64 = 0;
+/// Tag holding the address of the `Elf64_Rela` relocation table
+const DT_RELA: u64 = 7;
+/// Tag holding the total size, in bytes, of the `Elf64_Rela` relocation table
+const DT_RELASZ: u64 = 8;
+/// Tag holding the size, in bytes, of a single `Elf64_Rela` entry
+const DT_RELAENT: u64 = 9;
+
+/// Size, in bytes, of an `Elf64_Rela` entry: an offset, info, and addend, each 64 bits
+const RELA_ENTRY_SIZE: u64 = 24;
+/// Relocation that performs no action
+const R_AARCH64_NONE: u64 = 0;
+/// Relocation that writes the load bias plus the addend
+const R_AARCH64_RELATIVE: u64 = 1027;
+
+/// Virtual address in the loader's own address space through which freshly allocated pages are
+/// filled in, one at a time
+const SCRATCH_VA: u64 = 0x180_0000;
+/// Serializes use of the scratch address between concurrent loads
+static SCRATCH_WINDOW: SpinLock<()> = SpinLock::new(());
+
+/// Temporarily maps the given freshly allocated page writeable at the scratch address, and runs
+/// `f` over its contents. The scratch address is unmapped and invalidated again afterwards
+fn with_scratch<R>(pa: u64, f: impl FnOnce(&mut [u8]) -> R) -> R {
+    let _window = SCRATCH_WINDOW.lock();
+    with_temp_map(SCRATCH_VA, pa, f)
+        .expect("The scratch address should only be mapped while the window is held")
+}
+
+/// Virtual address of the initial stack page in the loaded program's address space. The loader
+/// fills the page in through a scratch address, so this is never mapped in the loader itself
+const STACK_VA: u64 = memory_layout::USER_STACK_VA;
+
+/// Virtual address at which the loaded program's translation table is mapped into its own
+/// address space
+const TABLE_VA: u64 = memory_layout::USER_TABLE_VA;
+
+/// Lays out the arguments and environment on the initial stack page, which must be mapped
+/// writeable at `window` in the loader's address space, and returns the initial stack pointer.
+/// Pointers written onto the stack are relative to `STACK_VA`, where the loaded program sees it
+///
+/// From the stack pointer upwards, the stack holds `argc`, the `argv` pointers and a null
+/// pointer, the `envp` pointers and a null pointer, and then the virtual address of the program's
+/// own translation table. The NUL-terminated strings themselves are packed at the top of the page
+fn write_initial_stack(
+    args: &[&str],
+    env: &[&str],
+    window: u64,
+    page_size: u64,
+) -> Result<u64, ElfLoadError> {
+    let to_window = |va: u64| usize::try_from(window + (va - STACK_VA)).unwrap();
+    let word = mem::size_of::<u64>();
+    let string_bytes: usize = args.iter().chain(env).map(|string| string.len() + 1).sum();
+    let pointer_words = args.len() + env.len() + 4;
+    let stack_top = STACK_VA + page_size;
+    let strings_start = u64::try_from(string_bytes)
+        .ok()
+        .and_then(|bytes| stack_top.checked_sub(bytes))
+        .ok_or(ElfLoadError::ArgumentsTooLarge)?;
+    // The stack pointer must be 16 byte aligned
+    let sp = u64::try_from(pointer_words * word)
+        .ok()
+        .and_then(|bytes| strings_start.checked_sub(bytes))
+        .filter(|&sp| sp >= STACK_VA + 16)
+        .ok_or(ElfLoadError::ArgumentsTooLarge)?
+        & !0xF;
+
+    let mut slot = ptr::from_exposed_addr_mut::<u64>(to_window(sp));
+    let mut string_va = strings_start;
+    let mut push = |value: u64| {
+        // SAFETY: The pointer block was checked to fit below the strings, within the stack page
+        unsafe {
+            slot.write(value);
+            slot = slot.add(1);
+        }
+    };
+    push(u64::try_from(args.len()).unwrap());
+    for strings in [args, env] {
+        for string in strings {
+            let destination = ptr::from_exposed_addr_mut::<u8>(to_window(string_va));
+            // SAFETY: The strings were checked to fit between `strings_start` and the top of the
+            // stack page
+            unsafe {
+                ptr::copy_nonoverlapping(string.as_ptr(), destination, string.len());
+                destination.add(string.len()).write(0);
+            }
+            push(string_va);
+            string_va += u64::try_from(string.len() + 1).unwrap();
+        }
+        push(0);
+    }
+    push(TABLE_VA);
+    Ok(sp)
+}
+
+/// Translates a virtual address, as specified in the ELF, into an index into the ELF's words, using
+/// the file-backed portion of the loadable segments
+fn va_to_word_index(prog_headers: &[ProgramHeader], va: u64) -> Result<usize, ElfLoadError> {
+    #[expect(
+        clippy::as_conversions,
+        reason = "Enum discriminants are specified as `u32`s"
+    )]
+    let offset = prog_headers
+        .iter()
+        .filter(|header| header.p_type == ProgramHeaderType::Load as u32)
+        .find_map(|header| {
+            va.checked_sub(header.va)
+                .filter(|delta| *delta < header.filesz)
+                .and_then(|delta| delta.checked_add(header.offset))
+        })
+        .ok_or(ElfLoadError::UnexpectedEoF)?;
+    if offset % 8 != 0 {
+        return Err(ElfLoadError::Alignment);
+    }
+    usize::try_from(offset / 8).map_err(|_| ElfLoadError::UnexpectedEoF)
+}
+
+/// Applies the `DT_RELA` relocations listed in the dynamic section of a position-independent ELF,
+/// relative to the given load bias
+///
+/// Only `R_AARCH64_RELATIVE` relocations are supported; any other type returns
+/// `UnsupportedReloc`
+fn apply_relocations(
+    elf: &mut [u64],
+    prog_headers: &[ProgramHeader],
+    dynamic: &ProgramHeader,
+    load_bias: u64,
+) -> Result<(), ElfLoadError> {
+    let read =
+        |elf: &[u64], index: usize| elf.get(index).copied().ok_or(ElfLoadError::UnexpectedEoF);
+
+    if dynamic.offset % 8 != 0 {
+        return Err(ElfLoadError::Alignment);
+    }
+    let dynamic_start =
+        usize::try_from(dynamic.offset / 8).map_err(|_| ElfLoadError::UnexpectedEoF)?;
+    let dynamic_words =
+        usize::try_from(dynamic.filesz / 8).map_err(|_| ElfLoadError::UnexpectedEoF)?;
+
+    let mut rela = None;
+    let mut rela_size = None;
+    for index in (dynamic_start..dynamic_start.saturating_add(dynamic_words)).step_by(2) {
+        let value = read(elf, index.saturating_add(1))?;
+        match read(elf, index)? {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => rela_size = Some(value),
+            DT_RELAENT if value != RELA_ENTRY_SIZE => return Err(ElfLoadError::HeaderSize),
+            _ => {}
+        }
+    }
+
+    let (Some(rela), Some(rela_size)) = (rela, rela_size) else {
+        // Nothing to relocate
+        return Ok(());
+    };
+
+    for entry in 0..rela_size / RELA_ENTRY_SIZE {
+        let entry_va = entry
+            .checked_mul(RELA_ENTRY_SIZE)
+            .and_then(|offset| rela.checked_add(offset))
+            .ok_or(ElfLoadError::UnexpectedEoF)?;
+        let index = va_to_word_index(prog_headers, entry_va)?;
+        let target = read(elf, index)?;
+        let info = read(elf, index.saturating_add(1))?;
+        let addend = read(elf, index.saturating_add(2))?;
+
+        match info & 0xFFFF_FFFF {
+            R_AARCH64_NONE => {}
+            R_AARCH64_RELATIVE => {
+                let target_index = va_to_word_index(prog_headers, target)?;
+                let slot = elf
+                    .get_mut(target_index)
+                    .ok_or(ElfLoadError::UnexpectedEoF)?;
+                // The addend is signed, but two's complement wrapping addition is equivalent
+                *slot = load_bias.wrapping_add(addend);
+            }
+            _ => return Err(ElfLoadError::UnsupportedReloc),
+        }
+    }
+    Ok(())
+}
+
+/// Backs the pages of a loadable segment past the last full page of file data with freshly
+/// allocated pages, which the kernel hands out already zeroed. Only the first of these pages can
+/// share a page with the file data, and just that data is copied into it, so that the BSS reads as
+/// zeroes without clobbering whatever follows the segment in the ELF image
+fn map_zero_filled_tail<const PAGE_BITS: u8, const ADDRESS_BITS: u8>(
+    address_space: &mut AddressSpace<PAGE_BITS, ADDRESS_BITS>,
+    elf: &[u64],
+    header: &ProgramHeader,
+    load_bias: u64,
+) -> Result<(), ElfLoadError>
+where
+    [(); 1 << (ADDRESS_BITS - PAGE_BITS)]: Sized,
+{
+    let page_size = 1_u64 << PAGE_BITS;
+    let page_mask = page_size - 1;
+    let va = header
+        .va
+        .checked_add(load_bias)
+        .ok_or(ElfLoadError::UnexpectedEoF)?;
+    let file_end = va
+        .checked_add(header.filesz)
+        .ok_or(ElfLoadError::UnexpectedEoF)?;
+    let memory_end = va
+        .checked_add(header.memsz)
+        .and_then(|end| end.checked_add(page_mask))
+        .ok_or(ElfLoadError::UnexpectedEoF)?
+        & !page_mask;
+    // SAFETY: Any initialized `u64`s are also valid as bytes
+    let (_, elf_bytes, _) = unsafe { elf.align_to::<u8>() };
+
+    for page in ((file_end & !page_mask)..memory_end).step_by(usize::try_from(page_size).unwrap()) {
+        let pa = alloc_page().ok_or(ElfLoadError::OutOfMemory)?;
+        if let Some(file_bytes) = file_end.checked_sub(page).filter(|&bytes| bytes != 0) {
+            let start = usize::try_from((header.offset & !page_mask) + (page - (va & !page_mask)))
+                .map_err(|_| ElfLoadError::UnexpectedEoF)?;
+            let source = elf_bytes
+                .get(start..start + usize::try_from(file_bytes).unwrap())
+                .ok_or(ElfLoadError::UnexpectedEoF)?;
+            with_scratch(pa, |contents| {
+                // SAFETY: `file_bytes` is less than a page, so the copy stays within the page,
+                // which is freshly allocated and cannot overlap the ELF image
+                unsafe {
+                    ptr::copy_nonoverlapping(source.as_ptr(), contents.as_mut_ptr(), source.len());
+                }
+            });
+        }
+        // SAFETY: Both addresses are page aligned
+        unsafe {
+            address_space.remap(
+                page,
+                pa,
+                page_size,
+                header.flags.writeable(),
+                header.flags.executable(),
+                false,
+            );
+        }
+    }
+    Ok(())
 }
 
 /// Loads the given ELF file into the given address space, and returns the entry point for the ELF.
 ///
+/// Position-independent executables are loaded at `PIE_LOAD_BASE`, and have their relative
+/// relocations applied in place in `elf`.
+///
+/// `args` and `env` are laid out on the initial stack as described by `write_initial_stack`, and
+/// the returned stack pointer points to `argc`
+///
 /// Returns `None` if an error occurs while loading the ELF
 #[expect(clippy::module_name_repetitions, reason = "Name is not final")]
 #[inline]
@@ -177,9 +445,10 @@ pub enum ElfLoadError {
 pub fn load_elf<const PAGE_BITS: u8, const ADDRESS_BITS: u8>(
     address_space: &mut AddressSpace<PAGE_BITS, ADDRESS_BITS>,
     table_pa: u64,
-    elf: &[u64],
+    elf: &mut [u64],
     elf_pa: u64,
-    arguments: &[&[u8]],
+    args: &[&str],
+    env: &[&str],
 ) -> Result<(u64, u64, u64, u64, usize), ElfLoadError>
 where
     [(); 1 << (ADDRESS_BITS - PAGE_BITS)]: Sized,
@@ -221,9 +490,19 @@ where
         return Err(ElfLoadError::HeaderSize);
     }
 
+    let load_bias =
+        match FromPrimitive::from_u16(header.obj_file).ok_or(ElfLoadError::ObjectFile)? {
+            ObjectFile::Executable => 0,
+            ObjectFile::Shared => PIE_LOAD_BASE,
+            ObjectFile::Unknown | ObjectFile::Relocatable | ObjectFile::Core => {
+                return Err(ElfLoadError::ObjectFile)
+            }
+        };
+
     let mut bss_start = None;
     let mut bss_end = None;
     let mut ctx_addr = None;
+    let mut dynamic = None;
 
     match FromPrimitive::from_u8(header.bit_version).ok_or(ElfLoadError::BitVersion)? {
         BitVersion::Bit32 => todo!("Implement 32-bit ELF loading"),
@@ -248,27 +527,35 @@ where
                 return Err(ElfLoadError::Alignment);
             }
 
-            let prog_headers =
+            // The headers are copied out, since relocations are applied to `elf` in place while
+            // they are still in use
+            let prog_headers: Vec<ProgramHeader> =
                 // SAFETY: we have checked above for sufficient size and proper alignment, program
-                // headers can be constructed from arbitrary bits, the memory is not mutated, and the size does not
-                // overflow
-                unsafe { NonNull::slice_from_raw_parts(prog_headers_ptr, num_headers).as_ref() };
-
-            let entry = header.entry;
-            for header in prog_headers {
+                // headers can be constructed from arbitrary bits, the memory is not mutated while
+                // the slice exists, and the size does not overflow
+                unsafe { NonNull::slice_from_raw_parts(prog_headers_ptr, num_headers).as_ref() }
+                    .to_vec();
+
+            let entry = header
+                .entry
+                .checked_add(load_bias)
+                .ok_or(ElfLoadError::UnexpectedEoF)?;
+            for header in &prog_headers {
                 // ELF files are specified to have the same offset from a page in both the file and in
                 // memory
                 if header.offset & page_mask != header.va & page_mask {
-                    return Err(ElfLoadError::Alignment);
+                    return Err(ElfLoadError::SegmentAlignment);
                 }
 
                 match FromPrimitive::from_u32(header.p_type).ok_or(ElfLoadError::HeaderType)? {
                     ProgramHeaderType::Load => {
-                        let virtual_start = header.va & !page_mask;
+                        let va = header
+                            .va
+                            .checked_add(load_bias)
+                            .ok_or(ElfLoadError::UnexpectedEoF)?;
+                        let virtual_start = va & !page_mask;
                         let virtual_backed_range = page_round_up(
-                            header
-                                .va
-                                .checked_sub(virtual_start)
+                            va.checked_sub(virtual_start)
                                 .and_then(|addr| addr.checked_add(header.filesz))
                                 .ok_or(ElfLoadError::UnexpectedEoF)?,
                             PAGE_BITS,
@@ -277,7 +564,7 @@ where
                             assert!(ctx_addr.is_none());
                             let e_as_bytes = unsafe {
                                 NonNull::slice_from_raw_parts(
-                                    NonNull::from(elf).cast::<u8>(),
+                                    NonNull::from(&*elf).cast::<u8>(),
                                     elf_len,
                                 )
                                 .as_ref()
@@ -306,12 +593,20 @@ where
                                     .checked_add(header.offset)
                                     .ok_or(ElfLoadError::UnexpectedEoF)?
                                     & !page_mask;
+                                // Only whole pages of file data are mapped straight from the ELF
+                                // image; any page that the zero-filled tail starts in is backed
+                                // separately once relocations are applied
+                                let shared_range = if header.memsz > header.filesz {
+                                    page_round_down(va + header.filesz, PAGE_BITS) - virtual_start
+                                } else {
+                                    virtual_backed_range
+                                };
                                 // SAFETY: The physical and virtual starts are properly aligned by masking
                                 unsafe {
-                                    address_space.map_range(
+                                    address_space.remap(
                                         virtual_start,
                                         physical_start,
-                                        virtual_backed_range,
+                                        shared_range,
                                         header.flags.writeable(),
                                         header.flags.executable(),
                                         false,
@@ -320,8 +615,8 @@ where
                                 if header.memsz > header.filesz {
                                     assert!(bss_start.is_none());
                                     assert!(bss_end.is_none());
-                                    bss_start = Some(header.va + header.filesz);
-                                    bss_end = Some(header.va + header.memsz);
+                                    bss_start = Some(va + header.filesz);
+                                    bss_end = Some(va + header.memsz);
                                 }
                             }
                             Ordering::Greater => {
@@ -330,6 +625,10 @@ where
                             }
                         }
                     }
+                    ProgramHeaderType::Dynamic => {
+                        assert!(dynamic.is_none());
+                        dynamic = Some(header);
+                    }
                     ProgramHeaderType::GNUStack
                     | ProgramHeaderType::GnuEhFrame
                     | ProgramHeaderType::GnuRelRO
@@ -337,34 +636,39 @@ where
                 }
             }
 
-            let stack_pg = alloc_page().unwrap();
-            let mut sp = 0x101_0000 as *mut usize;
+            if let Some(dynamic) = dynamic {
+                apply_relocations(elf, &prog_headers, dynamic, load_bias)?;
+            }
+
+            #[expect(
+                clippy::as_conversions,
+                reason = "Enum discriminants are specified as `u32`s"
+            )]
+            let load = ProgramHeaderType::Load as u32;
+            for header in prog_headers
+                .iter()
+                .filter(|header| header.p_type == load && header.memsz > header.filesz)
+            {
+                map_zero_filled_tail(address_space, elf, header, load_bias)?;
+            }
+
+            let stack_pg = alloc_page().ok_or(ElfLoadError::OutOfMemory)?;
             // TODO: use some form of mmap here!
             unsafe {
-                address_space.map_range(0x101_0000, table_pa, 1 << PAGE_BITS, true, false, false);
-                address_space.map_range(0x100_0000, stack_pg, 1 << PAGE_BITS, true, false, false);
-                ADDRESS_SPACE.get().unwrap().lock().map_range(
-                    0x100_0000,
-                    stack_pg,
-                    1 << PAGE_BITS,
-                    true,
-                    false,
-                    false,
-                );
-                sp = sp.cast::<usize>();
-                sp = unsafe { sp.sub(1) };
-                unsafe { *sp = 0 };
-
-                sp = unsafe { sp.sub(1) };
-                unsafe { *sp = 0x101_000 };
+                address_space.remap(TABLE_VA, table_pa, 1 << PAGE_BITS, true, false, false);
+                address_space.remap(STACK_VA, stack_pg, 1 << PAGE_BITS, true, false, false);
             }
+            let sp = with_scratch(stack_pg, |stack| {
+                let window = u64::try_from(stack.as_mut_ptr().expose_addr()).unwrap();
+                write_initial_stack(args, env, window, 1 << PAGE_BITS)
+            })?;
 
             Ok((
-                header.entry,
+                entry,
                 bss_start.unwrap_or(0),
                 bss_end.unwrap_or(0),
                 ctx_addr.unwrap(),
-                sp.addr(),
+                usize::try_from(sp).unwrap(),
             ))
         }
     }
@@ -1,12 +1,22 @@
+use crate::barrier;
 use crate::cell::OnceLock;
 use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
 use bitfield_struct::bitfield;
-use core::{cell::OnceCell, ptr::NonNull};
+use core::{
+    arch::asm,
+    cell::OnceCell,
+    fmt,
+    ptr::{self, NonNull},
+};
 use macros::AsBits;
 
 mod elf;
 pub use elf::load_elf;
 
+/// Virtual address at which the kernel maps the device tree, read-only, into the init program
+pub const DEVICE_TREE_ADDRESS: usize = 0x1FE_0000;
+
 #[bitfield(u64)]
 struct PageDirectoryEntry {
     valid: bool,
@@ -18,22 +28,26 @@ struct PageDirectoryEntry {
     #[bits(3)]
     res0: u8,
     ignored: u8,
+    /// Forbids EL1 from executing anything mapped through this table
     privilege_xn: bool,
+    /// Forbids EL0 from executing anything mapped through this table
     execute_never: bool,
-    el0_accessible: bool,
-    writeable: bool,
+    /// Forbids EL0 from accessing anything mapped through this table
+    el0_inaccessible: bool,
+    /// Forbids writes to anything mapped through this table
+    read_only: bool,
     _ns_table: bool,
 }
 
 impl PageDirectoryEntry {
+    /// Generates a descriptor pointing to the given next-level table. The descriptor restricts
+    /// nothing beyond kernel execution, so the final-level entries alone decide permissions
     fn valid_base(page_table_addr: usize) -> Self {
         Self::new()
             .with_valid(true)
             .with_is_pte_pointer(true)
             .with_page_table_address((page_table_addr >> 12).try_into().unwrap())
             .with_privilege_xn(true)
-            .with_execute_never(true)
-            .with_el0_accessible(true)
     }
 }
 
@@ -106,6 +120,39 @@ impl PageTableEntry {
     }
 }
 
+/// The decoded attributes of a single mapped page, mirroring the parameters of
+/// `AddressSpace::remap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageMapping {
+    /// The physical address the page is mapped to
+    pub pa: u64,
+    /// Whether the page is writeable from usermode
+    pub writeable: bool,
+    /// Whether the page is executable from usermode
+    pub executable: bool,
+    /// Whether the page is mapped as device memory
+    pub is_device: bool,
+}
+
+impl From<PageTableEntry> for PageMapping {
+    fn from(entry: PageTableEntry) -> Self {
+        Self {
+            pa: entry.pa() << 12,
+            writeable: !entry.writeable_never(),
+            executable: !entry.execute_never(),
+            is_device: matches!(entry.memory_type(), MemoryAttribute::Device),
+        }
+    }
+}
+
+/// The error returned by `AddressSpace::map_range_exclusive` when the requested region overlaps
+/// an existing mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlreadyMapped {
+    /// The virtual address of the first page that was already mapped
+    pub va: u64,
+}
+
 #[repr(transparent)]
 /// A final-level translation table, containing descriptors pointing to physical pages
 struct PageTable<const PAGE_BITS: u8, const REMAINING_BITS: u8>(
@@ -132,12 +179,27 @@ where
 {
     /// Pointer to the top-level translation table for this address space
     base_table: NonNull<PageTable<PAGE_BITS, ADDRESS_BITS>>,
+    /// Virtual addresses of the final-level tables, keyed by their index in the base table. Only
+    /// used when the address space is too large for a single table
+    next_level: BTreeMap<usize, NonNull<PageTableEntry>>,
+    /// Provides a zeroed, mapped page for each new final-level table, as its virtual and physical
+    /// addresses
+    table_allocator: Option<fn() -> Option<(NonNull<()>, u64)>>,
 }
 
 impl<const PAGE_BITS: u8, const ADDRESS_BITS: u8> AddressSpace<PAGE_BITS, ADDRESS_BITS>
 where
     [(); 1 << (ADDRESS_BITS - PAGE_BITS)]: Sized,
 {
+    /// Number of address bits resolved by each level of table, as a table fills exactly one page
+    /// with 8 byte descriptors
+    const LEVEL_BITS: u8 = PAGE_BITS - 3;
+    /// Number of address bits covered by a single final-level table
+    const FINAL_TABLE_BITS: u8 = PAGE_BITS + Self::LEVEL_BITS;
+    /// Whether the whole address space fits in the base table, in which case the base table holds
+    /// page descriptors directly. Otherwise, it holds directory entries to final-level tables
+    const IS_FLAT: bool = ADDRESS_BITS <= Self::FINAL_TABLE_BITS;
+
     /// Creates a new address space where the base table is virtually accessible by the given
     /// pointer
     ///
@@ -151,9 +213,21 @@ where
     pub const unsafe fn new(base_table: NonNull<()>) -> Self {
         Self {
             base_table: base_table.cast(),
+            next_level: BTreeMap::new(),
+            table_allocator: None,
         }
     }
 
+    /// Sets how final-level tables are allocated, which is required for address spaces too large
+    /// for a single table. The allocator returns a zeroed page that stays mapped for the lifetime
+    /// of this address space, as its virtual and physical addresses
+    #[inline]
+    #[must_use]
+    pub fn with_table_allocator(mut self, allocate: fn() -> Option<(NonNull<()>, u64)>) -> Self {
+        self.table_allocator = Some(allocate);
+        self
+    }
+
     /// A safe wrapper to extract a mutable reference to the tables
     #[must_use]
     fn table<'address, 'table>(&'address mut self) -> &'table mut PageTable<PAGE_BITS, ADDRESS_BITS>
@@ -165,6 +239,116 @@ where
         unsafe { self.base_table.as_mut() }
     }
 
+    /// Gets the final-level entry for the given virtual address, if the table containing it exists
+    fn entry(&mut self, va: u64) -> Option<&mut PageTableEntry> {
+        let va = usize::try_from(va).ok()?;
+        if Self::IS_FLAT {
+            self.table().get_mut(va)
+        } else {
+            let table = *self.next_level.get(&(va >> Self::FINAL_TABLE_BITS))?;
+            let index = (va >> PAGE_BITS) & ((1 << Self::LEVEL_BITS) - 1);
+            // SAFETY: Final-level tables fill a whole page, and stay mapped for the lifetime of
+            // this address space by the contract of the table allocator
+            Some(unsafe { table.add(index).as_mut() })
+        }
+    }
+
+    /// Gets the final-level entry for the given virtual address, first installing a final-level
+    /// table to hold it if there is none yet
+    ///
+    /// # Panics
+    ///
+    /// Panics if the virtual address exceeds the range possible for this address space, or if a
+    /// final-level table is needed but cannot be allocated
+    fn entry_or_insert(&mut self, va: u64) -> &mut PageTableEntry {
+        if !Self::IS_FLAT {
+            assert_eq!(
+                va >> ADDRESS_BITS,
+                0,
+                "Virtual address should be in range of the address space"
+            );
+            let index = usize::try_from(va >> Self::FINAL_TABLE_BITS).unwrap();
+            if !self.next_level.contains_key(&index) {
+                let (table, pa) = self
+                    .table_allocator
+                    .and_then(|allocate| allocate())
+                    .expect("Should be able to allocate a final-level table");
+                // SAFETY: When the address space is not flat, the base table holds one directory
+                // entry for each final-level table, and the index was checked to be in range
+                unsafe {
+                    self.base_table
+                        .cast::<PageDirectoryEntry>()
+                        .add(index)
+                        .write(PageDirectoryEntry::valid_base(pa.try_into().unwrap()));
+                }
+                self.next_level.insert(index, table.cast());
+            }
+        }
+        self.entry(va)
+            .expect("Virtual address should be in range of the address space")
+    }
+
+    /// Iterates over every final-level entry backed by a table, in increasing order of virtual
+    /// address, along with the virtual address that it maps
+    fn entries(&self) -> impl Iterator<Item = (u64, PageTableEntry)> + '_ {
+        // SAFETY: The conditions for the creation of this address space ensure that this is a
+        // safe operation
+        let flat = Self::IS_FLAT.then(|| (0, unsafe { self.base_table.as_ref() }.0.as_slice()));
+        let nested = self.next_level.iter().map(|(&index, &table)| {
+            (
+                u64::try_from(index).unwrap() << Self::FINAL_TABLE_BITS,
+                // SAFETY: Final-level tables fill a whole page, and stay mapped for the lifetime
+                // of this address space by the contract of the table allocator
+                unsafe { NonNull::slice_from_raw_parts(table, 1 << Self::LEVEL_BITS).as_ref() },
+            )
+        });
+        flat.into_iter().chain(nested).flat_map(|(base, entries)| {
+            entries
+                .iter()
+                .zip((base..).step_by(1 << PAGE_BITS))
+                .map(|(&entry, va)| (va, entry))
+        })
+    }
+
+    /// Maps the given virtual address range to the given physical address range, with the
+    /// specified attributes, unless any page in that region is already mapped.
+    ///
+    /// On failure, nothing is mapped and the virtual address of the first conflicting page is
+    /// returned; use `remap` to deliberately replace existing mappings.
+    ///
+    /// # Safety
+    ///
+    /// Both `va` and `pa` must be suitably aligned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the virtual address range exceeds the range possible for this address space, or
+    /// if the physical range excees the range possible for descriptors
+    #[inline]
+    pub unsafe fn map_range_exclusive(
+        &mut self,
+        va: u64,
+        pa: u64,
+        size: u64,
+        writeable: bool,
+        executable: bool,
+        is_device: bool,
+    ) -> Result<(), AlreadyMapped> {
+        for offset in (0..size).step_by(1 << PAGE_BITS) {
+            assert_eq!(
+                (va + offset) >> ADDRESS_BITS,
+                0,
+                "Virtual address should be in range of the address space"
+            );
+            if self.entry(va + offset).is_some_and(|entry| entry.valid()) {
+                return Err(AlreadyMapped { va: va + offset });
+            }
+        }
+        // SAFETY: The caller upholds the alignment requirements
+        unsafe { self.remap(va, pa, size, writeable, executable, is_device) };
+        Ok(())
+    }
+
     /// Maps the given virtual address range to the given physical address range, with the
     /// specified attributes. Overrides any existing mappings for that region.
     ///
@@ -181,7 +365,7 @@ where
     /// Panics if the virtual address range exceeds the range possible for this address space, or
     /// if the physical range excees the range possible for descriptors
     #[inline]
-    pub unsafe fn map_range(
+    pub unsafe fn remap(
         &mut self,
         va: u64,
         pa: u64,
@@ -191,10 +375,7 @@ where
         is_device: bool,
     ) {
         for offset in (0..size).step_by(1 << PAGE_BITS) {
-            *self
-                .table()
-                .get_mut((va + offset).try_into().unwrap())
-                .unwrap() = PageTableEntry::valid_base(pa + offset)
+            *self.entry_or_insert(va + offset) = PageTableEntry::valid_base(pa + offset)
                 .unwrap()
                 .with_writeable_never(!writeable)
                 .with_execute_never(!executable)
@@ -205,6 +386,149 @@ where
                 });
         }
     }
+
+    /// Removes any mappings for the given virtual address range
+    ///
+    /// This does not invalidate cached translations: TLB maintenance is only possible at EL1, and
+    /// this may run in usermode. Until they are invalidated with `invalidate_translation`, stale
+    /// translations may remain, so the range should not be mapped again or accessed
+    ///
+    /// # Safety
+    ///
+    /// `va` must be suitably aligned. No references into the unmapped range may remain
+    ///
+    /// # Panics
+    ///
+    /// Panics if the virtual address range exceeds the range possible for this address space
+    #[inline]
+    pub unsafe fn unmap_range(&mut self, va: u64, size: u64) {
+        for offset in (0..size).step_by(1 << PAGE_BITS) {
+            assert_eq!(
+                (va + offset) >> ADDRESS_BITS,
+                0,
+                "Virtual address should be in range of the address space"
+            );
+            // Pages without a final-level table are not mapped in the first place
+            if let Some(entry) = self.entry(va + offset) {
+                *entry = PageTableEntry::new();
+            }
+        }
+        barrier::dsb_ishst();
+    }
+
+    /// Returns the physical address that the given virtual address translates to, or `None` if it
+    /// is not mapped
+    #[inline]
+    pub fn translate(&mut self, va: u64) -> Option<u64> {
+        let entry = *self.entry(va)?;
+        entry
+            .valid()
+            .then(|| PageMapping::from(entry).pa | (va & ((1 << PAGE_BITS) - 1)))
+    }
+
+    /// Iterates over every page currently mapped in this address space, in increasing order of
+    /// virtual address, yielding each page's virtual address and attributes
+    #[inline]
+    pub fn iter_mappings(&mut self) -> impl Iterator<Item = (u64, PageMapping)> + '_ {
+        self.entries()
+            .filter(|(_, entry)| entry.valid())
+            .map(|(va, entry)| (va, PageMapping::from(entry)))
+    }
+
+    /// Writes one line for each page mapped in `va_start..va_end`, giving its virtual and physical
+    /// addresses and then its permissions. Those are `W` if writeable, `X` if executable, `D` if
+    /// device memory and `G` if global, each replaced with `-` when absent. Unmapped pages are
+    /// skipped
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `out` fails
+    #[inline]
+    pub fn dump(&mut self, out: &mut impl fmt::Write, va_start: u64, va_end: u64) -> fmt::Result {
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+        for (va, entry) in self
+            .entries()
+            .skip_while(|&(va, _)| va.saturating_add(1 << PAGE_BITS) <= va_start)
+            .take_while(|&(va, _)| va < va_end)
+            .filter(|(_, entry)| entry.valid())
+        {
+            let mapping = PageMapping::from(entry);
+            writeln!(
+                out,
+                "{va:#011x} -> {:#011x} {}{}{}{}",
+                mapping.pa,
+                flag(mapping.writeable, 'W'),
+                flag(mapping.executable, 'X'),
+                flag(mapping.is_device, 'D'),
+                flag(!entry.not_global(), 'G'),
+            )?;
+        }
+        Ok(())
+    }
 }
 
 pub static ADDRESS_SPACE: OnceLock<SpinLock<AddressSpace<16, 25>>> = OnceLock::new();
+
+/// Invalidates any cached translation of the given virtual address in this program's address
+/// space. Usermode cannot perform TLB maintenance itself, so this asks the kernel to do so
+#[inline]
+pub fn invalidate_translation(va: u64) {
+    // SAFETY: Invalidating a translation does not affect memory
+    unsafe {
+        asm! {
+            "svc 0x1700",
+            in("x0") va,
+            options(nostack),
+            clobber_abi("C"),
+        }
+    }
+}
+
+/// Maps the page at `pa` writeable to `va` in this program's own address space for the duration
+/// of `f`, which is given the contents of the page. The page is then unmapped and its translation
+/// invalidated, so that `va` can be reused by the next temporary mapping
+///
+/// # Errors
+///
+/// Returns `AlreadyMapped`, without calling `f`, if `va` is already mapped
+///
+/// # Panics
+///
+/// Panics if the address space has not been initialized, or if `va` is not page aligned
+#[inline]
+pub fn with_temp_map<R>(
+    va: u64,
+    pa: u64,
+    f: impl FnOnce(&mut [u8]) -> R,
+) -> Result<R, AlreadyMapped> {
+    const PAGE_SIZE: u64 = 1 << 16;
+    assert_eq!(va % PAGE_SIZE, 0, "Temporary mappings must be page aligned");
+    let address_space = ADDRESS_SPACE
+        .get()
+        .expect("The address space should be initialized");
+    // SAFETY: Both addresses are page aligned, and nothing is replaced if `va` is in use
+    unsafe {
+        address_space
+            .lock()
+            .map_range_exclusive(va, pa, PAGE_SIZE, true, false, false)?;
+    }
+    barrier::dsb_ishst();
+    barrier::isb();
+    // SAFETY: The page was just mapped writeable at `va`, and nothing else refers to it there.
+    // The lock is not held while `f` runs, so that `f` may map pages of its own
+    let result = f(unsafe {
+        core::slice::from_raw_parts_mut(
+            ptr::from_exposed_addr_mut(usize::try_from(va).unwrap()),
+            usize::try_from(PAGE_SIZE).unwrap(),
+        )
+    });
+    let mut address_space = address_space.lock();
+    // SAFETY: The slice given to `f` cannot outlive it, so the page is no longer referenced
+    unsafe { address_space.unmap_range(va, PAGE_SIZE) };
+    invalidate_translation(va);
+    debug_assert!(
+        address_space.translate(va).is_none(),
+        "The temporary mapping should be gone once the closure returns"
+    );
+    Ok(result)
+}
@@ -1,16 +1,73 @@
 use core::arch::aarch64::{__sev, __wfe};
 use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
-use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 use core::{hint, mem};
 
-// use crate::println;
+#[cfg(debug_assertions)]
+use crate::cell::OnceLock;
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicU64;
+
+/// Number of polls of a contended `SpinLock` after which a possible deadlock is reported, in debug
+/// builds
+#[cfg(debug_assertions)]
+const DEADLOCK_SPINS: u32 = 1 << 24;
+
+/// Owner value for an unheld `SpinLock`
+#[cfg(debug_assertions)]
+const NO_OWNER: u64 = u64::MAX;
+
+/// Callback to report possible deadlocks with, given the owner and waiter identifiers
+#[cfg(debug_assertions)]
+static DEADLOCK_REPORTER: OnceLock<fn(u64, u64)> = OnceLock::new();
+
+/// Whether a possible deadlock is currently being reported, so that a report that itself
+/// contends on a lock (e.g. for the console) does not recurse
+#[cfg(debug_assertions)]
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+/// Sets the callback used, in debug builds, to report a `SpinLock` that has been spun on for
+/// suspiciously long. The callback is given the identifier of the owner and of the waiter: the
+/// value of `TPIDRRO_EL0`, i.e. the PID running on that core.
+///
+/// Returns `Err` with the given callback if a callback was already set
+#[inline]
+pub fn set_deadlock_reporter(reporter: fn(u64, u64)) -> Result<(), fn(u64, u64)> {
+    #[cfg(debug_assertions)]
+    return DEADLOCK_REPORTER.set(reporter);
+    #[cfg(not(debug_assertions))]
+    {
+        // Deadlocks are only detected in debug builds
+        let _: fn(u64, u64) = reporter;
+        Ok(())
+    }
+}
+
+/// Returns the identifier of the current owner of the core, for deadlock reports
+#[cfg(debug_assertions)]
+fn owner_id() -> u64 {
+    let id;
+    // SAFETY: This only reads a system register with no other side effects
+    unsafe {
+        core::arch::asm! {
+            "mrs {}, TPIDRRO_EL0",
+            out(reg) id,
+            options(nomem, nostack, preserves_flags)
+        };
+    }
+    id
+}
 
 /// A spinlock mutex
 pub struct SpinLock<T: ?Sized> {
     /// Whether or not the spinlock is taken
     is_locked: AtomicBool,
+    /// Identifier of the holder of the lock, for deadlock reports
+    #[cfg(debug_assertions)]
+    owner: AtomicU64,
     /// The protected data
     data: UnsafeCell<T>,
 }
@@ -25,22 +82,81 @@ impl<T> SpinLock<T> {
         Self {
             data: UnsafeCell::new(data),
             is_locked: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            owner: AtomicU64::new(NO_OWNER),
         }
     }
 
+    /// Consumes the spinlock and returns the protected data. This does not touch the lock, since
+    /// owning the spinlock already rules out any other access
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Returns a mutable reference to the protected data. This does not touch the lock, since the
+    /// mutable borrow already rules out any other access
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
     /// Locks the mutex. The mutex is automatically unlocked when the returned `MutexGuard` is
     /// dropped
+    ///
+    /// In debug builds, spinning for a suspiciously long time reports a possible deadlock via the
+    /// callback given to `set_deadlock_reporter`, before continuing to spin
     #[inline]
     pub fn lock(&self) -> MutexGuard<T> {
+        #[cfg(debug_assertions)]
+        let mut spins = 0_u32;
         while self.is_locked.swap(true, Ordering::Acquire) {
             while self.is_locked.load(Ordering::Relaxed) {
+                #[cfg(debug_assertions)]
+                {
+                    spins = spins.saturating_add(1);
+                    if spins == DEADLOCK_SPINS {
+                        self.report_possible_deadlock();
+                    }
+                }
                 core::hint::spin_loop();
             }
         }
 
+        #[cfg(debug_assertions)]
+        self.owner.store(owner_id(), Ordering::Relaxed);
         MutexGuard(self, Cell::new(true))
     }
 
+    /// Attempts to lock the mutex without spinning. The mutex is automatically unlocked when the
+    /// returned `MutexGuard` is dropped
+    ///
+    /// Returns `None` if the mutex is already locked. This is suitable for contexts that cannot
+    /// wait on the holder, such as interrupt handlers that may have preempted it
+    #[inline]
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        self.is_locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| {
+                #[cfg(debug_assertions)]
+                self.owner.store(owner_id(), Ordering::Relaxed);
+                MutexGuard(self, Cell::new(true))
+            })
+    }
+
+    /// Reports that this lock has been waited on for suspiciously long
+    #[cfg(debug_assertions)]
+    fn report_possible_deadlock(&self) {
+        let Some(reporter) = DEADLOCK_REPORTER.get() else {
+            return;
+        };
+        if !REPORTING.swap(true, Ordering::Acquire) {
+            reporter(self.owner.load(Ordering::Relaxed), owner_id());
+            REPORTING.store(false, Ordering::Release);
+        }
+    }
+
     /// Unlocks the mutex
     ///
     /// # Safety
@@ -48,6 +164,8 @@ impl<T> SpinLock<T> {
     /// This must only be called by the destructor of the `MutexGuard` that locked this mutex
     #[inline]
     unsafe fn unlock(&self) {
+        #[cfg(debug_assertions)]
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
         self.is_locked.store(false, Ordering::Release);
     }
 }
@@ -68,6 +186,86 @@ impl<'locked, T> MutexGuard<'locked, T> {
         // SAFETY: pointers to `data` are nonnull
         unsafe { NonNull::new_unchecked(self.0.data.get()) }
     }
+
+    /// Narrows a guard to some part of the protected data, such as a single field. The lock stays
+    /// held until the returned guard is dropped
+    ///
+    /// # Panics
+    ///
+    /// Panics if the guard was already unlocked
+    #[inline]
+    pub fn map<U, F: FnOnce(&mut T) -> &mut U>(guard: Self, f: F) -> MappedMutexGuard<'locked, U> {
+        assert!(guard.1.get());
+        // SAFETY: Since the lock has been acquired, we have exclusive mutable access to the
+        // interior
+        let data = NonNull::from(f(unsafe { guard.get_pointer().as_mut() }));
+        let lock = guard.0;
+        // Forget the guard so that it does not unlock; the mapped guard now does so instead
+        mem::forget(guard);
+        MappedMutexGuard {
+            data,
+            lock,
+            _data: PhantomData,
+        }
+    }
+}
+
+/// A lock that a `MappedMutexGuard` can release without knowing the type of its data
+trait Unlock {
+    /// Unlocks the lock
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held, by the guard calling this
+    unsafe fn unlock(&self);
+}
+
+impl<T> Unlock for SpinLock<T> {
+    #[inline]
+    unsafe fn unlock(&self) {
+        // SAFETY: The caller promises that it holds the lock
+        unsafe { SpinLock::unlock(self) }
+    }
+}
+
+/// A guard for part of the data protected by a `SpinLock`, created by `MutexGuard::map`
+pub struct MappedMutexGuard<'locked, U> {
+    /// The part of the protected data that this guard gives access to
+    data: NonNull<U>,
+    /// The lock that was held by the original guard
+    lock: &'locked dyn Unlock,
+    /// Ties the data to the lifetime of the lock
+    _data: PhantomData<&'locked mut U>,
+}
+
+impl<'locked, U> Deref for MappedMutexGuard<'locked, U> {
+    type Target = U;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The lock is held for as long as this guard exists, so we have exclusive mutable
+        // access to the data
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<'locked, U> DerefMut for MappedMutexGuard<'locked, U> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The lock is held for as long as this guard exists, so we have exclusive mutable
+        // access to the data
+        unsafe { self.data.as_mut() }
+    }
+}
+
+impl<'locked, U> Drop for MappedMutexGuard<'locked, U> {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: The lock was taken over from the original guard, which did not release it
+        unsafe {
+            self.lock.unlock();
+        }
+    }
 }
 
 impl<'locked, T> Deref for MutexGuard<'locked, T> {
@@ -165,6 +363,39 @@ impl<T> RwLock<T> {
         WriteGuard(self)
     }
 
+    /// Attempts to lock the reader end without spinning. The lock is automatically released when
+    /// the returned `ReadGuard` is dropped
+    ///
+    /// Returns `None` if a writer holds the lock, or there are already as many readers as can be
+    /// counted
+    #[inline]
+    pub fn try_read(&self) -> Option<ReadGuard<T>> {
+        self.state
+            .fetch_update(Ordering::Relaxed, Ordering::Acquire, |state| match state {
+                Self::MAX_READERS | Self::WRITER => None,
+                state => Some(state + 1),
+            })
+            .ok()
+            .map(|_| ReadGuard(self))
+    }
+
+    /// Attempts to lock the writer end without spinning. The lock is automatically released when
+    /// the returned `WriteGuard` is dropped
+    ///
+    /// Returns `None` if any reader or writer holds the lock
+    #[inline]
+    pub fn try_write(&self) -> Option<WriteGuard<T>> {
+        self.state
+            .compare_exchange(
+                Self::UNLOCKED,
+                Self::WRITER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| WriteGuard(self))
+    }
+
     /// Unlocks the reader end of a reader-writer lock
     ///
     /// # Safety
@@ -202,6 +433,26 @@ impl<'locked, T> ReadGuard<'locked, T> {
     pub fn state(&self) -> u8 {
         self.0.state.load(Ordering::Relaxed)
     }
+
+    /// Attempts to upgrade a read guard into a write guard, without releasing the lock in between
+    ///
+    /// Returns the read guard back, still holding its read lock, if there are any other readers
+    pub fn try_upgrade(guard: Self) -> Result<WriteGuard<'locked, T>, Self> {
+        // Only the sole reader may upgrade, as no other reader can then observe the change
+        if guard
+            .0
+            .state
+            .compare_exchange(1, RwLock::<T>::WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            let rw = guard.0;
+            // Forget the guard so that it does not release the now-exclusive lock
+            mem::forget(guard);
+            Ok(WriteGuard(rw))
+        } else {
+            Err(guard)
+        }
+    }
 }
 
 impl<'locked, T> Deref for ReadGuard<'locked, T> {
@@ -278,3 +529,48 @@ impl<'locked, T> Drop for WriteGuard<'locked, T> {
         }
     }
 }
+
+/// A rendezvous point at which a fixed number of parties wait until all of them have arrived
+///
+/// The barrier resets itself once everyone has passed, so the same barrier can be waited on again
+/// for the next rendezvous
+pub struct Barrier {
+    /// Number of parties that must arrive before any may pass
+    parties: usize,
+    /// Number of parties that have arrived at the current rendezvous
+    count: AtomicUsize,
+    /// Incremented each time the barrier releases its parties, so that waiters can tell their own
+    /// rendezvous apart from the next one
+    generation: AtomicU32,
+}
+
+impl Barrier {
+    /// Creates a barrier that releases its parties once `parties` of them have arrived
+    #[inline]
+    #[must_use]
+    pub const fn new(parties: usize) -> Self {
+        Self {
+            parties,
+            count: AtomicUsize::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    /// Waits until all parties have arrived at the barrier. Everything a party did before
+    /// arriving is visible to every party once it passes
+    #[inline]
+    pub fn wait(&self) {
+        // The generation must be read before arriving, since the last arrival moves it on
+        let generation = self.generation.load(Ordering::Acquire);
+        if self.count.fetch_add(1, Ordering::AcqRel) + 1 == self.parties {
+            // No one can arrive for the next rendezvous until the generation moves on, so the
+            // count can be reset first
+            self.count.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+        } else {
+            while self.generation.load(Ordering::Acquire) == generation {
+                hint::spin_loop();
+            }
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Driver for the SD card's host controller, which follows the SD Host Controller specification.
+//! Only enough is implemented to identify a card and read single blocks from it by polling
+
+use core::{hint, ptr::NonNull, time::Duration};
+
+use user::os::syscalls;
+
+/// Size of a block on the card, in bytes
+pub const BLOCK_SIZE: usize = 512;
+/// `BLOCK_SIZE`, as the card takes it in command arguments
+const BLOCK_LENGTH: u32 = BLOCK_SIZE as u32;
+
+/// Block size and count of the next transfer
+const BLKSIZECNT: usize = 0x04;
+/// Argument of the next command
+const ARG1: usize = 0x08;
+/// Command and transfer mode. Writing this issues the command
+const CMDTM: usize = 0x0C;
+/// Bits 31:0 of the last response
+const RESP0: usize = 0x10;
+/// Data port, through which transferred blocks pass a word at a time
+const DATA: usize = 0x20;
+/// Present state of the controller
+const STATUS: usize = 0x24;
+/// Host configuration, including bus power
+const CONTROL0: usize = 0x28;
+/// Clock configuration and resets
+const CONTROL1: usize = 0x2C;
+/// Interrupt flags, cleared by writing 1 to them
+const INTERRUPT: usize = 0x30;
+/// Which interrupt flags are reported in `INTERRUPT`
+const IRPT_MASK: usize = 0x34;
+/// Which interrupt flags raise an interrupt
+const IRPT_EN: usize = 0x38;
+/// Capabilities of the controller, including its base clock
+const CAPABILITIES: usize = 0x40;
+
+/// `STATUS`: The command line is in use
+const CMD_INHIBIT: u32 = 1 << 0;
+/// `STATUS`: The data lines are in use
+const DAT_INHIBIT: u32 = 1 << 1;
+
+/// `CONTROL0`: Powers the bus at 3.3V
+const BUS_POWER_3V3: u32 = (0b111 << 9) | (1 << 8);
+
+/// `CONTROL1`: Enables the internal clock
+const CLK_INTLEN: u32 = 1 << 0;
+/// `CONTROL1`: The internal clock is stable
+const CLK_STABLE: u32 = 1 << 1;
+/// `CONTROL1`: Enables the clock to the card
+const CLK_EN: u32 = 1 << 2;
+/// `CONTROL1`: The 10-bit clock divider, split between bits 15:8 and 7:6
+const CLK_DIVIDER: u32 = 0xFFC0;
+/// `CONTROL1`: Data timeout, as a power of two of card clock cycles
+const DATA_TOUNIT: u32 = 0xF << 16;
+/// `CONTROL1`: The longest data timeout
+const DATA_TOUNIT_MAX: u32 = 0xE << 16;
+/// `CONTROL1`: Resets the whole controller
+const SRST_HC: u32 = 1 << 24;
+/// `CONTROL1`: Resets the command line
+const SRST_CMD: u32 = 1 << 25;
+/// `CONTROL1`: Resets the data lines
+const SRST_DATA: u32 = 1 << 26;
+
+/// `INTERRUPT`: A command completed
+const CMD_DONE: u32 = 1 << 0;
+/// `INTERRUPT`: A data transfer completed
+const DATA_DONE: u32 = 1 << 1;
+/// `INTERRUPT`: The data port holds a block to be read
+const READ_RDY: u32 = 1 << 5;
+/// `INTERRUPT`: Some error occurred, with the cause in the upper bits
+const ERR: u32 = 1 << 15;
+/// `INTERRUPT`: The card did not respond to a command
+const CTO_ERR: u32 = 1 << 16;
+
+/// `CMDTM`: A 136-bit response is expected
+const RESPONSE_136: u32 = 1 << 16;
+/// `CMDTM`: A 48-bit response is expected
+const RESPONSE_48: u32 = 2 << 16;
+/// `CMDTM`: A 48-bit response is expected, after which the card may signal busy
+const RESPONSE_48_BUSY: u32 = 3 << 16;
+/// `CMDTM`: The response's CRC is checked
+const CRC_CHECK: u32 = 1 << 19;
+/// `CMDTM`: The response's command index is checked
+const INDEX_CHECK: u32 = 1 << 20;
+/// `CMDTM`: The command transfers data
+const IS_DATA: u32 = 1 << 21;
+/// `CMDTM`: Data is transferred from the card to the host
+const DATA_READ: u32 = 1 << 4;
+
+/// Encodes a command index into `CMDTM`
+const fn command(index: u32) -> u32 {
+    index << 24
+}
+
+/// Resets the card to the idle state
+const GO_IDLE_STATE: u32 = command(0);
+/// Asks the card for its CID, moving it to the identification state
+const ALL_SEND_CID: u32 = command(2) | RESPONSE_136 | CRC_CHECK;
+/// Asks the card to publish a relative address, moving it to the standby state
+const SEND_RELATIVE_ADDR: u32 = command(3) | RESPONSE_48 | CRC_CHECK | INDEX_CHECK;
+/// Selects the card with the given relative address, moving it to the transfer state
+const SELECT_CARD: u32 = command(7) | RESPONSE_48_BUSY | CRC_CHECK | INDEX_CHECK;
+/// Checks that the card supports the host's voltage, which only version 2 cards understand
+const SEND_IF_COND: u32 = command(8) | RESPONSE_48 | CRC_CHECK | INDEX_CHECK;
+/// Sets the block length of standard capacity cards
+const SET_BLOCKLEN: u32 = command(16) | RESPONSE_48 | CRC_CHECK | INDEX_CHECK;
+/// Reads a single block
+const READ_SINGLE_BLOCK: u32 =
+    command(17) | RESPONSE_48 | CRC_CHECK | INDEX_CHECK | IS_DATA | DATA_READ;
+/// Sends the card's operating conditions, and starts its initialization. Application-specific
+const SD_SEND_OP_COND: u32 = command(41) | RESPONSE_48;
+/// Marks the next command as application-specific
+const APP_CMD: u32 = command(55) | RESPONSE_48 | CRC_CHECK | INDEX_CHECK;
+
+/// Argument to `SEND_IF_COND`: 2.7-3.6V, along with a check pattern that the card echoes back
+const IF_COND: u32 = 0x1AA;
+/// Operating conditions: the host supports 3.2-3.4V
+const OCR_VOLTAGE_WINDOW: u32 = 0x0030_0000;
+/// Operating conditions: the host supports high capacity cards, or the card is one
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+/// Operating conditions: the card has left the idle state
+const OCR_READY: u32 = 1 << 31;
+
+/// Card clock while identifying the card, which the specification caps at 400kHz
+const IDENTIFICATION_CLOCK_HZ: u32 = 400_000;
+/// Card clock once the card is identified, at default speed
+const TRANSFER_CLOCK_HZ: u32 = 25_000_000;
+/// Base clock to assume if the controller does not report one
+const DEFAULT_BASE_CLOCK_HZ: u32 = 100_000_000;
+
+/// How long to wait for the controller to reset, or its clock to stabilize
+const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+/// How long to wait for a command to complete
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
+/// How long to wait for each step of a data transfer
+const DATA_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long a card may take to leave the idle state, which the specification caps at 1 second
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to wait between polls of whether the card has left the idle state
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Error arising from operating the card
+#[derive(Debug)]
+pub enum EmmcError {
+    /// The controller did not finish resetting, or its clock did not stabilize, in time
+    Controller,
+    /// A command failed or timed out, with the given interrupt flags
+    Command { index: u32, flags: u32 },
+    /// A data transfer failed or timed out, with the given interrupt flags
+    Data { flags: u32 },
+    /// The card does not support the host's voltage
+    UnsupportedCard,
+    /// The card never left the idle state
+    IdleTimeout,
+    /// The block is beyond what a standard capacity card can address
+    OutOfRange,
+}
+
+/// A driver for an SD card behind the host controller
+pub struct Emmc {
+    /// The memory-mapped registers of the host controller
+    registers: NonNull<u32>,
+    /// Relative address of the card, published during initialization
+    rca: u32,
+    /// Whether the card is addressed in blocks, rather than bytes
+    high_capacity: bool,
+}
+
+impl Emmc {
+    /// Resets the host controller and brings the card into the transfer state, ready for reads
+    ///
+    /// # Safety
+    ///
+    /// `registers` must point to the mapped registers of the host controller, which must not be
+    /// accessed other than through the returned driver
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the controller or card does not respond as expected. In particular, a
+    /// card that never leaves the idle state is reported as `IdleTimeout`
+    pub unsafe fn init(registers: NonNull<u32>) -> Result<Self, EmmcError> {
+        let mut emmc = Self {
+            registers,
+            rca: 0,
+            high_capacity: false,
+        };
+        emmc.reset()?;
+        emmc.set_clock(IDENTIFICATION_CLOCK_HZ)?;
+        emmc.send_command(GO_IDLE_STATE, 0)?;
+        // Cards before version 2 do not respond at all
+        let is_version_2 = match emmc.send_command(SEND_IF_COND, IF_COND) {
+            Ok(echo) if echo & 0xFFF == IF_COND => true,
+            Ok(_) => return Err(EmmcError::UnsupportedCard),
+            Err(EmmcError::Command { flags, .. }) if flags & CTO_ERR != 0 => false,
+            Err(err) => return Err(err),
+        };
+        let ocr = emmc.wait_for_ready(if is_version_2 {
+            OCR_VOLTAGE_WINDOW | OCR_HIGH_CAPACITY
+        } else {
+            OCR_VOLTAGE_WINDOW
+        })?;
+        emmc.high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+        emmc.send_command(ALL_SEND_CID, 0)?;
+        emmc.rca = emmc.send_command(SEND_RELATIVE_ADDR, 0)? >> 16;
+        emmc.set_clock(TRANSFER_CLOCK_HZ)?;
+        emmc.send_command(SELECT_CARD, emmc.rca << 16)?;
+        if !emmc.high_capacity {
+            emmc.send_command(SET_BLOCKLEN, BLOCK_LENGTH)?;
+        }
+        Ok(emmc)
+    }
+
+    /// Reads the block at the given logical block address into `buf`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the card refuses the read, or the transfer fails
+    pub fn read_blk(&mut self, lba: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), EmmcError> {
+        let address = if self.high_capacity {
+            lba
+        } else {
+            lba.checked_mul(BLOCK_LENGTH).ok_or(EmmcError::OutOfRange)?
+        };
+        self.write(BLKSIZECNT, (1 << 16) | BLOCK_LENGTH);
+        self.send_command(READ_SINGLE_BLOCK, address)?;
+        self.wait_for_data(READ_RDY)?;
+        for word in buf.chunks_exact_mut(4) {
+            word.copy_from_slice(&self.read(DATA).to_le_bytes());
+        }
+        self.wait_for_data(DATA_DONE)
+    }
+
+    /// Reads the register at the given byte offset
+    fn read(&self, offset: usize) -> u32 {
+        // SAFETY: `init`'s caller promises that the registers are mapped, and the offsets used are
+        // all within the register block
+        unsafe { self.registers.byte_add(offset).read_volatile() }
+    }
+
+    /// Writes the register at the given byte offset
+    fn write(&mut self, offset: usize, value: u32) {
+        // SAFETY: `init`'s caller promises that the registers are mapped and exclusively owned by
+        // this driver, and the offsets used are all within the register block
+        unsafe { self.registers.byte_add(offset).write_volatile(value) }
+    }
+
+    /// Polls until `done` holds, or `timeout` elapses. Returns whether `done` held
+    fn wait_until(&self, timeout: Duration, mut done: impl FnMut(&Self) -> bool) -> bool {
+        let deadline = syscalls::uptime().0.saturating_add(timeout);
+        loop {
+            if done(self) {
+                return true;
+            }
+            if syscalls::uptime().0 >= deadline {
+                return false;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Sets the given reset bits of `CONTROL1`, and waits for the controller to clear them
+    fn reset_lines(&mut self, lines: u32) -> Result<(), EmmcError> {
+        let control1 = self.read(CONTROL1);
+        self.write(CONTROL1, control1 | lines);
+        if self.wait_until(RESET_TIMEOUT, |emmc| emmc.read(CONTROL1) & lines == 0) {
+            Ok(())
+        } else {
+            Err(EmmcError::Controller)
+        }
+    }
+
+    /// Resets the whole controller, powers the bus, and switches to polling for interrupt flags
+    fn reset(&mut self) -> Result<(), EmmcError> {
+        self.reset_lines(SRST_HC)?;
+        self.write(CONTROL0, BUS_POWER_3V3);
+        self.write(IRPT_EN, 0);
+        self.write(IRPT_MASK, u32::MAX);
+        self.write(INTERRUPT, u32::MAX);
+        Ok(())
+    }
+
+    /// Returns the frequency of the clock that the card clock is divided from
+    fn base_clock_hz(&self) -> u32 {
+        match (self.read(CAPABILITIES) >> 8) & 0xFF {
+            0 => DEFAULT_BASE_CLOCK_HZ,
+            mhz => mhz * 1_000_000,
+        }
+    }
+
+    /// Switches the card clock to at most the given frequency
+    fn set_clock(&mut self, target_hz: u32) -> Result<(), EmmcError> {
+        let control1 = self.read(CONTROL1) & !CLK_EN;
+        self.write(CONTROL1, control1);
+        // The card clock is the base clock over twice the divider, or the base clock itself if
+        // the divider is 0
+        let divider = self.base_clock_hz().div_ceil(2 * target_hz).min(0x3FF);
+        let control1 = (control1 & !(CLK_DIVIDER | DATA_TOUNIT))
+            | ((divider & 0xFF) << 8)
+            | ((divider >> 8) << 6)
+            | DATA_TOUNIT_MAX
+            | CLK_INTLEN;
+        self.write(CONTROL1, control1);
+        if !self.wait_until(RESET_TIMEOUT, |emmc| emmc.read(CONTROL1) & CLK_STABLE != 0) {
+            return Err(EmmcError::Controller);
+        }
+        self.write(CONTROL1, control1 | CLK_EN);
+        Ok(())
+    }
+
+    /// Issues a command with the given argument, and returns the low 32 bits of its response
+    fn send_command(&mut self, command: u32, argument: u32) -> Result<u32, EmmcError> {
+        let index = command >> 24;
+        let inhibit = if command & (IS_DATA | RESPONSE_48_BUSY) == 0 {
+            CMD_INHIBIT
+        } else {
+            CMD_INHIBIT | DAT_INHIBIT
+        };
+        if !self.wait_until(COMMAND_TIMEOUT, |emmc| emmc.read(STATUS) & inhibit == 0) {
+            return Err(EmmcError::Command { index, flags: 0 });
+        }
+        self.write(INTERRUPT, u32::MAX);
+        self.write(ARG1, argument);
+        self.write(CMDTM, command);
+        let completed = self.wait_until(COMMAND_TIMEOUT, |emmc| {
+            emmc.read(INTERRUPT) & (CMD_DONE | ERR) != 0
+        });
+        let flags = self.read(INTERRUPT);
+        if !completed || flags & ERR != 0 {
+            self.write(INTERRUPT, u32::MAX);
+            // The command line stays inhibited after an error until it is reset
+            self.reset_lines(SRST_CMD)?;
+            return Err(EmmcError::Command { index, flags });
+        }
+        self.write(INTERRUPT, CMD_DONE);
+        Ok(self.read(RESP0))
+    }
+
+    /// Issues an application-specific command with the given argument, and returns the low 32
+    /// bits of its response
+    fn send_app_command(&mut self, command: u32, argument: u32) -> Result<u32, EmmcError> {
+        self.send_command(APP_CMD, self.rca << 16)?;
+        self.send_command(command, argument)
+    }
+
+    /// Repeats `SD_SEND_OP_COND` until the card leaves the idle state, and returns its operating
+    /// conditions
+    fn wait_for_ready(&mut self, conditions: u32) -> Result<u32, EmmcError> {
+        let deadline = syscalls::uptime().0.saturating_add(IDLE_TIMEOUT);
+        loop {
+            let ocr = self.send_app_command(SD_SEND_OP_COND, conditions)?;
+            if ocr & OCR_READY != 0 {
+                return Ok(ocr);
+            }
+            if syscalls::uptime().0 >= deadline {
+                return Err(EmmcError::IdleTimeout);
+            }
+            syscalls::sleep(IDLE_POLL_INTERVAL);
+        }
+    }
+
+    /// Waits for the given interrupt flag of a data transfer, and clears it
+    fn wait_for_data(&mut self, flag: u32) -> Result<(), EmmcError> {
+        let raised = self.wait_until(DATA_TIMEOUT, |emmc| {
+            emmc.read(INTERRUPT) & (flag | ERR) != 0
+        });
+        let flags = self.read(INTERRUPT);
+        if !raised || flags & ERR != 0 {
+            self.write(INTERRUPT, u32::MAX);
+            self.reset_lines(SRST_DATA)?;
+            return Err(EmmcError::Data { flags });
+        }
+        self.write(INTERRUPT, flag);
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Filesystem server. For now, this only brings up the SD card and checks its partition table
+
+#![no_std]
+#![no_main]
+#![feature(non_null_convenience)]
+#![feature(strict_provenance)]
+#![feature(lint_reasons)]
+#![warn(clippy::complexity)]
+#![deny(clippy::correctness)]
+#![warn(clippy::nursery)]
+#![warn(clippy::pedantic)]
+#![deny(clippy::perf)]
+#![warn(clippy::restriction)]
+#![warn(clippy::style)]
+#![deny(clippy::suspicious)]
+#![deny(unsafe_op_in_unsafe_fn)]
+#![expect(
+    clippy::blanket_clippy_restriction_lints,
+    reason = "This is intentionally enabled"
+)]
+#![expect(clippy::implicit_return, reason = "This is the desired format")]
+#![expect(
+    clippy::integer_division,
+    reason = "This is used with acceptable or intended rounding"
+)]
+#![expect(clippy::mod_module_files, reason = "This is the desired format")]
+#![expect(clippy::question_mark_used, reason = "This is the desired format")]
+#![expect(clippy::semicolon_inside_block, reason = "This is the desired format")]
+#![expect(
+    clippy::separated_literal_suffix,
+    reason = "This is the desired format"
+)]
+
+mod emmc;
+
+use core::ptr::{self, NonNull};
+
+use common::os::memory_layout;
+use emmc::{Emmc, BLOCK_SIZE};
+use user::{os::syscalls, println};
+
+/// Physical address of the SD card's host controller registers
+const EMMC_PA: u64 = 0x4_7E34_0000;
+/// Virtual address at which the host controller registers are mapped, clear of this program's
+/// image, stack and translation table
+const EMMC_VA: u64 = memory_layout::USER_FREE_VA_BASE;
+/// Size of the host controller's register block, rounded up to a page
+const EMMC_SIZE: u64 = 1 << 16;
+
+/// Offset of the boot signature within a master boot record
+const MBR_SIGNATURE_OFFSET: usize = 510;
+/// Boot signature that ends a valid master boot record
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+#[no_mangle]
+extern "C" fn main() {
+    // SAFETY: Nothing else in this program is placed at `EMMC_VA`
+    if let Err(err) = unsafe { syscalls::map_device(EMMC_PA, EMMC_VA, EMMC_SIZE) } {
+        println!("fs: unable to map the SD card controller: {err:?}");
+        return;
+    }
+    let registers = NonNull::new(ptr::from_exposed_addr_mut(
+        usize::try_from(EMMC_VA).expect("`u64`s should fit in a `usize`"),
+    ))
+    .expect("Mapped registers should not be null");
+    // SAFETY: The registers were just mapped, and this is their only driver
+    let mut emmc = match unsafe { Emmc::init(registers) } {
+        Ok(emmc) => emmc,
+        Err(err) => {
+            println!("fs: unable to initialize the SD card: {err:?}");
+            return;
+        }
+    };
+
+    let mut block = [0; BLOCK_SIZE];
+    match emmc.read_blk(0, &mut block) {
+        Ok(()) if block[MBR_SIGNATURE_OFFSET..] == MBR_SIGNATURE => {
+            println!("fs: found a master boot record");
+        }
+        Ok(()) => println!("fs: block 0 is not a master boot record"),
+        Err(err) => println!("fs: unable to read block 0: {err:?}"),
+    }
+}
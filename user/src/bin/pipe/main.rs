@@ -55,7 +55,7 @@
 
 use crate::{
     process::{CreateError, PROCESSES},
-    service_channel::{ReadError, Request, Response, WriteError},
+    service_channel::{ReadError, Request, Response, StatError, WriteError},
 };
 use alloc::{collections::vec_deque::Drain, sync::Arc};
 use user::{os::syscalls, println};
@@ -69,7 +69,7 @@ mod service_channel;
 extern "C" fn main() -> ! {
     // simple_signal(handle_message)
     let mut count = 0;
-    println!d("Pipin time");
+    println!("Pipin time");
     loop {
         syscalls::block();
         println!("Simple signal: {count}!");
@@ -80,23 +80,23 @@ extern "C" fn main() -> ! {
 /// Handler when a message is delivered to this process by some
 extern "C" fn handle_message(request_pid: u16) {
     let mut processes = PROCESSES.lock();
-    let Some(process) = processes.get_mut(request_pid) else {
-        println!("Unknown PID {request_pid}");
-        return;
-    };
-    while let Some(message) = process.channel.incoming.read_message() {
+    loop {
+        let Some(process) = processes.get_mut(request_pid) else {
+            println!("Unknown PID {request_pid}");
+            return;
+        };
+        let Some(message) = process.read_request() else {
+            return;
+        };
         println!("message received!");
         let response: Response<Drain<u8>> = match message {
             Request::Read(pipe_id, count) => match process.get_read(pipe_id) {
                 Some(pipe) => {
                     let pipe = Arc::clone(pipe);
                     let mut pipe = pipe.lock();
-                    let bytes = pipe.read(count);
+                    let bytes = pipe.read(request_pid, count);
                     drop(message);
-                    process
-                        .channel
-                        .outgoing
-                        .write_message(Response::Read(bytes));
+                    process.respond(Response::Read(bytes));
                     continue;
                 }
                 None => Response::ReadFailure(ReadError::NoSuchPipe),
@@ -106,25 +106,60 @@ extern "C" fn handle_message(request_pid: u16) {
                 |pipe| {
                     let pipe = Arc::clone(pipe);
                     let mut pipe = pipe.lock();
-                    pipe.write(bytes.iter().copied());
-                    Response::Write
+                    match pipe.write(request_pid, &bytes) {
+                        0 if !bytes.is_empty() => Response::WriteFailure(WriteError::Full),
+                        accepted => Response::Write(
+                            u16::try_from(accepted)
+                                .expect("Writes are at most `u16::MAX` bytes long"),
+                        ),
+                    }
                 },
             ),
-            Request::Fork(target_pid) => todo!(),
+            Request::Fork(target_pid) => {
+                // Each end is shared by cloning the `Arc` to its pipe, so the pipe lives until
+                // every process holding it has dropped it
+                let child = process.fork();
+                if processes.get(target_pid).is_some() {
+                    Response::ForkFailure
+                } else {
+                    processes.set(target_pid, Some(child));
+                    Response::Fork
+                }
+            }
             Request::Create => match process.create_pipe() {
                 Ok(pid) => Response::Create(pid),
                 Err(CreateError::MaxPipeCount) => Response::CreateFailure,
                 Err(CreateError::NoMemory) => todo!(),
             },
-            Request::DropRead(pipe_id) => match process.drop_read(pipe_id) {
+            Request::DropRead(pipe_id) => match process.drop_read(request_pid, pipe_id) {
                 Ok(()) => Response::DropRead,
                 Err(err) => Response::DropReadFailure(err),
             },
-            Request::DropWrite(pipe_id) => match process.drop_write(pipe_id) {
+            Request::DropWrite(pipe_id) => match process.drop_write(request_pid, pipe_id) {
                 Ok(()) => Response::DropWrite,
                 Err(err) => Response::DropWriteFailure(err),
             },
+            // Either end of a pipe is enough to query it
+            Request::Stat(pipe_id) => process
+                .get_read(pipe_id)
+                .or_else(|| process.get_write(pipe_id))
+                .map_or(Response::StatFailure(StatError::NoSuchPipe), |pipe| {
+                    let buffered = pipe.lock().buffered();
+                    Response::Stat(
+                        u32::try_from(buffered).expect("Pipe capacity should fit in a `u32`"),
+                    )
+                }),
+            Request::Dup(pipe_id) => process
+                .dup(pipe_id)
+                .map_or_else(Response::DupFailure, Response::Dup),
+            Request::Dup2(src, dst) => match process.dup2(src, dst) {
+                Ok(()) => Response::Dup(dst),
+                Err(err) => Response::DupFailure(err),
+            },
         };
-        process.channel.outgoing.write_message(response);
+        processes
+            .get_mut(request_pid)
+            .expect("The requesting process should not have been removed")
+            .respond(response);
     }
 }
@@ -1,12 +1,31 @@
-use alloc::collections::{vec_deque::Drain, VecDeque};
-use user::println;
+use alloc::{
+    collections::{vec_deque::Drain, VecDeque},
+    vec::Vec,
+};
+use user::os::syscalls;
 
 /// Integer type representing an ID for a pipe via a message
 pub type PipeId = u16;
 
+/// Maximum number of bytes buffered in a pipe. Writes beyond this are refused until a reader
+/// drains the pipe
+pub const CAPACITY: usize = 1 << 16;
+
 /// The shared component of a pipe. Buffers data from writers until readers remove said data.
 pub struct Pipe {
     buffer: VecDeque<u8>,
+    /// PIDs of readers that found the pipe empty, to be woken once data arrives
+    waiting_readers: Vec<u16>,
+    /// PIDs of writers that found the pipe full, to be woken once space frees up
+    waiting_writers: Vec<u16>,
+}
+
+/// Unblocks, and forgets, every program in the given list. Programs that have since exited need
+/// no wakeup
+fn wake_all(waiting: &mut Vec<u16>) {
+    for pid in waiting.drain(..) {
+        let _: bool = syscalls::unblock(pid);
+    }
 }
 
 impl Pipe {
@@ -14,18 +33,58 @@ impl Pipe {
     pub const fn new() -> Self {
         Self {
             buffer: VecDeque::new(),
+            waiting_readers: Vec::new(),
+            waiting_writers: Vec::new(),
         }
     }
 
-    /// Reads up to `max_count` bytes from the pipe, or less if less are available
-    pub fn read(&mut self, max_count: usize) -> Drain<u8> {
+    /// Reads up to `max_count` bytes from the pipe, or less if less are available, on behalf of
+    /// the program `reader`. If the pipe is empty, `reader` is woken by the next write; otherwise,
+    /// `reader` no longer waits, and any writers waiting for space are woken
+    pub fn read(&mut self, reader: u16, max_count: usize) -> Drain<u8> {
         let count = max_count.min(self.buffer.len());
+        if self.buffer.is_empty() {
+            if !self.waiting_readers.contains(&reader) {
+                self.waiting_readers.push(reader);
+            }
+        } else {
+            self.waiting_readers.retain(|&pid| pid != reader);
+            if count != 0 {
+                wake_all(&mut self.waiting_writers);
+            }
+        }
         self.buffer.drain(0..count)
     }
 
-    /// Writes all the given `bytes` into the pipe
-    pub fn write(&mut self, bytes: impl Iterator<Item = u8>) {
-        self.buffer.extend(bytes);
-        println!("buffer is now {:X?}", self.buffer);
+    /// Stops waking `reader` for data, as it has given up its read end
+    pub fn cancel_read(&mut self, reader: u16) {
+        self.waiting_readers.retain(|&pid| pid != reader);
+    }
+
+    /// Stops waking `writer` for space, as it has given up its write end
+    pub fn cancel_write(&mut self, writer: u16) {
+        self.waiting_writers.retain(|&pid| pid != writer);
+    }
+
+    /// Returns the number of bytes currently buffered in the pipe
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Writes as many of the given `bytes` into the pipe as fit, on behalf of the program
+    /// `writer`, and returns how many were accepted. If none fit, `writer` is woken by the next
+    /// read; otherwise, `writer` no longer waits, and any readers waiting for data are woken
+    pub fn write(&mut self, writer: u16, bytes: &[u8]) -> usize {
+        let accepted = bytes.len().min(CAPACITY - self.buffer.len());
+        if accepted == 0 {
+            if !bytes.is_empty() && !self.waiting_writers.contains(&writer) {
+                self.waiting_writers.push(writer);
+            }
+        } else {
+            self.waiting_writers.retain(|&pid| pid != writer);
+            self.buffer.extend(&bytes[..accepted]);
+            wake_all(&mut self.waiting_readers);
+        }
+        accepted
     }
 }
@@ -3,7 +3,7 @@ use user::{pid_map::U16Map, sync::SpinLock};
 
 use crate::{
     pipe::{Pipe, PipeId},
-    service_channel::Channel,
+    service_channel::{Channel, DupError, Request, Response},
 };
 
 pub static PROCESSES: SpinLock<U16Map<ProcessState>> = SpinLock::new(U16Map::new());
@@ -17,7 +17,8 @@ struct PipeInfo {
 
 pub struct ProcessState<'a> {
     pipes: U16Map<PipeInfo>,
-    pub channel: Channel<'a>,
+    /// The channel over which this process sends requests, once it has connected
+    channel: Option<Channel<'a>>,
 }
 
 pub enum CreateError {
@@ -34,14 +35,37 @@ impl<'a> ProcessState<'a> {
     pub const fn new_with_channel(channel: Channel<'a>) -> Self {
         Self {
             pipes: U16Map::new(),
-            channel,
+            channel: Some(channel),
         }
     }
 
     pub fn clone_with_channel(&self, channel: Channel<'a>) -> Self {
         Self {
             pipes: self.pipes.clone(),
-            channel,
+            channel: Some(channel),
+        }
+    }
+
+    /// Creates the state for a fork of this process, which holds every read and write end that
+    /// this process holds. The fork has no channel until it connects one of its own
+    pub fn fork(&self) -> Self {
+        Self {
+            pipes: self.pipes.clone(),
+            channel: None,
+        }
+    }
+
+    /// Reads the next request sent by this process, if any
+    pub fn read_request(&mut self) -> Option<Request> {
+        self.channel
+            .as_mut()
+            .and_then(|channel| channel.incoming.read_message())
+    }
+
+    /// Sends a response to this process
+    pub fn respond<T: ExactSizeIterator + Iterator<Item = u8>>(&mut self, response: Response<T>) {
+        if let Some(channel) = self.channel.as_mut() {
+            channel.outgoing.write_message(response);
         }
     }
 
@@ -76,11 +100,13 @@ impl<'a> ProcessState<'a> {
             })
     }
 
-    /// Drops read permissions from a pipe. Drops the pipe if it is no longer accessible
-    pub fn drop_read(&mut self, pipe_id: PipeId) -> Result<(), DropError> {
+    /// Drops read permissions from a pipe on behalf of the program `pid`, which is no longer
+    /// woken for data. Drops the pipe if it is no longer accessible
+    pub fn drop_read(&mut self, pid: u16, pipe_id: PipeId) -> Result<(), DropError> {
         let pipe = self.pipes.get_mut(pipe_id).ok_or(DropError::NoSuchPipe)?;
         if pipe.readable {
             pipe.readable = false;
+            pipe.pipe.lock().cancel_read(pid);
             if !pipe.writable {
                 self.pipes.set(pipe_id, None).unwrap();
             }
@@ -90,11 +116,13 @@ impl<'a> ProcessState<'a> {
         }
     }
 
-    /// Drops write permissions from a pipe. Drops the pipe if it is no longer accessible
-    pub fn drop_write(&mut self, pipe_id: PipeId) -> Result<(), DropError> {
+    /// Drops write permissions from a pipe on behalf of the program `pid`, which is no longer
+    /// woken for space. Drops the pipe if it is no longer accessible
+    pub fn drop_write(&mut self, pid: u16, pipe_id: PipeId) -> Result<(), DropError> {
         let pipe = self.pipes.get_mut(pipe_id).ok_or(DropError::NoSuchPipe)?;
         if pipe.writable {
             pipe.writable = false;
+            pipe.pipe.lock().cancel_write(pid);
             if !pipe.readable {
                 self.pipes.set(pipe_id, None).unwrap();
             }
@@ -103,4 +131,19 @@ impl<'a> ProcessState<'a> {
             Err(DropError::NoPermissions)
         }
     }
+
+    /// Makes a new pipe ID that refers to the same pipe, with the same permissions, as `pipe_id`.
+    /// The pipe is shared, so it stays alive until every ID referring to it is dropped
+    pub fn dup(&mut self, pipe_id: PipeId) -> Result<PipeId, DupError> {
+        let info = self.pipes.get(pipe_id).ok_or(DupError::NoSuchPipe)?.clone();
+        self.pipes.insert_lowest(info).ok_or(DupError::MaxPipeCount)
+    }
+
+    /// Makes `dst` refer to the same pipe, with the same permissions, as `src`, dropping
+    /// whatever `dst` referred to before
+    pub fn dup2(&mut self, src: PipeId, dst: PipeId) -> Result<(), DupError> {
+        let info = self.pipes.get(src).ok_or(DupError::NoSuchPipe)?.clone();
+        self.pipes.set(dst, Some(info));
+        Ok(())
+    }
 }
@@ -1,10 +1,11 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use core::{
     iter,
     sync::atomic::{AtomicU8, Ordering},
 };
-use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+use user::stdio::service::MessageKind;
+pub use user::stdio::service::{DupError, ReadError, StatError, WriteError};
 
 use crate::{pipe::PipeId, process::DropError};
 
@@ -18,20 +19,20 @@ impl Buffer {
         self.0[index % self.0.len()].load(Ordering::Relaxed)
     }
 
+    /// Reads the kind byte of a message, which is published after the rest of the message
+    fn read_kind(&self, index: usize) -> u8 {
+        self.0[index % self.0.len()].load(Ordering::Acquire)
+    }
+
     fn write_byte(&mut self, index: usize, value: u8) {
         self.0[index % self.0.len()].store(value, Ordering::Relaxed)
     }
-}
 
-#[derive(FromPrimitive)]
-pub enum MessageKind {
-    None = 0,
-    Read = 1,
-    Write = 2,
-    Fork = 3,
-    Create = 4,
-    DropRead = 5,
-    DropWrite = 6,
+    /// Writes the kind byte of a message, publishing the rest of the message written before it
+    fn write_kind(&mut self, index: usize, kind: MessageKind) {
+        #[expect(clippy::as_conversions)]
+        self.0[index % self.0.len()].store(kind as u8, Ordering::Release)
+    }
 }
 
 pub struct ReadBufferStream<'a>(&'a Buffer, usize);
@@ -68,9 +69,17 @@ impl ReadBufferStream<'_> {
 
     /// Reads a message from the incoming buffer, if any are available
     pub fn read_message(&mut self) -> Option<Request> {
-        let message_kind = self.read_byte();
+        let message_kind = self.0.read_kind(self.1);
+        self.1 = self.1.wrapping_add(1);
         match FromPrimitive::from_u8(message_kind) {
-            None | Some(MessageKind::None) => {
+            None
+            | Some(
+                MessageKind::None
+                | MessageKind::ReadFailure
+                | MessageKind::WriteFailure
+                | MessageKind::StatFailure
+                | MessageKind::DupFailure,
+            ) => {
                 self.back();
                 None
             }
@@ -98,62 +107,71 @@ impl ReadBufferStream<'_> {
                 let pipe_id = self.read_pipe_id();
                 Some(Request::DropWrite(pipe_id))
             }
+            Some(MessageKind::Stat) => {
+                let pipe_id = self.read_pipe_id();
+                Some(Request::Stat(pipe_id))
+            }
+            Some(MessageKind::Dup) => {
+                let pipe_id = self.read_pipe_id();
+                Some(Request::Dup(pipe_id))
+            }
+            Some(MessageKind::Dup2) => {
+                let src = self.read_pipe_id();
+                let dst = self.read_pipe_id();
+                Some(Request::Dup2(src, dst))
+            }
         }
     }
 }
 
 impl WriteBufferStream<'_> {
-    fn write_byte(&mut self, value: u8) {
-        self.0.write_byte(self.1, value);
-        self.1 = self.1.wrapping_add(1);
-    }
-
-    fn write_bytes(&mut self, value: impl Iterator<Item = u8>) {
-        for byte in value {
-            self.write_byte(byte);
-        }
-    }
-
-    /// Moves the pointer back one, e.g. to undo a read of a bad value
-    fn back(&mut self) {
-        self.1 = self.1.wrapping_sub(1);
-    }
-
-    /// Writes a message to the outgoing buffer
+    /// Writes a message to the outgoing buffer. The kind of the message is written last, after
+    /// the kind of the following message is cleared, so that the receiver never sees a partially
+    /// written message, nor a stale one left over from before the buffer wrapped
     #[expect(clippy::as_conversions)]
     pub fn write_message<T: ExactSizeIterator + Iterator<Item = u8>>(
         &mut self,
         response: Response<T>,
     ) {
-        match response {
-            Response::Read(bytes) => {
-                self.write_byte(MessageKind::Read as u8);
-                self.write_bytes(
-                    u16::try_from(bytes.size_hint().0)
-                        .expect("Number of written bits should be less than 2^16")
-                        .to_ne_bytes()
-                        .iter()
-                        .copied(),
-                );
-                self.write_bytes(bytes);
-            }
-            Response::ReadFailure(_) => todo!(),
-            Response::Write => self.write_byte(MessageKind::Write as u8),
-            Response::WriteFailure(_) => todo!(),
-            Response::Fork => self.write_byte(MessageKind::Fork as u8),
-            Response::ForkFailure => todo!(),
-            Response::Create(pipe_id) => {
-                self.write_byte(MessageKind::Create as u8);
-                self.write_bytes(pipe_id.to_ne_bytes().iter().copied());
-            }
+        let (kind, body): (MessageKind, Vec<u8>) = match response {
+            Response::Read(bytes) => (
+                MessageKind::Read,
+                u16::try_from(bytes.size_hint().0)
+                    .expect("Number of written bits should be less than 2^16")
+                    .to_ne_bytes()
+                    .into_iter()
+                    .chain(bytes)
+                    .collect(),
+            ),
+            Response::ReadFailure(err) => (MessageKind::ReadFailure, [err as u8].into()),
+            // Write responses carry the number of bytes accepted into the pipe
+            Response::Write(accepted) => (MessageKind::Write, accepted.to_ne_bytes().into()),
+            Response::WriteFailure(err) => (MessageKind::WriteFailure, [err as u8].into()),
+            // Fork responses carry whether the fork was registered, as 0 on success
+            Response::Fork => (MessageKind::Fork, [0].into()),
+            Response::ForkFailure => (MessageKind::Fork, [1].into()),
+            Response::Create(pipe_id) => (MessageKind::Create, pipe_id.to_ne_bytes().into()),
             Response::CreateFailure => todo!(),
-            Response::DropRead => self.write_byte(MessageKind::DropRead as u8),
+            Response::DropRead => (MessageKind::DropRead, Vec::new()),
             Response::DropReadFailure(_) => todo!(),
-            Response::DropWrite => self.write_byte(MessageKind::DropWrite as u8),
+            Response::DropWrite => (MessageKind::DropWrite, Vec::new()),
             Response::DropWriteFailure(_) => todo!(),
+            // Stat responses carry the number of bytes buffered, which may not fit in a `u16`
+            Response::Stat(buffered) => (MessageKind::Stat, buffered.to_ne_bytes().into()),
+            Response::StatFailure(err) => (MessageKind::StatFailure, [err as u8].into()),
+            // Both `dup` and `dup2` respond with the resulting pipe ID
+            Response::Dup(pipe_id) => (MessageKind::Dup, pipe_id.to_ne_bytes().into()),
+            Response::DupFailure(err) => (MessageKind::DupFailure, [err as u8].into()),
+        };
+        let start = self.1;
+        let next = start.wrapping_add(1).wrapping_add(body.len());
+        self.0.write_byte(next, MessageKind::None as u8);
+        for (offset, byte) in body.into_iter().enumerate() {
+            self.0
+                .write_byte(start.wrapping_add(1).wrapping_add(offset), byte);
         }
-        self.write_byte(MessageKind::None as u8);
-        self.back();
+        self.0.write_kind(start, kind);
+        self.1 = next;
     }
 }
 
@@ -170,30 +188,19 @@ pub enum Request {
     Create,
     DropRead(PipeId),
     DropWrite(PipeId),
-}
-
-#[derive(Clone, Copy)]
-#[repr(u8)]
-pub enum ReadError {
-    NoSuchPipe = 0,
-    InsufficientPermissions = 1,
-    Locked = 2,
-}
-
-#[derive(Clone, Copy)]
-#[repr(u8)]
-pub enum WriteError {
-    NoSuchPipe = 0,
-    InsufficientPermissions = 1,
-    Locked = 2,
+    Stat(PipeId),
+    Dup(PipeId),
+    /// Makes the second pipe ID refer to the pipe of the first
+    Dup2(PipeId, PipeId),
 }
 
 pub enum Response<T: ExactSizeIterator + Iterator<Item = u8>> {
     Read(T),
     ReadFailure(ReadError),
-    Write,
+    Write(u16),
     WriteFailure(WriteError),
     Fork,
+    /// The target of a fork is already registered
     ForkFailure,
     Create(u16),
     CreateFailure,
@@ -201,6 +208,10 @@ pub enum Response<T: ExactSizeIterator + Iterator<Item = u8>> {
     DropReadFailure(DropError),
     DropWrite,
     DropWriteFailure(DropError),
+    Stat(u32),
+    StatFailure(StatError),
+    Dup(PipeId),
+    DupFailure(DupError),
 }
 
 impl Drop for Channel<'_> {
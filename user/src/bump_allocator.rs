@@ -10,6 +10,8 @@ use core::{
 /// A bump allocator that grows upwards in a certain region of memory
 #[derive(Debug)]
 pub struct BumpAllocator<'allocator> {
+    /// The start of the region of memory set aside for the bump allocator
+    start: *mut u8,
     /// The current top of the bump allocator, where new allocations are serviced from
     pointer: AtomicPtr<u8>,
     /// The upper bound of the region of memory set aside for the bump allocator
@@ -22,6 +24,7 @@ impl<'allocator> BumpAllocator<'allocator> {
     /// Const-creates an empty bump allocator, which allocates no space
     pub const fn empty() -> Self {
         Self {
+            start: ptr::null_mut(),
             pointer: AtomicPtr::new(ptr::null_mut()),
             bound: ptr::null_mut(),
             _marker: PhantomData {},
@@ -38,11 +41,28 @@ impl<'allocator> BumpAllocator<'allocator> {
     {
         let memory = memory.as_mut_ptr_range();
         *self = Self {
+            start: memory.start,
             pointer: AtomicPtr::new(memory.start),
             bound: memory.end,
             _marker: PhantomData {},
         }
     }
+
+    /// Returns the number of bytes handed out since the allocator was set or last reset,
+    /// including any padding for alignment
+    pub fn bytes_used(&self) -> usize {
+        self.pointer
+            .load(Ordering::Relaxed)
+            .addr()
+            .wrapping_sub(self.start.addr())
+    }
+
+    /// Reclaims the whole region, so that the next allocation is serviced from its start
+    /// # Safety
+    /// * All allocations from this allocator are invalidated, and must not be used afterwards.
+    pub unsafe fn reset(&mut self) {
+        *self.pointer.get_mut() = self.start;
+    }
 }
 
 /// Aligns the given pointer to the provided alignment by rounding up if necsesary
@@ -1,10 +1,21 @@
 use core::cell::SyncUnsafeCell;
-use core::mem::MaybeUninit;
+use core::hint;
+use core::mem::{self, MaybeUninit};
 use core::ops::Deref;
 use core::ptr::NonNull;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
 
+/// Releases a claim on initializing a `OnceLock` when dropped, so that an initializer that panics
+/// does not leave the cell stuck mid-initialization
+struct SettingGuard<'cell>(&'cell AtomicBool);
+
+impl Drop for SettingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
 /// A cell that may only be initialized once and exactly once
 pub struct OnceLock<T> {
     /// The actual contents of the `OnceLock`
@@ -16,7 +27,7 @@ pub struct OnceLock<T> {
 }
 
 impl<T> OnceLock<T> {
-    /// Creates a new, empty `InitCell`
+    /// Creates a new, empty `OnceLock`
     #[inline]
     pub const fn new() -> Self {
         Self {
@@ -26,10 +37,11 @@ impl<T> OnceLock<T> {
         }
     }
 
-    /// Sets the `InitCell` to the given value, if the value is not already set or being set
+    /// Sets the `OnceLock` to the given value, if the value is not already set or being set
     ///
     /// Returns whether or not the setting operation was successful. Fails if already set or there
-    /// are concurrent setters
+    /// are concurrent setters: of any number of racing calls, exactly one claims `is_setting` and
+    /// succeeds, and the rest return their value untouched
     #[inline]
     pub fn set(&self, value: T) -> Result<(), T> {
         if self.is_setting.swap(true, Ordering::Relaxed) {
@@ -77,4 +89,45 @@ impl<T> OnceLock<T> {
             None
         }
     }
+
+    /// Gets the reference to the underlying value, first initializing it with `f` if the cell is
+    /// empty.
+    ///
+    /// Of any number of racing calls, exactly one runs its `f`, and the rest spin until that value
+    /// is stored. If `f` panics, the cell is left empty so that a later call may initialize it
+    #[inline]
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        while self.is_setting.swap(true, Ordering::Acquire) {
+            // Someone else is initializing the cell, or has already done so
+            if let Some(value) = self.get() {
+                return value;
+            }
+            hint::spin_loop();
+        }
+        let guard = SettingGuard(&self.is_setting);
+        let value = f();
+        {
+            let inner_pointer = self.contents.get();
+            let inner_ref =
+            // SAFETY: Claiming `is_setting` while the cell is unset grants exclusive access
+            // to the contents
+            unsafe { inner_pointer.as_mut() }.expect("Contents should never be null addressed");
+
+            assert!(
+                matches!(inner_ref.replace(value), None),
+                "Init cell should not be already be set"
+            );
+        }
+        assert!(
+            !self.is_set.swap(true, Ordering::Release),
+            "Init cell should not already be set"
+        );
+        // Like `set`, a successful initialization keeps `is_setting` claimed forever
+        mem::forget(guard);
+        self.get()
+            .expect("Init cell should have just been initialized")
+    }
 }
@@ -1,42 +1,22 @@
 //! A heap implementation and any associated utilities
 
-use bitvec::prelude::BitArray;
-use bitvec::ptr::BitPtr;
-use bitvec::slice::{self, BitSlice};
+use bitvec::slice::BitSlice;
 
 use crate::sync::SpinLock;
 use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
 use core::{num::NonZeroUsize, ptr::NonNull};
 
+#[allow(
+    dead_code,
+    unused_imports,
+    reason = "The per-level bitmap is kept for reference, but the allocator uses a flat bitmap"
+)]
 mod bitmap;
 
-/// Computes the integer base-2 logarithm, casted to u8
-const fn ilog2_u8(n: NonZeroUsize) -> u8 {
-    // Since `NonZeroUsize::BITS <= 256`, `n.ilog2() <=
-    // NonZeroUsize::MAX.ilog2() = (2^256 - 1).ilog2() <= 255 = u8::MAX`
-    #[expect(
-        clippy::cast_possible_truncation,
-        clippy::as_conversions,
-        reason = "Const conversion from u32 to u8 is not currently possible"
-    )]
-    (n.ilog2() as u8)
-}
-
-/// Returns the greatest power of 2 less than or equal to `n`
-const fn prev_power_of_2(n: NonZeroUsize) -> NonZeroUsize {
-    // SAFETY: Since `n <= NonZeroUsize::MAX = 2^NonZeroUsize::BITS - 1`, `n.ilog2() <=
-    // NonZeroUsize::MAX.ilog2() < (2^NonZeroUsize::BITS).ilog2() = NonZeroUsize::BITS`
-    let computation = unsafe { 1_usize.unchecked_shl(n.ilog2()) };
-    // SAFETY: `computation >= 1`
-    unsafe { NonZeroUsize::new_unchecked(computation) }
-}
-
 /// A buddy allocator
 pub struct BuddyAllocator<'a> {
     /// The start of the region used by this allocator
     start: NonNull<()>,
-    /// The current size of the region used by this allocator
-    size: usize,
     /// The map storing all free blocks for this allocator, as well as the backend to expand the
     /// heap
     in_use: SpinLock<&'a mut BitSlice<u64>>,
@@ -71,32 +51,45 @@ impl<'a> BuddyAllocator<'a> {
         }
 
         let num_bits = size / Self::MIN_BLOCK_SIZE;
-        let num_u64s = num_bits.next_multiple_of(64) / 64;
+        let num_u64s = num_bits.div_ceil(64);
 
+        // The bitmap is kept at the end of the region, so the blocks that it overlaps must never be
+        // handed out
+        // SAFETY: The bitmap is at most one bit per 4096 bytes, so it fits within the region
         let metadata_ptr = unsafe { end.as_ptr().byte_sub(num_u64s * 8).cast::<u64>() };
-
-        let metadata_slice =
-            unsafe { slice::from_raw_parts_mut(BitPtr::try_from(metadata_ptr).ok()?, num_u64s) }
-                .unwrap();
-        metadata_slice.fill(true);
-        let heap = Self {
+        let free_blocks = (metadata_ptr.addr() - start.addr().get()) / Self::MIN_BLOCK_SIZE;
+
+        // SAFETY: The caller guarantees that the region, which contains the bitmap, is valid and
+        // unused, and `end` is aligned enough for `u64`s
+        let metadata = unsafe { core::slice::from_raw_parts_mut(metadata_ptr, num_u64s) };
+        let in_use = BitSlice::from_slice_mut(metadata)
+            .get_mut(..num_bits)
+            .expect("The bitmap should have a bit for every block");
+        in_use.fill(true);
+        in_use
+            .get_mut(..free_blocks)
+            .expect("Free blocks should lie below the bitmap")
+            .fill(false);
+        Some(Self {
             start,
-            size,
-            // SAFETY: The caller guarantees that this range is suitable
-            in_use: SpinLock::new(metadata_slice),
-        };
+            in_use: SpinLock::new(in_use),
+        })
+    }
 
-        for addr in (start.addr().get()..metadata_ptr.addr()).step_by(Self::MIN_BLOCK_SIZE) {
-            unsafe {
-                heap.deallocate(
-                    NonNull::new(addr as *mut u8).unwrap(),
-                    Layout::from_size_align(Self::MIN_BLOCK_SIZE, Self::MIN_BLOCK_SIZE).unwrap(),
-                );
-            }
-        }
+    /// Returns the number of smallest blocks that make up a block of the given size
+    const fn blocks_for(block_size: NonZeroUsize) -> usize {
+        // Both sizes are powers of two, so this division is exact
+        block_size.get().max(Self::MIN_BLOCK_SIZE) / Self::MIN_BLOCK_SIZE
+    }
 
-        Some(heap)
-        // None
+    /// Returns the index of the first smallest block whose address is aligned to `block_size`
+    fn first_aligned_index(&self, block_size: NonZeroUsize) -> usize {
+        let start = self.start.addr().get();
+        start
+            .checked_next_multiple_of(block_size.get())
+            .map_or(usize::MAX, |aligned| {
+                (aligned - start) / Self::MIN_BLOCK_SIZE
+            })
     }
 }
 
@@ -118,40 +111,33 @@ unsafe impl<'a> Allocator for BuddyAllocator<'a> {
             return Err(AllocError {});
         };
 
+        let blocks = Self::blocks_for(block_size);
+
         let mut in_use = self.in_use.lock();
-        // Find the first available slot of at least this size
-
-        // // let (map, backend) = &mut *heap;
-        // let mut result = map.pop(ilog2_u8(block_size));
-        // // If initial allocation fails, try to expand the heap and retry
-        // if result.is_none() {
-        //     /// The minimum size by which to grow the heap, if necessary
-        //     #[expect(clippy::unwrap_used, reason = "Const unwrap cannot panic at runtime")]
-        //     const MIN_GROW_SIZE: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
-        //     // SAFETY: The grower cannot allocate with wrapping around, so the range for the
-        //     // heap does not wrap around the address space. `size` must fit into an `isize`
-        //     // because we cannot use half of the address space, bounding us to an `isize`. This
-        //     // is considered in the same allocated object as the heap range.
-        //     let heap_end_raw = unsafe { self.start.as_ptr().byte_add(self.size) };
-        //     // SAFETY: This cannot be 0, assuming a proper backend implementation
-        //     let heap_end = unsafe { NonNull::new_unchecked(heap_end_raw) };
-        //     result = if backend.grow(
-        //         heap_end,
-        //         NonZeroUsize::new(self.size)
-        //             .map_or(MIN_GROW_SIZE, prev_power_of_2)
-        //             .max(MIN_GROW_SIZE),
-        //     ) {
-        //         // SAFETY: This region of memory was just given to use by the grower
-        //         unsafe { map.remove_buddy_or_insert_recursive(heap_end.cast(), ilog2_u8(size)) };
-        //         map.pop(ilog2_u8(block_size))
-        //     } else {
-        //         None
-        //     };
-        // }
-        // result
-        //     .map(|block| NonNull::slice_from_raw_parts(block.cast(), block_size.get()))
-        //     .ok_or(AllocError {})
-        Err(AllocError {})
+        // Find the first free run of blocks that is naturally aligned to the block size, which
+        // also satisfies the requested alignment
+        let index = (self.first_aligned_index(block_size)..in_use.len())
+            .step_by(blocks)
+            .find(|&index| {
+                index
+                    .checked_add(blocks)
+                    .and_then(|end| in_use.get(index..end))
+                    .is_some_and(BitSlice::not_any)
+            })
+            .ok_or(AllocError {})?;
+        in_use
+            .get_mut(index..index + blocks)
+            .expect("Free run should have been verified to be in bounds")
+            .fill(true);
+        drop(in_use);
+
+        let offset = index * Self::MIN_BLOCK_SIZE;
+        let block = self.start.cast::<u8>().map_addr(|start| {
+            start
+                .checked_add(offset)
+                .expect("Blocks should lie within the heap")
+        });
+        Ok(NonNull::slice_from_raw_parts(block, block_size.get()))
     }
 
     #[inline]
@@ -166,21 +152,21 @@ unsafe impl<'a> Allocator for BuddyAllocator<'a> {
             .checked_next_power_of_two()
             .expect("The size of an allocated block should not overflow");
 
-        assert!(block_size.get() == 4096);
+        let blocks = Self::blocks_for(block_size);
+        let index = (ptr.addr().get() - self.start.addr().get()) / Self::MIN_BLOCK_SIZE;
 
         let mut in_use = self.in_use.lock();
-        let mut index = self.start.map_addr(|x| x.checked_add(4096).unwrap());
-
-        // // SAFETY: The caller guarantees that the given block is appropriately allocated
-        // unsafe {
-        //     self.heap
-        //         .lock()
-        //         .0
-        //         .remove_buddy_or_insert_recursive(ptr.cast(), ilog2_u8(block_size));
-        // };
+        let run = in_use
+            .get_mut(index..index + blocks)
+            .expect("Deallocated block should be within the heap");
+        assert!(run.all(), "Deallocated block should have been allocated");
+        // Buddies are coalesced implicitly, as a block is free exactly when all of its smallest
+        // blocks are
+        run.fill(false);
     }
 }
 
+// SAFETY: This defers to the `Allocator` implementation, which upholds the same contract
 unsafe impl<'a> GlobalAlloc for BuddyAllocator<'a> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         self.allocate(layout)
@@ -189,6 +175,7 @@ unsafe impl<'a> GlobalAlloc for BuddyAllocator<'a> {
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: The caller promises that `ptr` was allocated by this allocator with `layout`
         unsafe {
             self.deallocate(
                 NonNull::new(ptr).expect("Allocated pointers were never null"),
@@ -204,11 +191,15 @@ unsafe impl<'a> GlobalAlloc for BuddyAllocator<'a> {
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // SAFETY: The caller promises that `new_size`, rounded up to `layout.align()`, does not
+        // overflow, and `layout.align()` is already a valid alignment
         let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
         let pointer = NonNull::new(ptr).expect("Allocated pointers were never null");
         if new_size < layout.size() {
+            // SAFETY: The caller promises that `ptr` was allocated by this allocator with `layout`
             unsafe { self.shrink(pointer, layout, new_layout) }
         } else {
+            // SAFETY: The caller promises that `ptr` was allocated by this allocator with `layout`
             unsafe { self.grow(pointer, layout, new_layout) }
         }
         .map(NonNull::as_mut_ptr)
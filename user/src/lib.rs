@@ -97,11 +97,13 @@ pub type Result<T> = core::result::Result<T, errno::Error>;
 pub mod bump_allocator;
 pub mod cell;
 pub mod errno;
+pub mod heap;
 pub mod os;
 pub mod pid_map;
 pub mod runtime;
 pub mod signal;
 pub mod stdio;
+pub mod stdlib;
 pub mod sync;
 pub mod sys;
 pub mod unistd;
@@ -117,23 +119,48 @@ impl Write for Stdout {
     }
 }
 
+/// Prints to the console, with a trailing newline. Output that fails to be written is dropped,
+/// so that printing never panics; use `try_println` to observe failures
 #[macro_export]
 macro_rules! println {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
-        writeln!(&mut $crate::Stdout {}, $($arg)*).unwrap();
+        let _: core::fmt::Result = writeln!(&mut $crate::Stdout {}, $($arg)*);
     }};
 }
 
+/// Prints to the console. Output that fails to be written is dropped, so that printing never
+/// panics; use `try_print` to observe failures
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
-        write!(&mut $crate::Stdout{}, $($arg)*).unwrap();
+        let _: core::fmt::Result = write!(&mut $crate::Stdout{}, $($arg)*);
+    }};
+}
+
+/// Prints to the console, with a trailing newline, returning `EIO` if the output could not be
+/// written
+#[macro_export]
+macro_rules! try_println {
+    ($($arg:tt)*) => {{
+        use core::fmt::Write;
+        writeln!(&mut $crate::Stdout {}, $($arg)*).map_err(|_err| $crate::errno::Error::EIO)
+    }};
+}
+
+/// Prints to the console, returning `EIO` if the output could not be written
+#[macro_export]
+macro_rules! try_print {
+    ($($arg:tt)*) => {{
+        use core::fmt::Write;
+        write!(&mut $crate::Stdout{}, $($arg)*).map_err(|_err| $crate::errno::Error::EIO)
     }};
 }
 
 /// PANIC HANDLER
+///
+/// Uses the non-panicking `println`, so that a failure to report the panic cannot itself panic
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     match (info.location(), info.message()) {
@@ -144,5 +171,5 @@ fn panic(info: &PanicInfo) -> ! {
             println!("thread 'main' panicked at {location}:\n{message}");
         }
     }
-    syscalls::exit()
+    syscalls::exit(101)
 }
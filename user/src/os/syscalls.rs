@@ -1,6 +1,10 @@
 use crate::runtime::exception;
 use crate::runtime::exception::{UserContext, CONTEXT};
+use common::barrier;
+use core::ffi::c_int;
+use core::ptr;
 use core::sync::atomic::Ordering;
+use core::time::Duration;
 
 /// Allocates a physical page from the kernel.
 /// Returns `Some(page)` if successful.
@@ -47,14 +51,18 @@ pub fn write(bytes: &[u8]) -> bool {
     }
 }
 
-/// Exits the current program, cleaning up all its resources
+/// Exits the current program with the given code, cleaning up all its resources. As in POSIX,
+/// only the low 8 bits of the code are kept as the exit status, which the parent can collect with
+/// `waitpid`
 #[inline]
-pub fn exit() -> ! {
+pub fn exit(code: c_int) -> ! {
+    let status = code.to_le_bytes()[0];
     loop {
         // SAFETY: This correctly invokes an `exit` syscall
         unsafe {
             core::arch::asm! {
                 "svc 0x2000",
+                in("x0") u64::from(status),
                 options(nostack, readonly),
                 clobber_abi("C"),
             }
@@ -67,6 +75,8 @@ pub enum ExecErrorKind {
     TTBR0 = 0b01,
     TCR = 0b10,
     Context = 0b11,
+    /// Another core of this program was setting its context at the same time
+    Busy = 0b100,
 }
 
 /// Error arising from an `exec` call
@@ -92,6 +102,7 @@ pub struct ExecError {
 /// * If the `context` is not properly accessible from usermode, a `Context` error is returned
 /// * If the physical page for `ttbr0` is not owned by the program, a `TTBR0` error is returned
 /// * If `tcr` sets invalid/privileged bits, a `TCR` error is returned.
+/// * If another core of this program is executing at the same time, a `Busy` error is returned
 #[inline]
 pub unsafe fn exec(
     context: *mut UserContext,
@@ -125,6 +136,7 @@ pub unsafe fn exec(
             0b01 => ExecErrorKind::TTBR0,
             0b10 => ExecErrorKind::TCR,
             0b11 => ExecErrorKind::Context,
+            0b100 => ExecErrorKind::Busy,
             _ => unreachable!("Exec syscall returned an invalid success/failure value: {status}"),
         },
         alignment_caused: status & 0b1 == 1,
@@ -206,14 +218,24 @@ pub fn block() {
     };
 }
 
+/// Sends a signal carrying no payload to the given program. Returns whether the program exists
 #[inline]
 #[must_use]
 pub fn send_signal(target_pid: u16) -> bool {
+    send_message(target_pid, 0)
+}
+
+/// Sends a signal carrying the given word to the given program, which its signal handler receives
+/// in `si_value` along with the sender's PID. Returns whether the program exists
+#[inline]
+#[must_use]
+pub fn send_message(target_pid: u16, word: u64) -> bool {
     let status: u64;
     unsafe {
         core::arch::asm! {
             "svc 0x7000",
             in("x0") target_pid,
+            in("x1") word,
             lateout("x0") status,
             options(nomem, nostack),
             clobber_abi("C"),
@@ -227,3 +249,504 @@ pub fn send_signal(target_pid: u16) -> bool {
         }
     }
 }
+
+/// Waits for the given child program to exit, and returns its exit status. Each child's status
+/// can only be collected once.
+///
+/// Returns `None` if `pid` is not a child of this program, or its status was already collected
+#[inline]
+#[must_use]
+pub fn waitpid(pid: u16) -> Option<u8> {
+    loop {
+        let status: u64;
+        let value: u64;
+        // SAFETY: This correctly invokes and specifies the outputs for a wait syscall
+        unsafe {
+            core::arch::asm! {
+                "svc 0xD000",
+                inlateout("x0") u64::from(pid) => status,
+                lateout("x1") value,
+                options(nomem, nostack),
+                clobber_abi("C"),
+            }
+        }
+        match status {
+            0 => return Some(value.to_le_bytes()[0]),
+            0b10 => return None,
+            // The child is still running; its exit unblocks this program, so wait for that. A
+            // spurious wakeup just checks again
+            0b11 => block(),
+            status => {
+                unreachable!("Wait syscall returned an invalid success/failure value: {status}")
+            }
+        }
+    }
+}
+
+/// Blocks this program until it is unblocked, or until the given timeout elapses, whichever
+/// happens first. Returns `true` if an unblock ended the wait, and `false` if it timed out
+///
+/// As with `block`, an unblock that is already pending ends the wait immediately. An unblock that
+/// arrives after the timeout is left pending for the next wait
+#[inline]
+#[must_use]
+pub fn block_timeout(timeout: Duration) -> bool {
+    let (ticks, frequency) = read_counter();
+    let deadline = ticks_to_duration(ticks, frequency).saturating_add(timeout);
+    let nanos = u64::try_from(deadline.as_nanos()).unwrap_or(u64::MAX);
+    loop {
+        let status: u64;
+        let value: u64;
+        // SAFETY: This correctly invokes and specifies the outputs for a timed block syscall
+        unsafe {
+            core::arch::asm! {
+                "svc 0x1400",
+                inlateout("x0") nanos => status,
+                lateout("x1") value,
+                options(nomem, nostack),
+                clobber_abi("C"),
+            }
+        }
+        match status {
+            0 => return value == 0,
+            // The kernel has armed the timeout, and whichever wakeup arrives first unblocks this
+            // program, after which the kernel reports which one it was
+            0b11 => block(),
+            status => {
+                unreachable!(
+                    "Timed block syscall returned an invalid success/failure value: {status}"
+                )
+            }
+        }
+    }
+}
+
+/// Makes a system call that may run other programs before returning, passing `$arg` in `x0`
+///
+/// The kernel resumes this program at the return address pushed onto the exception stack, with
+/// the stack pointer saved in `exception::SP`, rather than restoring its registers. So `x19` and
+/// the frame pointer are saved on the stack around the call, and every other register is clobbered
+macro_rules! rescheduling_syscall {
+    ($code:literal, $arg:expr) => {{
+        let ra_location = CONTEXT.exception_stack.fetch_ptr_add(1, Ordering::Relaxed);
+        // SAFETY: This correctly marks all registers as clobbered and preserves the stack pointer
+        unsafe {
+            core::arch::asm! {
+                "stp x19, x29, [sp, -16]!",
+                "sub x1, sp, 0x100",
+                "adr x2, {saved_sp}",
+                "str x1, [x2]",
+                "adr x2, 0f",
+                "str x2, [x3]",
+                concat!("svc ", $code),
+                "0: ldp x19, x29, [sp], 16",
+                inlateout("x0") $arg => _,
+                inlateout("x3") ra_location => _,
+                lateout("x1") _,
+                lateout("x2") _,
+                lateout("x4") _,
+                lateout("x5") _,
+                lateout("x6") _,
+                lateout("x7") _,
+                lateout("x8") _,
+                lateout("x9") _,
+                lateout("x10") _,
+                lateout("x11") _,
+                lateout("x12") _,
+                lateout("x13") _,
+                lateout("x14") _,
+                lateout("x15") _,
+                lateout("x16") _,
+                lateout("x17") _,
+                lateout("x18") _,
+                lateout("x20") _,
+                lateout("x21") _,
+                lateout("x22") _,
+                lateout("x23") _,
+                lateout("x24") _,
+                lateout("x25") _,
+                lateout("x26") _,
+                lateout("x27") _,
+                lateout("x28") _,
+                lateout("x30") _,
+                saved_sp = sym exception::SP,
+                clobber_abi("C"),
+            }
+        };
+    }};
+}
+
+/// Voluntarily gives up the remainder of this program's timeslice, placing it at the back of the
+/// run queue. Returns once the scheduler resumes this program
+#[inline]
+pub fn sched_yield() {
+    rescheduling_syscall!("0x9000", 0_u64);
+}
+
+/// Blocks this program for at least the given duration. A zero duration only yields the rest of
+/// this program's timeslice
+///
+/// The sleep may end early if an `unblock` is pending for, or arrives at, this program, so callers
+/// waiting for a deadline should recheck the time on return
+#[inline]
+pub fn sleep(duration: Duration) {
+    let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+    rescheduling_syscall!("0xA000", nanos);
+}
+
+/// Returns the number of free physical pages and the total number of physical pages, in that
+/// order. The free count is a snapshot, and may be stale by the time it is returned
+#[inline]
+#[must_use]
+pub fn mem_info() -> (u32, u32) {
+    let counts: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for a memory information syscall
+    unsafe {
+        core::arch::asm! {
+            "svc 0xB000",
+            out("x0") _,
+            out("x1") counts,
+            options(nomem, nostack),
+            clobber_abi("C"),
+        }
+    }
+    let [free, total] = [counts, counts >> 32]
+        .map(|half| u32::try_from(half & u64::from(u32::MAX)).expect("Masked to 32 bits"));
+    (free, total)
+}
+
+/// Returns the physical address and size, in bytes, of the device tree, so that drivers can locate
+/// their devices instead of hardcoding addresses. The page is owned read-only by init and inherited
+/// by its forks, which may map it wherever suits them
+#[inline]
+#[must_use]
+pub fn get_dtb_info() -> (u64, u16) {
+    let info: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for a device tree information syscall
+    unsafe {
+        core::arch::asm! {
+            "svc 0xC000",
+            out("x0") _,
+            out("x1") info,
+            options(nomem, nostack),
+            clobber_abi("C"),
+        }
+    }
+    (
+        info & u64::from(u32::MAX),
+        u16::try_from(info >> 32).expect("Device tree size should fit in 16 bits"),
+    )
+}
+
+/// Error arising from a `set_priority` call
+#[derive(Debug)]
+pub enum PriorityError {
+    /// The level is not one of the kernel's priority levels
+    InvalidLevel,
+    /// The level is more urgent than this program is permitted, i.e. than the priority it was
+    /// forked with
+    AboveCeiling,
+}
+
+/// Moves this program to the given scheduling priority, where lower levels run first. A program
+/// may lower its priority and later restore it, but never beyond the priority it was forked with
+///
+/// # Errors
+///
+/// Returns an error if the level does not exist or is more urgent than permitted
+#[inline]
+pub fn set_priority(level: u8) -> Result<(), PriorityError> {
+    let status: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for a priority syscall
+    unsafe {
+        core::arch::asm! {
+            "svc 0xE000",
+            inlateout("x0") u64::from(level) => status,
+            lateout("x1") _,
+            options(nomem, nostack),
+            clobber_abi("C"),
+        }
+    }
+    match status {
+        0 => Ok(()),
+        0b10 => Err(PriorityError::InvalidLevel),
+        0b11 => Err(PriorityError::AboveCeiling),
+        status => {
+            unreachable!("Priority syscall returned an invalid success/failure value: {status}")
+        }
+    }
+}
+
+/// Restricts this program to the cores set in `mask`, one bit per core ID. Forked children inherit
+/// the restriction
+///
+/// # Errors
+///
+/// Returns `EINVAL` if the mask does not include any of the machine's cores
+#[inline]
+pub fn set_affinity(mask: u8) -> crate::Result<()> {
+    let status: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for an affinity syscall
+    unsafe {
+        core::arch::asm! {
+            "svc 0x1500",
+            inlateout("x0") u64::from(mask) => status,
+            lateout("x1") _,
+            options(nomem, nostack),
+            clobber_abi("C"),
+        }
+    }
+    match status {
+        0 => Ok(()),
+        0b10 => Err(crate::errno::Error::EINVAL),
+        status => {
+            unreachable!("Affinity syscall returned an invalid success/failure value: {status}")
+        }
+    }
+}
+
+/// Error arising from a `map_device` call
+#[derive(Debug)]
+pub enum DeviceError {
+    /// The physical or virtual range does not start and end on page boundaries
+    Misaligned,
+    /// The physical range is not a peripheral this program may access; reported as `EPERM`
+    NotPermitted,
+    /// Part of the virtual range is already mapped, or outside of this program's address space
+    InvalidAddress,
+}
+
+/// Asks the kernel to map the `size` bytes of peripheral registers at physical address `pa` to
+/// the virtual address `va`, as writeable device memory. The kernel only maps known peripherals
+/// this way, and only over virtual pages that are not yet mapped
+///
+/// # Safety
+///
+/// Nothing else may rely on the virtual range staying unmapped
+///
+/// # Errors
+///
+/// Returns an error if either range is misaligned, the physical range is not a permitted
+/// peripheral, or the virtual range cannot be mapped
+#[inline]
+pub unsafe fn map_device(pa: u64, va: u64, size: u64) -> Result<(), DeviceError> {
+    let status: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for a device mapping syscall
+    unsafe {
+        core::arch::asm! {
+            "svc 0xF000",
+            inlateout("x0") pa => status,
+            inlateout("x1") va => _,
+            inlateout("x2") size => _,
+            options(nomem, nostack),
+            clobber_abi("C"),
+        }
+    }
+    match status {
+        0 => Ok(()),
+        0b10 => Err(DeviceError::Misaligned),
+        0b11 => Err(DeviceError::NotPermitted),
+        0b100 => Err(DeviceError::InvalidAddress),
+        status => {
+            unreachable!(
+                "Device mapping syscall returned an invalid success/failure value: {status}"
+            )
+        }
+    }
+}
+
+/// Fills the given buffer with pseudorandom bytes from the kernel.
+///
+/// These bytes are **not** cryptographically secure, and must only be used for hashing, seeding,
+/// and similar purposes, never for secrets
+///
+/// # Errors
+///
+/// Returns `EFAULT` if the buffer is not writeable by this program
+#[inline]
+pub fn getrandom(buf: &mut [u8]) -> crate::Result<()> {
+    let status: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for a random bytes syscall, and the
+    // kernel only writes within the buffer
+    unsafe {
+        core::arch::asm! {
+            "svc 0x1100",
+            inlateout("x0") buf.as_mut_ptr() => status,
+            inlateout("x1") buf.len() => _,
+            options(nostack),
+            clobber_abi("C"),
+        }
+    }
+    match status {
+        0 => Ok(()),
+        1 => Err(crate::errno::Error::EFAULT),
+        status => {
+            unreachable!("Random bytes syscall returned an invalid success/failure value: {status}")
+        }
+    }
+}
+
+/// Returns the PID of the current program
+#[inline]
+#[must_use]
+pub fn getpid() -> u16 {
+    let pid: u64;
+    // SAFETY: This only reads the read-only thread register, which the kernel sets to the PID
+    unsafe {
+        core::arch::asm! {
+            "mrs {}, TPIDRRO_EL0",
+            out(reg) pid,
+            options(nomem, nostack, preserves_flags)
+        }
+    }
+    u16::try_from(pid).expect("PID should fit into 16 bits")
+}
+
+/// Returns the time elapsed since the system counter started, and the time that all cores have
+/// spent idle in that period, in that order
+///
+/// Idle time is summed across cores, so it may exceed the elapsed time on a mostly idle machine.
+/// The kernel only samples a core's time when it switches between idling and running, so the idle
+/// time is coarse, but is never stale for cores that are idle at the time of the call
+///
+/// # Panics
+///
+/// Panics if the kernel cannot write the times to this thread's stack
+#[inline]
+#[must_use]
+pub fn uptime() -> (Duration, Duration) {
+    let mut ticks = [0_u64; 2];
+    let status: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for an uptime syscall, and the
+    // kernel only writes within the buffer
+    unsafe {
+        core::arch::asm! {
+            "svc 0x1200",
+            inlateout("x0") ticks.as_mut_ptr() => status,
+            out("x1") _,
+            options(nostack),
+            clobber_abi("C"),
+        }
+    }
+    assert_eq!(
+        status, 0,
+        "The uptime buffer on the stack should be writeable"
+    );
+    let [elapsed, idle] = ticks;
+    let (_, frequency) = read_counter();
+    (
+        ticks_to_duration(elapsed, frequency),
+        ticks_to_duration(idle, frequency),
+    )
+}
+
+/// Reads the system counter and its frequency, in that order
+fn read_counter() -> (u64, u64) {
+    let (ticks, frequency): (u64, u64);
+    // Keeps the counter from being read early, out of order with preceding instructions
+    barrier::isb();
+    // SAFETY: The kernel grants usermode access to the physical counter and its frequency
+    unsafe {
+        core::arch::asm! {
+            "mrs {}, CNTPCT_EL0",
+            "mrs {}, CNTFRQ_EL0",
+            out(reg) ticks,
+            out(reg) frequency,
+            options(nomem, nostack, preserves_flags),
+        }
+    }
+    (ticks, frequency)
+}
+
+/// Converts a number of system counter ticks into the time that they take to elapse
+#[inline]
+#[must_use]
+pub fn duration_from_ticks(ticks: u64) -> Duration {
+    let (_, frequency) = read_counter();
+    ticks_to_duration(ticks, frequency)
+}
+
+/// Converts a number of system counter ticks at the given frequency into a duration
+fn ticks_to_duration(ticks: u64, frequency: u64) -> Duration {
+    let nanos = u128::from(ticks) * 1_000_000_000 / u128::from(frequency.max(1));
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+/// Copies the PIDs of the live programs into `pids`, in increasing order, and returns the number
+/// of live programs. If that exceeds `pids.len()`, only the lowest PIDs are copied
+///
+/// # Errors
+///
+/// Returns `EFAULT` if the buffer is not writeable by this program
+#[inline]
+pub fn list_processes(pids: &mut [u16]) -> crate::Result<usize> {
+    let status: u64;
+    let count: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for a process list syscall, and the
+    // kernel only writes within the buffer
+    unsafe {
+        core::arch::asm! {
+            "svc 0x1300",
+            inlateout("x0") pids.as_mut_ptr() => status,
+            inlateout("x1") pids.len() => count,
+            options(nostack),
+            clobber_abi("C"),
+        }
+    }
+    match status {
+        0 => Ok(usize::try_from(count).expect("`u64`s should fit in a `usize`")),
+        1 => Err(crate::errno::Error::EFAULT),
+        status => {
+            unreachable!("Process list syscall returned an invalid success/failure value: {status}")
+        }
+    }
+}
+
+/// Permissions for memory mapped by `mmap`. Mapped memory is always readable
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Protection {
+    /// Whether the memory may be written
+    pub writeable: bool,
+    /// Whether the memory may be executed
+    pub executable: bool,
+}
+
+/// Maps `len` bytes of freshly zeroed memory at the virtual address `va`, with the given
+/// permissions, and returns a pointer to the start of it. The kernel allocates every page before
+/// mapping any, so on failure nothing is allocated or mapped
+///
+/// # Errors
+///
+/// Returns `EINVAL` if the range is empty, not page aligned, outside the address space, or
+/// overlaps an existing mapping
+///
+/// Returns `ENOMEM` if not enough pages could be allocated
+#[inline]
+pub fn mmap(va: u64, len: u64, prot: Protection) -> crate::Result<*mut u8> {
+    // The POSIX `PROT_READ`, `PROT_WRITE` and `PROT_EXEC` bits
+    let prot = 0b1 | (u64::from(prot.writeable) << 1_u8) | (u64::from(prot.executable) << 2_u8);
+    let status: u64;
+    let mapped: u64;
+    // SAFETY: This correctly invokes and specifies the outputs for an anonymous mapping syscall,
+    // which only maps memory at addresses that were unmapped
+    unsafe {
+        core::arch::asm! {
+            "svc 0x1600",
+            inlateout("x0") va => status,
+            inlateout("x1") len => mapped,
+            in("x2") prot,
+            options(nostack),
+            clobber_abi("C"),
+        }
+    }
+    match status {
+        0 => Ok(ptr::from_exposed_addr_mut(
+            usize::try_from(mapped).expect("`u64`s should fit in a `usize`"),
+        )),
+        0b10 => Err(crate::errno::Error::ENOMEM),
+        0b11 => Err(crate::errno::Error::EINVAL),
+        status => {
+            unreachable!("Mmap syscall returned an invalid success/failure value: {status}")
+        }
+    }
+}
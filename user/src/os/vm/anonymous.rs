@@ -0,0 +1,93 @@
+//! Anonymous memory that is backed by physical pages lazily, on first access, so that reserving a
+//! large region only costs memory for the pages that are actually used
+
+use alloc::vec::Vec;
+use common::barrier;
+use core::{ops::Range, ptr};
+
+use super::ADDRESS_SPACE;
+use crate::{os::syscalls, sync::SpinLock};
+
+/// Size of the pages that back anonymous regions
+const PAGE_SIZE: u64 = 1 << 16;
+
+/// Virtual address ranges reserved for anonymous memory. Pages in these ranges are unmapped until
+/// first touched
+static RESERVATIONS: SpinLock<Vec<Range<u64>>> = SpinLock::new(Vec::new());
+
+/// Error arising from reserving an anonymous region
+#[derive(Debug)]
+pub enum ReserveError {
+    /// The start or size of the region is not a multiple of the page size
+    Misaligned,
+    /// The region wraps around the end of the address space
+    Overflow,
+    /// The region overlaps an existing reservation
+    Overlapping,
+}
+
+/// Reserves the given virtual address range for zero-filled anonymous memory. No physical pages
+/// are allocated up front; instead, each page is allocated and zeroed the first time it is accessed
+///
+/// # Errors
+///
+/// Returns an error if the region is misaligned, or overlaps an existing reservation
+#[inline]
+pub fn reserve_anonymous(va: u64, size: u64) -> Result<(), ReserveError> {
+    if va % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+        return Err(ReserveError::Misaligned);
+    }
+    let region = va..va.checked_add(size).ok_or(ReserveError::Overflow)?;
+    let mut reservations = RESERVATIONS.lock();
+    if reservations
+        .iter()
+        .any(|reserved| reserved.start < region.end && region.start < reserved.end)
+    {
+        return Err(ReserveError::Overlapping);
+    }
+    reservations.push(region);
+    Ok(())
+}
+
+/// Backs the page containing the faulting address with a freshly zeroed page, if that page lies
+/// in an anonymous reservation and has not been backed yet
+///
+/// Returns whether the fault was resolved
+pub(crate) fn resolve_fault(faulting_address: u64) -> bool {
+    let page_va = faulting_address & !(PAGE_SIZE - 1);
+    if !RESERVATIONS
+        .lock()
+        .iter()
+        .any(|reserved| reserved.contains(&page_va))
+    {
+        return false;
+    }
+    let Some(mut address_space) = ADDRESS_SPACE.get().map(SpinLock::lock) else {
+        return false;
+    };
+    if address_space.is_mapped(page_va) {
+        // The page is already backed, so this fault has some other cause
+        return false;
+    }
+    let Some(pa) = syscalls::alloc_page() else {
+        return false;
+    };
+    // SAFETY: Both addresses are page aligned, and the page was just allocated to this program so
+    // nothing else refers to it
+    unsafe {
+        address_space
+            .map_range_exclusive(page_va, pa, PAGE_SIZE, true, false, false)
+            .expect("The page was checked to be unmapped while the address space was locked");
+    }
+    barrier::dsb_ishst();
+    barrier::isb();
+    // SAFETY: The page is now mapped writeable at this address, and is exclusively owned
+    unsafe {
+        ptr::write_bytes(
+            ptr::from_exposed_addr_mut::<u8>(page_va.try_into().unwrap()),
+            0,
+            PAGE_SIZE.try_into().unwrap(),
+        );
+    }
+    true
+}
@@ -280,7 +280,7 @@ where
                                     & !page_mask;
                                 // SAFETY: The physical and virtual starts are properly aligned by masking
                                 unsafe {
-                                    address_space.map_range(
+                                    address_space.remap(
                                         virtual_start,
                                         physical_start,
                                         virtual_backed_range,
@@ -1,8 +1,14 @@
+use crate::cell::OnceLock;
+use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
 use bitfield_struct::bitfield;
 use core::ptr::NonNull;
 use macros::AsBits;
 
+mod anonymous;
 mod elf;
+pub(crate) use anonymous::resolve_fault;
+pub use anonymous::{reserve_anonymous, ReserveError};
 pub use elf::load_elf;
 
 #[bitfield(u64)]
@@ -16,22 +22,26 @@ struct PageDirectoryEntry {
     #[bits(3)]
     res0: u8,
     ignored: u8,
+    /// Forbids EL1 from executing anything mapped through this table
     privilege_xn: bool,
+    /// Forbids EL0 from executing anything mapped through this table
     execute_never: bool,
-    el0_accessible: bool,
-    writeable: bool,
+    /// Forbids EL0 from accessing anything mapped through this table
+    el0_inaccessible: bool,
+    /// Forbids writes to anything mapped through this table
+    read_only: bool,
     _ns_table: bool,
 }
 
 impl PageDirectoryEntry {
+    /// Generates a descriptor pointing to the given next-level table. The descriptor restricts
+    /// nothing beyond kernel execution, so the final-level entries alone decide permissions
     fn valid_base(page_table_addr: usize) -> Self {
         Self::new()
             .with_valid(true)
             .with_is_pte_pointer(true)
             .with_page_table_address((page_table_addr >> 12).try_into().unwrap())
             .with_privilege_xn(true)
-            .with_execute_never(true)
-            .with_el0_accessible(true)
     }
 }
 
@@ -104,6 +114,14 @@ impl PageTableEntry {
     }
 }
 
+/// The error returned by `AddressSpace::map_range_exclusive` when the requested region overlaps
+/// an existing mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlreadyMapped {
+    /// The virtual address of the first page that was already mapped
+    pub va: u64,
+}
+
 #[repr(transparent)]
 /// A final-level translation table, containing descriptors pointing to physical pages
 struct PageTable<const PAGE_BITS: u8, const REMAINING_BITS: u8>(
@@ -130,12 +148,27 @@ where
 {
     /// Pointer to the top-level translation table for this address space
     base_table: NonNull<PageTable<PAGE_BITS, ADDRESS_BITS>>,
+    /// Virtual addresses of the final-level tables, keyed by their index in the base table. Only
+    /// used when the address space is too large for a single table
+    next_level: BTreeMap<usize, NonNull<PageTableEntry>>,
+    /// Provides a zeroed, mapped page for each new final-level table, as its virtual and physical
+    /// addresses
+    table_allocator: Option<fn() -> Option<(NonNull<()>, u64)>>,
 }
 
 impl<const PAGE_BITS: u8, const ADDRESS_BITS: u8> AddressSpace<PAGE_BITS, ADDRESS_BITS>
 where
     [(); 1 << (ADDRESS_BITS - PAGE_BITS)]: Sized,
 {
+    /// Number of address bits resolved by each level of table, as a table fills exactly one page
+    /// with 8 byte descriptors
+    const LEVEL_BITS: u8 = PAGE_BITS - 3;
+    /// Number of address bits covered by a single final-level table
+    const FINAL_TABLE_BITS: u8 = PAGE_BITS + Self::LEVEL_BITS;
+    /// Whether the whole address space fits in the base table, in which case the base table holds
+    /// page descriptors directly. Otherwise, it holds directory entries to final-level tables
+    const IS_FLAT: bool = ADDRESS_BITS <= Self::FINAL_TABLE_BITS;
+
     /// Creates a new address space where the base table is virtually accessible by the given
     /// pointer
     ///
@@ -149,9 +182,21 @@ where
     pub const unsafe fn new(base_table: NonNull<()>) -> Self {
         Self {
             base_table: base_table.cast(),
+            next_level: BTreeMap::new(),
+            table_allocator: None,
         }
     }
 
+    /// Sets how final-level tables are allocated, which is required for address spaces too large
+    /// for a single table. The allocator returns a zeroed page that stays mapped for the lifetime
+    /// of this address space, as its virtual and physical addresses
+    #[inline]
+    #[must_use]
+    pub fn with_table_allocator(mut self, allocate: fn() -> Option<(NonNull<()>, u64)>) -> Self {
+        self.table_allocator = Some(allocate);
+        self
+    }
+
     /// A safe wrapper to extract a mutable reference to the tables
     #[must_use]
     fn table<'address, 'table>(&'address mut self) -> &'table mut PageTable<PAGE_BITS, ADDRESS_BITS>
@@ -163,6 +208,94 @@ where
         unsafe { self.base_table.as_mut() }
     }
 
+    /// Gets the final-level entry for the given virtual address, if the table containing it exists
+    fn entry(&mut self, va: u64) -> Option<&mut PageTableEntry> {
+        let va = usize::try_from(va).ok()?;
+        if Self::IS_FLAT {
+            self.table().get_mut(va)
+        } else {
+            let table = *self.next_level.get(&(va >> Self::FINAL_TABLE_BITS))?;
+            let index = (va >> PAGE_BITS) & ((1 << Self::LEVEL_BITS) - 1);
+            // SAFETY: Final-level tables fill a whole page, and stay mapped for the lifetime of
+            // this address space by the contract of the table allocator
+            Some(unsafe { table.add(index).as_mut() })
+        }
+    }
+
+    /// Gets the final-level entry for the given virtual address, first installing a final-level
+    /// table to hold it if there is none yet
+    ///
+    /// # Panics
+    ///
+    /// Panics if the virtual address exceeds the range possible for this address space, or if a
+    /// final-level table is needed but cannot be allocated
+    fn entry_or_insert(&mut self, va: u64) -> &mut PageTableEntry {
+        if !Self::IS_FLAT {
+            assert_eq!(
+                va >> ADDRESS_BITS,
+                0,
+                "Virtual address should be in range of the address space"
+            );
+            let index = usize::try_from(va >> Self::FINAL_TABLE_BITS).unwrap();
+            if !self.next_level.contains_key(&index) {
+                let (table, pa) = self
+                    .table_allocator
+                    .and_then(|allocate| allocate())
+                    .expect("Should be able to allocate a final-level table");
+                // SAFETY: When the address space is not flat, the base table holds one directory
+                // entry for each final-level table, and the index was checked to be in range
+                unsafe {
+                    self.base_table
+                        .cast::<PageDirectoryEntry>()
+                        .add(index)
+                        .write(PageDirectoryEntry::valid_base(pa.try_into().unwrap()));
+                }
+                self.next_level.insert(index, table.cast());
+            }
+        }
+        self.entry(va)
+            .expect("Virtual address should be in range of the address space")
+    }
+
+    /// Maps the given virtual address range to the given physical address range, with the
+    /// specified attributes, unless any page in that region is already mapped.
+    ///
+    /// On failure, nothing is mapped and the virtual address of the first conflicting page is
+    /// returned; use `remap` to deliberately replace existing mappings.
+    ///
+    /// # Safety
+    ///
+    /// Both `va` and `pa` must be suitably aligned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the virtual address range exceeds the range possible for this address space, or
+    /// if the physical range excees the range possible for descriptors
+    #[inline]
+    pub unsafe fn map_range_exclusive(
+        &mut self,
+        va: u64,
+        pa: u64,
+        size: u64,
+        writeable: bool,
+        executable: bool,
+        is_device: bool,
+    ) -> Result<(), AlreadyMapped> {
+        for offset in (0..size).step_by(1 << PAGE_BITS) {
+            assert_eq!(
+                (va + offset) >> ADDRESS_BITS,
+                0,
+                "Virtual address should be in range of the address space"
+            );
+            if self.is_mapped(va + offset) {
+                return Err(AlreadyMapped { va: va + offset });
+            }
+        }
+        // SAFETY: The caller upholds the alignment requirements
+        unsafe { self.remap(va, pa, size, writeable, executable, is_device) };
+        Ok(())
+    }
+
     /// Maps the given virtual address range to the given physical address range, with the
     /// specified attributes. Overrides any existing mappings for that region.
     ///
@@ -179,7 +312,7 @@ where
     /// Panics if the virtual address range exceeds the range possible for this address space, or
     /// if the physical range excees the range possible for descriptors
     #[inline]
-    pub unsafe fn map_range(
+    pub unsafe fn remap(
         &mut self,
         va: u64,
         pa: u64,
@@ -189,10 +322,7 @@ where
         is_device: bool,
     ) {
         for offset in (0..size).step_by(1 << PAGE_BITS) {
-            *self
-                .table()
-                .get_mut((va + offset).try_into().unwrap())
-                .unwrap() = PageTableEntry::valid_base(pa + offset)
+            *self.entry_or_insert(va + offset) = PageTableEntry::valid_base(pa + offset)
                 .unwrap()
                 .with_writeable_never(!writeable)
                 .with_execute_never(!executable)
@@ -203,4 +333,20 @@ where
                 });
         }
     }
+
+    /// Returns whether the given virtual address is within the range of this address space
+    #[inline]
+    #[must_use]
+    pub const fn contains(&self, va: u64) -> bool {
+        va >> ADDRESS_BITS == 0
+    }
+
+    /// Returns whether the page containing the given virtual address is currently mapped
+    #[inline]
+    pub fn is_mapped(&mut self, va: u64) -> bool {
+        self.entry(va).is_some_and(|entry| entry.valid())
+    }
 }
+
+/// The address space of this program, once the runtime has located its translation table
+pub static ADDRESS_SPACE: OnceLock<SpinLock<AddressSpace<16, 25>>> = OnceLock::new();
@@ -20,8 +20,9 @@ impl<T> U16Map<T> {
 
     pub fn set(&mut self, pid: u16, value: Option<T>) -> Option<T> {
         let pid = usize::from(pid);
-        self.0
-            .extend(iter::repeat_with(|| None).take(pid.saturating_sub(self.0.len())));
+        self.0.extend(
+            iter::repeat_with(|| None).take(pid.saturating_add(1).saturating_sub(self.0.len())),
+        );
         mem::replace(
             self.0
                 .get_mut(pid)
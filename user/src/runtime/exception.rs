@@ -1,20 +1,12 @@
-use alloc::boxed::Box;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
-use crate::{
-    println,
-    signal::ffi::{SigInfo, SigVal},
-    sys::types::ffi::pid_t,
-};
+use crate::{os::vm, signal, sys::types::ffi::pid_t};
 
 use super::init::_start;
 use core::{
     arch,
-    ffi::{c_int, c_void},
-    hint,
-    ops::Deref,
-    ptr::{self, addr_of_mut, NonNull},
+    ptr::addr_of_mut,
     sync::atomic::{AtomicPtr, Ordering},
 };
 
@@ -201,134 +193,13 @@ struct ReturnRegs {
     x1: u64,
 }
 
-pub enum HandlerType {
-    Signal(unsafe extern "C" fn(c_int)),
-    SigAction(unsafe extern "C" fn(c_int, *mut SigInfo, *mut c_void)),
-}
-pub(crate) struct SignalInfo {
-    handler: HandlerType,
-    switch_stack: bool,
-}
-
-pub struct AtomicBox<T>(AtomicPtr<T>);
-
-struct TaggedPointer<T>(*mut T);
-
-impl<T> TaggedPointer<T> {
-    fn ptr(self) -> *mut T {
-        self.0
-    }
-
-    fn tag(self) -> u8 {
-        u8::try_from(self.0.addr() >> 56).unwrap()
-    }
-}
-
-impl<T> Clone for TaggedPointer<T> {
-    fn clone(&self) -> Self {
-        *self
-    }
-}
-
-impl<T> Copy for TaggedPointer<T> {}
-
-impl<T> AtomicBox<T> {
-    const unsafe fn new() -> Self {
-        Self(AtomicPtr::new(ptr::null_mut()))
-    }
-
-    /// reeeeplace
-    pub fn replace(&self, info: Box<T>) -> Option<Box<T>> {
-        let raw_ptr = Box::into_raw(info);
-        let prev_ptr = loop {
-            let prev_ptr = TaggedPointer(self.0.load(Ordering::Relaxed));
-            if prev_ptr.tag() == 0
-                && let Ok(previous_ptr) = self.0.compare_exchange(
-                    prev_ptr.0,
-                    raw_ptr,
-                    Ordering::AcqRel,
-                    Ordering::Relaxed,
-                )
-            {
-                break previous_ptr;
-            }
-            hint::spin_loop();
-        };
-        NonNull::new(prev_ptr).map(|ptr| unsafe { Box::from_raw(ptr.as_ptr()) })
-    }
-
-    ///reaads
-    #[allow(clippy::unwrap_in_result)]
-    pub fn read(&self) -> Option<AtomicBoxGuard<T>> {
-        let raw_ptr = self
-            .0
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |ptr| {
-                if ptr.is_null() {
-                    None
-                } else {
-                    Some(ptr.map_addr(|addr| {
-                        let addr_portion = addr & 0xFF_FFFF_FFFF_FFFF;
-                        let top_bits = u8::try_from(addr >> 56).unwrap();
-                        let new_count = top_bits.checked_add(1).unwrap();
-                        addr_portion & (usize::from(new_count) << 56)
-                    }))
-                }
-            });
-        match raw_ptr {
-            Ok(raw_ptr) => Some(AtomicBoxGuard {
-                container: self,
-                reference: unsafe { raw_ptr.as_ref() }.unwrap(),
-            }),
-            Err(p) => {
-                assert!(p.is_null());
-                None
-            }
-        }
-    }
-
-    unsafe fn decrement_readers(&self) {
-        self.0
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |ptr| {
-                assert!(!ptr.is_null());
-                Some(ptr.map_addr(|addr| {
-                    let addr_portion = addr & 0xFF_FFFF_FFFF_FFFF;
-                    let top_bits = u8::try_from(addr >> 56).unwrap();
-                    let new_count = top_bits.checked_sub(1).unwrap();
-                    addr_portion & (usize::from(new_count) << 56)
-                }))
-            })
-            .unwrap();
-    }
-}
-
-pub struct AtomicBoxGuard<'reference, 'data, T>
-where
-    'data: 'reference,
-{
-    container: &'data AtomicBox<T>,
-    reference: &'reference T,
-}
-
-impl<'reference, 'data, T> Drop for AtomicBoxGuard<'reference, 'data, T> {
-    fn drop(&mut self) {
-        unsafe { self.container.decrement_readers() };
-    }
-}
-
-impl<'reference, 'data, T> Deref for AtomicBoxGuard<'reference, 'data, T> {
-    type Target = T;
-
-    fn deref(&self) -> &Self::Target {
-        self.reference
-    }
-}
-
-/// Signal handlers for
-pub(crate) static SIGNAL_HANDLERS: [AtomicBox<SignalInfo>; 4] =
-    [const { unsafe { AtomicBox::new() } }; 4];
-
-/// Rust handler invoked when any exception occurs
-extern "C" fn general_handler(exception_code: u64, arg0: u64, sp: usize) -> ReturnRegs {
+/// Rust handler invoked when any exception occurs. `payload` is only meaningful for user signals
+extern "C" fn general_handler(
+    exception_code: u64,
+    arg0: u64,
+    sp: usize,
+    payload: u64,
+) -> ReturnRegs {
     match FromPrimitive::from_u64(exception_code) {
         Some(ExceptionCode::Preemption) => {
             unreachable!("Preemption should not reach the general handler")
@@ -343,7 +214,7 @@ extern "C" fn general_handler(exception_code: u64, arg0: u64, sp: usize) -> Retu
             ReturnRegs { x0, x1 }
         }
         Some(ExceptionCode::UserSignal) => {
-            handle_user_signal(pid_t::try_from(arg0).expect("PID should be valid"));
+            handle_user_signal(pid_t::try_from(arg0).expect("PID should be valid"), payload);
             ReturnRegs {
                 x0: exception_code,
                 x1: arg0,
@@ -355,40 +226,33 @@ extern "C" fn general_handler(exception_code: u64, arg0: u64, sp: usize) -> Retu
     }
 }
 
+/// Bit of the page fault information set by the kernel for permission faults, as opposed to
+/// faults on addresses without a valid translation
+const PERMISSION_FAULT: u64 = 1 << 63;
+/// Bit of the page fault information set by the kernel for faults on instruction fetches
+const INSTRUCTION_FETCH: u64 = 1 << 62;
+
 /// Handler when the kernel delivers a page fault to this process. Resolves abstractions such as `mmap` before dispatching to the user handler, if necessary
 extern "C" fn handle_page_fault(faulting_info: u64) {
-    panic!("Page fault occured! Faulting information: {faulting_info:X}");
+    let faulting_address = faulting_info & !(PERMISSION_FAULT | INSTRUCTION_FETCH);
+    let kind = if faulting_info & INSTRUCTION_FETCH == 0 {
+        "data access"
+    } else {
+        "instruction fetch"
+    };
+    // Anonymous memory only ever lacks a translation; a permission fault means the page is
+    // already mapped with the wrong permissions
+    if faulting_info & PERMISSION_FAULT != 0 {
+        panic!("Permission fault on {kind} at {faulting_address:X}");
+    }
+    if !vm::resolve_fault(faulting_address) {
+        panic!("Page fault on {kind} at {faulting_address:X}");
+    }
 }
 
-/// Handler when a signal is delivered from another process
-extern "C" fn handle_user_signal(sender_pid: u16) {
-    println!("User signal occured! Sender: {sender_pid}");
-    if let Some(handler_info) = SIGNAL_HANDLERS[ExceptionCode::UserSignal as usize].read() {
-        match handler_info.handler {
-            HandlerType::Signal(handler) => unsafe { handler(ExceptionCode::UserSignal as _) },
-            HandlerType::SigAction(handler) => {
-                let siginfo = SigInfo {
-                    si_addr: ptr::null_mut(),
-                    si_band: 0,
-                    // what exactly is this value?
-                    si_value: SigVal {
-                        sival_int: ExceptionCode::UserSignal as _,
-                    },
-                    si_signo: ExceptionCode::UserSignal as _,
-                    si_code: todo!("SI_USER"),
-                    si_errno: 0, // check?
-                    si_status: 0,
-                    si_pid: sender_pid,
-                    si_uid: 0,
-                };
-                unsafe {
-                    handler(
-                        ExceptionCode::UserSignal as _,
-                        addr_of_mut!(siginfo),
-                        ptr::null_mut(),
-                    )
-                }
-            }
-        }
-    }
+/// Handler when a signal is delivered from another process. Such signals are delivered as
+/// `SIGUSR1`, with the sender and the word it sent available to `SA_SIGINFO` handlers as `si_pid`
+/// and `si_value`
+extern "C" fn handle_user_signal(sender_pid: u16, word: u64) {
+    signal::ffi::deliver(signal::ffi::SIGUSR1, sender_pid, word);
 }
@@ -1,8 +1,17 @@
-use core::{arch, ptr::NonNull};
-
-use alloc::boxed::Box;
-
-use crate::{os::syscalls, println};
+use core::{
+    arch,
+    ffi::{c_char, c_int, CStr},
+    ptr::{self, NonNull},
+};
+
+use crate::{
+    os::{
+        syscalls,
+        vm::{AddressSpace, ADDRESS_SPACE},
+    },
+    print, println,
+    sync::SpinLock,
+};
 
 /// The entry point of the program.
 /// * Reads arguments off the stack and jumps into Rust code.
@@ -58,12 +67,14 @@ impl PointerReader {
 /// The Rust entry point of the program. Initializes the runtime and then jumps to main
 /// # Safety
 /// * Should only be called once, upon program load.
-/// * The arguments must be correct: `ttbr0_virtual` must be the virtual address of the base table for translation,
-/// `argc` must be the number of arguments, `arglens` must be the length of those arguments as an array of `u16`s, and `argbytes` must be a pointer to the packed, concatenated contents of those arguments
+/// * The stack must be laid out by the loader: `argc`, then `argc` pointers to NUL-terminated
+/// arguments followed by a null pointer, then pointers to NUL-terminated environment entries
+/// followed by a null pointer, and then `ttbr0_virtual`, the virtual address of the base table
+/// for translation
 /// * `main` must be a C-abi compatible label to invoke, and must be safe
 unsafe extern "C" fn start(sp: Option<NonNull<u128>>) -> ! {
     extern "C" {
-        fn main();
+        fn main(argc: c_int, argv: *const *const c_char);
     }
 
     let sp = sp.expect("Arguments pointer should not be null");
@@ -79,25 +90,40 @@ unsafe extern "C" fn start(sp: Option<NonNull<u128>>) -> ! {
     let mut reader = PointerReader(sp.cast());
 
     // SAFETY: The caller promises that the arguments region is safe
-    let ttbr0_virtual = unsafe { reader.read::<usize>() };
+    let arg_count = unsafe { reader.read::<usize>() };
     // SAFETY: The caller promises that the arguments region is safe
-    let arg_count = unsafe { reader.read::<u16>() }.into();
-
+    let argv = unsafe { reader.read_slice::<*const c_char>(arg_count) };
+    // SAFETY: The caller promises that the arguments are followed by a null pointer
+    let _: *const c_char = unsafe { reader.read() };
+    // The environment is not used yet, so it is skipped up to its terminating null pointer
+    // SAFETY: The caller promises that the environment is terminated by a null pointer
+    while !unsafe { reader.read::<*const c_char>() }.is_null() {}
     // SAFETY: The caller promises that the arguments region is safe
-    let arg_lens = unsafe { reader.read_slice::<u16>(arg_count) };
+    let ttbr0_virtual = unsafe { reader.read::<usize>() };
 
-    let args: Box<[&[u8]]> = arg_lens
-        .iter()
-        .scan(reader, |reader, &length| {
-            // SAFETY: The caller promises that the arguments region is safe
-            Some(unsafe { reader.read_slice::<u8>(length.into()) })
-        })
-        .collect();
+    print!("ARGUMENTS: {ttbr0_virtual:X}");
+    for &arg in argv {
+        // SAFETY: The caller promises that each argument is NUL-terminated
+        print!(" {:?}", unsafe { CStr::from_ptr(arg) });
+    }
+    println!();
 
-    println!("ARGUMENTS: {ttbr0_virtual:X} {args:X?}");
+    let base_table = NonNull::new(ptr::from_exposed_addr_mut(ttbr0_virtual))
+        .expect("Translation table should not be null");
+    // SAFETY: The loader maps this program's translation table at `ttbr0_virtual` for the whole
+    // lifetime of the program
+    let address_space = unsafe { AddressSpace::new(base_table) };
+    assert!(
+        ADDRESS_SPACE.set(SpinLock::new(address_space)).is_ok(),
+        "The address space should only be set once"
+    );
 
-    // SET UP VM THINGS
     // SAFETY: The caller/program promises to uphold safety
-    unsafe { main() };
-    syscalls::exit()
+    unsafe {
+        main(
+            c_int::try_from(arg_count).expect("Argument count should fit in a `c_int`"),
+            argv.as_ptr(),
+        );
+    };
+    syscalls::exit(0)
 }
@@ -6,11 +6,18 @@ pub struct SigInfo {}
 #[allow(clippy::struct_field_names)]
 /// C interface, POSIX-specified functions
 pub mod ffi {
-    use core::ffi::{c_int, c_long, c_void};
+    use core::{
+        ffi::{c_int, c_long, c_void},
+        ptr::{self, addr_of_mut},
+    };
 
     use bitfield_struct::bitfield;
 
-    use crate::sys::types::ffi::{pid_t, uid_t};
+    use crate::{
+        errno::{self, Error},
+        sync::SpinLock,
+        sys::types::ffi::{pid_t, uid_t},
+    };
 
     #[repr(C)]
     pub union SigVal {
@@ -31,14 +38,41 @@ pub mod ffi {
         pub si_uid: uid_t,
     }
 
+    /// Number of supported signals; valid signal numbers are `1..NSIG`
+    pub const NSIG: c_int = 32;
+
+    /// Abnormal termination, as by `abort`
+    pub const SIGABRT: c_int = 6;
+    /// Application-defined signal, raised when another program signals this one
+    pub const SIGUSR1: c_int = 10;
+    /// Application-defined signal
+    pub const SIGUSR2: c_int = 12;
+
+    /// `si_code` for signals sent by another program
+    pub const SI_USER: c_int = 0;
+
     #[repr(C)]
-    struct SigAction {
-        sa_handler: unsafe extern "C" fn(c_int),
-        sa_sigaction: unsafe extern "C" fn(c_int, *mut SigInfo, *mut c_void),
+    #[derive(Clone, Copy)]
+    pub struct SigAction {
+        /// Handler invoked with just the signal number, if `SA_SIGINFO` is not set
+        pub sa_handler: Option<unsafe extern "C" fn(c_int)>,
+        /// Handler invoked with the signal information, if `SA_SIGINFO` is set
+        pub sa_sigaction: Option<unsafe extern "C" fn(c_int, *mut SigInfo, *mut c_void)>,
+        /// Flags modifying the behavior of the signal
+        pub sa_flags: SigFlags,
+    }
+
+    impl SigAction {
+        /// The default action, which currently ignores the signal
+        const DEFAULT: Self = Self {
+            sa_handler: None,
+            sa_sigaction: None,
+            sa_flags: SigFlags::new(),
+        };
     }
 
     #[bitfield(u32)]
-    struct SigFlags {
+    pub struct SigFlags {
         nocldstop: bool,
         onstack: bool,
         resethand: bool,
@@ -50,13 +84,80 @@ pub mod ffi {
         __: u32,
     }
 
+    /// The registered action for each signal, indexed by signal number
+    #[expect(clippy::as_conversions, reason = "`NSIG` is a small positive constant")]
+    static ACTIONS: SpinLock<[SigAction; NSIG as usize]> =
+        SpinLock::new([SigAction::DEFAULT; NSIG as usize]);
+
+    /// Converts a signal number into an index into `ACTIONS`, if it is a valid signal
+    #[expect(clippy::as_conversions, reason = "`NSIG` is a small positive constant")]
+    fn action_index(sig: c_int) -> Option<usize> {
+        usize::try_from(sig)
+            .ok()
+            .filter(|&index| index != 0 && index < NSIG as usize)
+    }
+
+    /// Invokes the handler registered for the given signal, if any, on behalf of `sender_pid`.
+    /// `word` is passed to `SA_SIGINFO` handlers as the pointer member of `si_value`
+    pub(crate) fn deliver(sig: c_int, sender_pid: pid_t, word: u64) {
+        let index = action_index(sig).expect("Delivered signals should be valid");
+        let action = {
+            let mut actions = ACTIONS.lock();
+            let action = actions[index];
+            if action.sa_flags.resethand() {
+                actions[index] = SigAction::DEFAULT;
+            }
+            action
+        };
+        // The lock is released before running the handler, so that the handler may itself call
+        // `sigaction`
+        if action.sa_flags.siginfo() {
+            if let Some(handler) = action.sa_sigaction {
+                let mut siginfo = SigInfo {
+                    si_addr: ptr::null_mut(),
+                    si_band: 0,
+                    si_value: SigVal {
+                        sival_ptr: ptr::from_exposed_addr_mut(
+                            usize::try_from(word).expect("`u64`s should fit in a `usize`"),
+                        ),
+                    },
+                    si_signo: sig,
+                    si_code: SI_USER,
+                    si_errno: 0,
+                    si_status: 0,
+                    si_pid: sender_pid,
+                    si_uid: 0,
+                };
+                // SAFETY: The handler was registered by the program through `sigaction`
+                unsafe { handler(sig, addr_of_mut!(siginfo), ptr::null_mut()) };
+            }
+        } else if let Some(handler) = action.sa_handler {
+            // SAFETY: The handler was registered by the program through `sigaction`
+            unsafe { handler(sig) };
+        }
+    }
+
+    /// Examines and/or changes the action taken on receipt of the given signal. The previous
+    /// action is stored into `oact`, and the new action is taken from `act`, if either is present
+    ///
+    /// Returns 0 on success, or -1 with `errno` set to `EINVAL` if `sig` is not a valid signal
     #[no_mangle]
     unsafe extern "C" fn sigaction(
         sig: c_int,
         act: Option<&SigAction>,
         oact: Option<&mut SigAction>,
     ) -> c_int {
-        // if act.is_some() {}
+        let Some(index) = action_index(sig) else {
+            errno::set_errno(Error::EINVAL);
+            return -1;
+        };
+        let mut actions = ACTIONS.lock();
+        if let Some(oact) = oact {
+            *oact = actions[index];
+        }
+        if let Some(&act) = act {
+            actions[index] = act;
+        }
         0
     }
 }
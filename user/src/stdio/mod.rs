@@ -1,9 +1,17 @@
 use core::ffi::{c_size_t, c_uchar};
+use core::time::Duration;
 
-use crate::errno::Error;
+use crate::{errno::Error, os::syscalls};
+
+use self::service::{ReadError, RequestError, StatError, WriteError};
+
+pub mod service;
 
 pub type fpos_t = u64;
 
+/// Number of bytes written to a stream that are held back before being sent together
+const WRITE_BUFFER_SIZE: usize = 256;
+
 struct Pipe {
     /// ID of the pipe to write to
     id: u16,
@@ -13,28 +21,219 @@ enum FileType {
     Pipe(Pipe),
 }
 
+/// The kind of object underlying a stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Pipe,
+}
+
+/// The point that a seek offset is relative to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Whence {
+    /// The start of the stream
+    Set,
+    /// The current position in the stream
+    Current,
+    /// The end of the stream
+    End,
+}
+
+/// When the bytes written to a stream are sent to the underlying object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Buffering {
+    /// Bytes are sent once the write buffer fills
+    Full,
+    /// Bytes are sent once the write buffer fills, or a newline is written
+    Line,
+    /// Bytes are sent as soon as they are written
+    Unbuffered,
+}
+
+/// Information about the object underlying a stream
+#[derive(Clone, Copy, Debug)]
+pub struct FileStat {
+    /// The kind of the underlying object
+    pub kind: FileKind,
+    /// For pipes, the number of bytes buffered and not yet read
+    pub size: usize,
+}
+
 pub struct FILE {
     is_error: bool,
     blocking: bool,
+    /// When buffered bytes are flushed to the underlying object
+    buffering: Buffering,
+    /// Bytes written to this stream but not yet sent to the underlying object
+    write_buffer: [c_uchar; WRITE_BUFFER_SIZE],
+    /// Number of bytes at the start of `write_buffer` waiting to be sent
+    buffered: usize,
     inner: FileType,
 }
 
 impl FILE {
+    /// Waits before retrying an operation that would block, or returns `EAGAIN` if this stream is
+    /// non-blocking. If the pipe server has promised to unblock this program once the operation can
+    /// make progress, `notified` should be set, so that this program blocks rather than polls
+    ///
+    /// If a deadline, as an uptime, is given, the wait ends by then, and `ETIMEDOUT` is returned
+    /// once the deadline has passed
+    fn would_block(&self, notified: bool, deadline: Option<Duration>) -> crate::Result<()> {
+        if !self.blocking {
+            return Err(Error::EAGAIN);
+        }
+        let remaining = deadline
+            .map(|deadline| {
+                deadline
+                    .checked_sub(syscalls::uptime().0)
+                    .filter(|remaining| !remaining.is_zero())
+                    .ok_or(Error::ETIMEDOUT)
+            })
+            .transpose()?;
+        match (notified, remaining) {
+            (true, None) => syscalls::block(),
+            // Whether woken or timed out, the operation is retried once more before the deadline
+            // is checked again. An unblock that arrives too late is left pending, and only ends
+            // the next wait early
+            (true, Some(remaining)) => {
+                let _woken = syscalls::block_timeout(remaining);
+            }
+            (false, _) => syscalls::sched_yield(),
+        }
+        Ok(())
+    }
+
+    /// Sets when bytes written to this stream are sent to the underlying object. Bytes that are
+    /// already buffered are flushed first, so that they are not held back under the new mode
+    pub fn setvbuf(&mut self, buffering: Buffering) -> crate::Result<()> {
+        self.fflush()?;
+        self.buffering = buffering;
+        Ok(())
+    }
+
+    /// Writes a byte to this stream. The byte is buffered, and only sent as the buffering mode of
+    /// this stream dictates
     pub fn fputc(&mut self, c: c_uchar) -> crate::Result<()> {
+        if self.buffered == self.write_buffer.len() {
+            self.fflush()?;
+        }
+        self.write_buffer[self.buffered] = c;
+        self.buffered += 1;
+        let flush = match self.buffering {
+            Buffering::Full => self.buffered == self.write_buffer.len(),
+            Buffering::Line => self.buffered == self.write_buffer.len() || c == b'\n',
+            Buffering::Unbuffered => true,
+        };
+        if flush {
+            self.fflush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sends every buffered byte to the underlying object. On failure, the bytes that could not be
+    /// sent stay buffered
+    pub fn fflush(&mut self) -> crate::Result<()> {
         match self.inner {
-            FileType::Pipe(_) => {
-                // Talk to pipe arbiter here
-                todo!()
-            }
+            FileType::Pipe(Pipe { id }) => loop {
+                if self.buffered == 0 {
+                    return Ok(());
+                }
+                match service::write(id, &self.write_buffer[..self.buffered]) {
+                    // The pipe server refuses a write with no room rather than accepting nothing,
+                    // so retrying would never make progress
+                    Ok(0) => {
+                        self.is_error = true;
+                        return Err(Error::EIO);
+                    }
+                    Ok(written) => {
+                        let written = written.min(self.buffered);
+                        self.write_buffer.copy_within(written..self.buffered, 0);
+                        self.buffered -= written;
+                    }
+                    Err(RequestError::Refused(WriteError::Full)) => self.would_block(true, None)?,
+                    Err(RequestError::Refused(WriteError::Locked)) => {
+                        self.would_block(false, None)?;
+                    }
+                    Err(RequestError::Refused(
+                        WriteError::NoSuchPipe | WriteError::InsufficientPermissions,
+                    )) => return Err(Error::EBADF),
+                    Err(RequestError::Transport) => {
+                        self.is_error = true;
+                        return Err(Error::EIO);
+                    }
+                }
+            },
         }
     }
 
+    /// Reads a byte from this stream. If the pipe is empty, a blocking stream blocks until the
+    /// pipe server wakes it after the next write, rather than polling
     pub fn fgetc(&mut self) -> crate::Result<c_uchar> {
+        self.fgetc_timeout(None)
+    }
+
+    /// Reads a byte from this stream, as with `fgetc`, but waits at most `timeout` system counter
+    /// ticks for one to arrive, if given, before failing with `ETIMEDOUT`
+    ///
+    /// Each read request is answered before this waits, so a timeout leaves nothing outstanding
+    /// with the pipe server, and a byte written later is returned by the next read
+    pub fn fgetc_timeout(&mut self, timeout: Option<u64>) -> crate::Result<c_uchar> {
+        let deadline = timeout.map(|ticks| {
+            syscalls::uptime()
+                .0
+                .saturating_add(syscalls::duration_from_ticks(ticks))
+        });
         match self.inner {
-            FileType::Pipe(_) => {
-                // Talk to pipe arbiter here
-                todo!()
-            }
+            FileType::Pipe(Pipe { id }) => loop {
+                let mut byte = 0;
+                match service::read(id, core::slice::from_mut(&mut byte)) {
+                    Ok(0) => self.would_block(true, deadline)?,
+                    Err(RequestError::Refused(ReadError::Locked)) => {
+                        self.would_block(false, deadline)?;
+                    }
+                    Ok(_) => return Ok(byte),
+                    Err(RequestError::Refused(
+                        ReadError::NoSuchPipe | ReadError::InsufficientPermissions,
+                    )) => return Err(Error::EBADF),
+                    Err(RequestError::Transport) => {
+                        self.is_error = true;
+                        return Err(Error::EIO);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Returns information about the object underlying this stream. For pipes, the size is the
+    /// number of bytes written and not yet read, which may be stale by the time it is returned
+    pub fn fstat(&mut self) -> crate::Result<FileStat> {
+        match self.inner {
+            FileType::Pipe(Pipe { id }) => match service::stat(id) {
+                Ok(buffered) => Ok(FileStat {
+                    kind: FileKind::Pipe,
+                    size: usize::try_from(buffered).expect("`u32`s should fit in a `usize`"),
+                }),
+                Err(RequestError::Refused(StatError::NoSuchPipe)) => Err(Error::EBADF),
+                Err(RequestError::Transport) => {
+                    self.is_error = true;
+                    Err(Error::EIO)
+                }
+            },
+        }
+    }
+
+    /// Moves the position of this stream to `offset` bytes past the point given by `whence`. Pipes
+    /// have no position, so seeking them fails with `ESPIPE`
+    pub fn fseek(&mut self, _offset: i64, _whence: Whence) -> crate::Result<()> {
+        match self.inner {
+            FileType::Pipe(_) => Err(Error::ESPIPE),
+        }
+    }
+
+    /// Returns the position of this stream, as a number of bytes from its start
+    pub fn ftell(&self) -> crate::Result<fpos_t> {
+        match self.inner {
+            FileType::Pipe(_) => Err(Error::ESPIPE),
         }
     }
 
@@ -58,16 +257,46 @@ impl FILE {
     }
 }
 
+impl Drop for FILE {
+    /// Flushes whatever buffered bytes can be sent without waiting. Bytes that do not fit, and any
+    /// errors, are lost, so callers that care should `fflush` first
+    fn drop(&mut self) {
+        self.blocking = false;
+        let _: crate::Result<()> = self.fflush();
+    }
+}
+
 /// C compatible interface, as specified by POSIX
 pub mod ffi {
-    use crate::{errno, EOF};
+    use crate::{
+        errno,
+        sys::{
+            stat::ffi::{stat, S_IFIFO},
+            types::ffi::off_t,
+        },
+        EOF,
+    };
 
-    use super::FILE;
+    use super::{Buffering, FileKind, Whence, FILE};
     use core::{
-        ffi::{c_int, c_size_t, c_uchar, c_void},
+        ffi::{c_int, c_long, c_size_t, c_uchar, c_void},
         ptr::NonNull,
     };
 
+    /// `mode` for `setvbuf` to flush only once the buffer fills
+    pub const _IOFBF: c_int = 0;
+    /// `mode` for `setvbuf` to also flush on every newline
+    pub const _IOLBF: c_int = 1;
+    /// `mode` for `setvbuf` to flush every byte as it is written
+    pub const _IONBF: c_int = 2;
+
+    /// `whence` for `fseek` to seek relative to the start of the stream
+    pub const SEEK_SET: c_int = 0;
+    /// `whence` for `fseek` to seek relative to the current position
+    pub const SEEK_CUR: c_int = 1;
+    /// `whence` for `fseek` to seek relative to the end of the stream
+    pub const SEEK_END: c_int = 2;
+
     #[no_mangle]
     pub unsafe extern "C" fn fputc(c: c_int, stream: *mut FILE) -> c_int {
         assert!(
@@ -86,6 +315,60 @@ pub mod ffi {
         }
     }
 
+    /// Sends any bytes buffered in a stream. Streams are not tracked, so a null stream, which asks
+    /// to flush every open stream, flushes nothing
+    #[no_mangle]
+    pub unsafe extern "C" fn fflush(stream: *mut FILE) -> c_int {
+        assert!(
+            stream.is_aligned(),
+            "Stream should be a valid, aligned pointer"
+        );
+        let Some(stream) = (unsafe { stream.as_mut() }) else {
+            return 0;
+        };
+        match stream.fflush() {
+            Ok(()) => 0,
+            Err(err) => {
+                errno::set_errno(err);
+                EOF
+            }
+        }
+    }
+
+    /// Sets the buffering mode of a stream. Streams always use their own buffer, so `buf` and
+    /// `size` are ignored, as POSIX permits
+    #[no_mangle]
+    pub unsafe extern "C" fn setvbuf(
+        stream: *mut FILE,
+        _buf: *mut c_uchar,
+        mode: c_int,
+        _size: c_size_t,
+    ) -> c_int {
+        assert!(
+            stream.is_aligned(),
+            "Stream should be a valid, aligned pointer"
+        );
+        let buffering = match mode {
+            _IOFBF => Buffering::Full,
+            _IOLBF => Buffering::Line,
+            _IONBF => Buffering::Unbuffered,
+            _ => {
+                errno::set_errno(errno::Error::EINVAL);
+                return -1;
+            }
+        };
+        let value = unsafe { stream.as_mut() }
+            .expect("Stream should not be null")
+            .setvbuf(buffering);
+        match value {
+            Ok(()) => 0,
+            Err(err) => {
+                errno::set_errno(err);
+                -1
+            }
+        }
+    }
+
     #[no_mangle]
     pub unsafe extern "C" fn fgetc(stream: *mut FILE) -> c_int {
         assert!(
@@ -159,4 +442,82 @@ pub mod ffi {
         }
         count
     }
+
+    /// Queries the object underlying a stream, filling in `buf` as `fstat` would for its file
+    /// descriptor. This library has no descriptor table, so it takes the stream itself, and is
+    /// named apart from `fstat` so as not to clash with the POSIX signature
+    #[no_mangle]
+    pub unsafe extern "C" fn ffstat(stream: *mut FILE, buf: *mut stat) -> c_int {
+        assert!(
+            stream.is_aligned(),
+            "Stream should be a valid, aligned pointer"
+        );
+        assert!(
+            buf.is_aligned(),
+            "Buffer should be a valid, aligned pointer"
+        );
+        let value = unsafe { stream.as_mut() }
+            .expect("Stream should not be null")
+            .fstat();
+        match value {
+            Ok(file_stat) => {
+                let buf = unsafe { buf.as_mut() }.expect("Buffer should not be null");
+                buf.st_mode = match file_stat.kind {
+                    FileKind::Pipe => S_IFIFO,
+                };
+                buf.st_size = file_stat.size.try_into().unwrap_or(off_t::MAX);
+                0
+            }
+            Err(err) => {
+                errno::set_errno(err);
+                -1
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn fseek(stream: *mut FILE, offset: c_long, whence: c_int) -> c_int {
+        assert!(
+            stream.is_aligned(),
+            "Stream should be a valid, aligned pointer"
+        );
+        let whence = match whence {
+            SEEK_SET => Whence::Set,
+            SEEK_CUR => Whence::Current,
+            SEEK_END => Whence::End,
+            _ => {
+                errno::set_errno(errno::Error::EINVAL);
+                return -1;
+            }
+        };
+        let value = unsafe { stream.as_mut() }
+            .expect("Stream should not be null")
+            .fseek(offset, whence);
+        match value {
+            Ok(()) => 0,
+            Err(err) => {
+                errno::set_errno(err);
+                -1
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn ftell(stream: *mut FILE) -> c_long {
+        assert!(
+            stream.is_aligned(),
+            "Stream should be a valid, aligned pointer"
+        );
+        let value = unsafe { stream.as_ref() }
+            .expect("Stream should not be null")
+            .ftell()
+            .and_then(|position| c_long::try_from(position).map_err(|_| errno::Error::EOVERFLOW));
+        match value {
+            Ok(position) => position,
+            Err(err) => {
+                errno::set_errno(err);
+                -1
+            }
+        }
+    }
 }
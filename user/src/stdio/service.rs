@@ -0,0 +1,309 @@
+//! Client side of the channel to the pipe server, over which pipe reads and writes are requested
+
+use alloc::vec::Vec;
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+use crate::{os::syscalls, sync::SpinLock};
+
+/// Number of bytes in each direction of a channel. A channel occupies a single page, with requests
+/// in the lower half and responses in the upper half
+pub const BUFFER_SIZE: usize = 1 << 15;
+
+/// The kind of a message sent over a channel, which is its first byte
+#[derive(FromPrimitive)]
+pub enum MessageKind {
+    None = 0,
+    Read = 1,
+    Write = 2,
+    Fork = 3,
+    Create = 4,
+    DropRead = 5,
+    DropWrite = 6,
+    ReadFailure = 7,
+    WriteFailure = 8,
+    Stat = 9,
+    StatFailure = 10,
+    Dup = 11,
+    Dup2 = 12,
+    DupFailure = 13,
+}
+
+/// Reasons the pipe server may refuse a read
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+#[repr(u8)]
+pub enum ReadError {
+    NoSuchPipe = 0,
+    InsufficientPermissions = 1,
+    Locked = 2,
+}
+
+/// Reasons the pipe server may refuse a write
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+#[repr(u8)]
+pub enum WriteError {
+    NoSuchPipe = 0,
+    InsufficientPermissions = 1,
+    Locked = 2,
+    /// The pipe has no room for any more bytes. The pipe server unblocks the writer once a
+    /// reader drains the pipe
+    Full = 3,
+}
+
+/// Reasons the pipe server may refuse a stat
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+#[repr(u8)]
+pub enum StatError {
+    /// This program holds neither end of the pipe
+    NoSuchPipe = 0,
+}
+
+/// Reasons the pipe server may refuse to duplicate a pipe end
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+#[repr(u8)]
+pub enum DupError {
+    /// This program holds neither end of the source pipe
+    NoSuchPipe = 0,
+    /// This program holds as many pipe IDs as it can
+    MaxPipeCount = 1,
+}
+
+/// Error from a request to the pipe server
+#[derive(Debug)]
+pub enum RequestError<E> {
+    /// The pipe server could not be reached, or its response could not be understood
+    Transport,
+    /// The pipe server received the request but refused it
+    Refused(E),
+}
+
+/// This program's end of its channel to the pipe server
+struct ServiceChannel {
+    /// Buffer that requests are written into
+    requests: NonNull<[AtomicU8; BUFFER_SIZE]>,
+    /// Buffer that responses are read from
+    responses: NonNull<[AtomicU8; BUFFER_SIZE]>,
+    /// Index at which the next request is written
+    request_index: usize,
+    /// Index at which the next response is read
+    response_index: usize,
+    /// PID of the pipe server, which is signalled whenever a request is sent
+    server_pid: u16,
+}
+
+/// The channel to the pipe server, once connected
+static CHANNEL: SpinLock<Option<ServiceChannel>> = SpinLock::new(None);
+
+/// Sets up the channel over which all pipe requests from this program are sent
+///
+/// # Safety
+///
+/// `page` must point to a page shared with the pipe server `server_pid`, that remains mapped for
+/// the rest of this program and is not otherwise accessed by this program
+#[inline]
+pub unsafe fn connect(page: NonNull<[AtomicU8; 2 * BUFFER_SIZE]>, server_pid: u16) {
+    let requests = page.cast::<[AtomicU8; BUFFER_SIZE]>();
+    *CHANNEL.lock() = Some(ServiceChannel {
+        requests,
+        // SAFETY: The page is twice the size of one buffer
+        responses: unsafe { requests.add(1) },
+        request_index: 0,
+        response_index: 0,
+        server_pid,
+    });
+}
+
+impl ServiceChannel {
+    /// Returns the byte of the request buffer at the given offset past the next request
+    fn request_byte(&self, offset: usize) -> &AtomicU8 {
+        // SAFETY: The buffer is valid for the lifetime of the channel, by the contract of `connect`
+        let requests = unsafe { self.requests.as_ref() };
+        &requests[self.request_index.wrapping_add(offset) % BUFFER_SIZE]
+    }
+
+    /// Sends a request of the given kind and body to the pipe server. Returns whether the server
+    /// could be signalled
+    fn send(&mut self, kind: MessageKind, body: &[u8]) -> bool {
+        // The kind is written last, so that the server never sees a partially written request
+        self.request_byte(body.len().wrapping_add(1))
+            .store(0, Ordering::Relaxed);
+        for (offset, &byte) in body.iter().enumerate() {
+            self.request_byte(offset.wrapping_add(1))
+                .store(byte, Ordering::Relaxed);
+        }
+        #[expect(clippy::as_conversions)]
+        self.request_byte(0).store(kind as u8, Ordering::Release);
+        self.request_index = self.request_index.wrapping_add(body.len().wrapping_add(1));
+        syscalls::send_signal(self.server_pid)
+    }
+
+    /// Reads the next byte of the response buffer
+    fn next_response_byte(&mut self) -> u8 {
+        // SAFETY: The buffer is valid for the lifetime of the channel, by the contract of `connect`
+        let responses = unsafe { self.responses.as_ref() };
+        let byte = responses[self.response_index % BUFFER_SIZE].load(Ordering::Acquire);
+        self.response_index = self.response_index.wrapping_add(1);
+        byte
+    }
+
+    /// Waits for the next response from the pipe server, and returns its kind
+    fn receive(&mut self) -> Option<MessageKind> {
+        loop {
+            match MessageKind::from_u8(self.next_response_byte()) {
+                Some(MessageKind::None) => {
+                    self.response_index = self.response_index.wrapping_sub(1);
+                    syscalls::sched_yield();
+                }
+                kind => return kind,
+            }
+        }
+    }
+
+    /// Reads a `u16` from the response buffer
+    fn next_response_u16(&mut self) -> u16 {
+        u16::from_ne_bytes([self.next_response_byte(), self.next_response_byte()])
+    }
+
+    /// Reads a `u32` from the response buffer
+    fn next_response_u32(&mut self) -> u32 {
+        u32::from_ne_bytes([(); 4].map(|()| self.next_response_byte()))
+    }
+}
+
+/// Sends a request over the channel to the pipe server and processes its response with `receive`
+fn request<T, E>(
+    kind: MessageKind,
+    body: &[u8],
+    receive: impl FnOnce(&mut ServiceChannel) -> Result<T, RequestError<E>>,
+) -> Result<T, RequestError<E>> {
+    let mut channel = CHANNEL.lock();
+    let channel = channel.as_mut().ok_or(RequestError::Transport)?;
+    if channel.send(kind, body) {
+        receive(channel)
+    } else {
+        Err(RequestError::Transport)
+    }
+}
+
+/// Reads up to `buffer.len()` bytes from the given pipe into `buffer`, returning the number of
+/// bytes read. Returns 0 if the pipe is currently empty, in which case the pipe server unblocks
+/// this program once data is written
+///
+/// # Errors
+///
+/// Returns an error if the pipe server cannot be reached, or refuses the read
+#[inline]
+pub fn read(pipe_id: u16, buffer: &mut [u8]) -> Result<usize, RequestError<ReadError>> {
+    let count = u16::try_from(buffer.len()).unwrap_or(u16::MAX);
+    let mut body = [0; 4];
+    body[..2].copy_from_slice(&pipe_id.to_ne_bytes());
+    body[2..].copy_from_slice(&count.to_ne_bytes());
+    request(MessageKind::Read, &body, |channel| {
+        match channel.receive() {
+            Some(MessageKind::Read) => {
+                let length = usize::from(channel.next_response_u16());
+                if length > buffer.len() {
+                    return Err(RequestError::Transport);
+                }
+                for byte in &mut buffer[..length] {
+                    *byte = channel.next_response_byte();
+                }
+                Ok(length)
+            }
+            Some(MessageKind::ReadFailure) => Err(ReadError::from_u8(channel.next_response_byte())
+                .map_or(RequestError::Transport, RequestError::Refused)),
+            _ => Err(RequestError::Transport),
+        }
+    })
+}
+
+/// Writes as many of `bytes` into the given pipe as it has room for, returning the number of bytes
+/// written. The pipe server unblocks this program once a reader drains the pipe, if the pipe is
+/// full
+///
+/// # Errors
+///
+/// Returns an error if the pipe server cannot be reached, or refuses the write
+#[inline]
+pub fn write(pipe_id: u16, bytes: &[u8]) -> Result<usize, RequestError<WriteError>> {
+    let length = u16::try_from(bytes.len()).map_err(|_err| RequestError::Transport)?;
+    let body: Vec<u8> = pipe_id
+        .to_ne_bytes()
+        .into_iter()
+        .chain(length.to_ne_bytes())
+        .chain(bytes.iter().copied())
+        .collect();
+    request(MessageKind::Write, &body, |channel| {
+        match channel.receive() {
+            Some(MessageKind::Write) => Ok(usize::from(channel.next_response_u16())),
+            Some(MessageKind::WriteFailure) => {
+                Err(WriteError::from_u8(channel.next_response_byte())
+                    .map_or(RequestError::Transport, RequestError::Refused))
+            }
+            _ => Err(RequestError::Transport),
+        }
+    })
+}
+
+/// Returns the number of bytes currently buffered in the given pipe, which may be stale by the
+/// time it is returned
+///
+/// # Errors
+///
+/// Returns an error if the pipe server cannot be reached, or this program holds neither end of the
+/// pipe
+#[inline]
+pub fn stat(pipe_id: u16) -> Result<u32, RequestError<StatError>> {
+    request(
+        MessageKind::Stat,
+        &pipe_id.to_ne_bytes(),
+        |channel| match channel.receive() {
+            Some(MessageKind::Stat) => Ok(channel.next_response_u32()),
+            Some(MessageKind::StatFailure) => Err(StatError::from_u8(channel.next_response_byte())
+                .map_or(RequestError::Transport, RequestError::Refused)),
+            _ => Err(RequestError::Transport),
+        },
+    )
+}
+
+/// Receives the response to a `dup` or `dup2` request, which is the resulting pipe ID
+fn receive_dup(channel: &mut ServiceChannel) -> Result<u16, RequestError<DupError>> {
+    match channel.receive() {
+        Some(MessageKind::Dup) => Ok(channel.next_response_u16()),
+        Some(MessageKind::DupFailure) => Err(DupError::from_u8(channel.next_response_byte())
+            .map_or(RequestError::Transport, RequestError::Refused)),
+        _ => Err(RequestError::Transport),
+    }
+}
+
+/// Makes a new pipe ID that refers to the same pipe, with the same permissions, as `pipe_id`, and
+/// returns it. The pipe stays open until both IDs are dropped
+///
+/// # Errors
+///
+/// Returns an error if the pipe server cannot be reached, or this program holds neither end of the
+/// pipe or has no pipe IDs to spare
+#[inline]
+pub fn dup(pipe_id: u16) -> Result<u16, RequestError<DupError>> {
+    request(MessageKind::Dup, &pipe_id.to_ne_bytes(), receive_dup)
+}
+
+/// Makes `dst` refer to the same pipe, with the same permissions, as `src`, dropping whatever
+/// `dst` referred to before. Returns `dst`
+///
+/// # Errors
+///
+/// Returns an error if the pipe server cannot be reached, or this program holds neither end of
+/// `src`
+#[inline]
+pub fn dup2(src: u16, dst: u16) -> Result<u16, RequestError<DupError>> {
+    let mut body = [0; 4];
+    body[..2].copy_from_slice(&src.to_ne_bytes());
+    body[2..].copy_from_slice(&dst.to_ne_bytes());
+    request(MessageKind::Dup2, &body, receive_dup)
+}
@@ -0,0 +1,25 @@
+use core::ffi::c_int;
+
+/// Exit status of a program that called `abort`, matching the conventional status of a shell
+/// whose child was killed by `SIGABRT`
+pub const ABORT_STATUS: c_int = 134;
+
+/// C interface, POSIX-specified functions
+pub mod ffi {
+    use super::ABORT_STATUS;
+    use crate::os::syscalls;
+    use core::ffi::c_int;
+
+    /// Terminates the program with the given status, which a parent can collect with `waitpid`
+    #[no_mangle]
+    pub extern "C" fn exit(status: c_int) -> ! {
+        syscalls::exit(status)
+    }
+
+    /// Abnormally terminates the program, so that a parent collecting the exit status with
+    /// `waitpid` sees a failure
+    #[no_mangle]
+    pub extern "C" fn abort() -> ! {
+        syscalls::exit(ABORT_STATUS)
+    }
+}
@@ -0,0 +1,74 @@
+use super::{MutexGuard, SpinLock};
+use crate::os::syscalls;
+use alloc::collections::VecDeque;
+
+/// A condition variable, for waiting until some `SpinLock`-protected state changes without polling.
+///
+/// Waiters block through the `block`/`unblock` syscalls. A waiter is recorded before its lock is
+/// released, so a notification that lands between the release and the `block` supplies the
+/// blocking token early, and the `block` returns immediately instead of missing the wakeup. As
+/// that token may also be left over from an unrelated `unblock`, wakeups can be spurious, and
+/// callers should recheck their condition in a loop
+pub struct CondVar {
+    /// PIDs of programs waiting on this condition, in order of arrival
+    waiters: SpinLock<VecDeque<u16>>,
+}
+
+impl CondVar {
+    /// Creates a new condition variable with no waiters
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Releases the given lock and blocks the current program until notified, then reacquires the
+    /// lock before returning
+    #[inline]
+    pub fn wait<'locked, T>(&self, guard: MutexGuard<'locked, T>) -> MutexGuard<'locked, T> {
+        let lock = guard.0;
+        let pid = syscalls::getpid();
+        self.waiters.lock().push_back(pid);
+        drop(guard);
+        syscalls::block();
+        // A spurious return leaves this program queued, where it would soak up a later
+        // `notify_one`
+        let mut waiters = self.waiters.lock();
+        if let Some(index) = waiters.iter().position(|&waiter| waiter == pid) {
+            waiters.remove(index);
+        }
+        drop(waiters);
+        lock.lock()
+    }
+
+    /// Wakes the longest-waiting program, if any
+    #[inline]
+    pub fn notify_one(&self) {
+        // Skip over any waiters that have since exited, so that the notification is not lost
+        while let Some(pid) = self.waiters.lock().pop_front() {
+            if syscalls::unblock(pid) {
+                break;
+            }
+        }
+    }
+
+    /// Wakes every waiting program
+    #[inline]
+    pub fn notify_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        // Waiters that have since exited need no wakeup, so failures are ignored
+        let _: usize = waiters
+            .into_iter()
+            .filter(|&pid| syscalls::unblock(pid))
+            .count();
+    }
+}
+
+impl Default for CondVar {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
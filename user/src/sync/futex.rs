@@ -0,0 +1,72 @@
+use super::SpinLock;
+use crate::os::syscalls;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// A futex-like primitive: a 32-bit value that programs can sleep on until it changes.
+///
+/// Built on the per-program blocking token of the `block`/`unblock` syscalls. A wakeup that
+/// arrives after a waiter has queued itself, but before it actually blocks, leaves the token
+/// available so that the subsequent `block` returns immediately; wakeups are therefore never
+/// lost. As a consequence, `wait` may also return spuriously (e.g. due to a token left over from
+/// an earlier unrelated `unblock`), so callers should recheck their condition in a loop.
+pub struct Futex {
+    /// The value being waited on
+    value: AtomicU32,
+    /// PIDs of waiting programs, in order of arrival
+    waiters: SpinLock<VecDeque<u16>>,
+}
+
+impl Futex {
+    /// Creates a new futex with the given initial value
+    #[inline]
+    #[must_use]
+    pub const fn new(value: u32) -> Self {
+        Self {
+            value: AtomicU32::new(value),
+            waiters: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns the underlying value, which may be updated freely before calling `wake`
+    #[inline]
+    #[must_use]
+    pub const fn value(&self) -> &AtomicU32 {
+        &self.value
+    }
+
+    /// Blocks the current program if the value still equals `expected`, until woken by `wake`.
+    ///
+    /// Returns immediately if the value differs from `expected`. May return spuriously
+    #[inline]
+    pub fn wait(&self, expected: u32) {
+        let mut waiters = self.waiters.lock();
+        // Checking under the waiter lock orders this against any `wake` that follows a change
+        if self.value.load(Ordering::Acquire) != expected {
+            return;
+        }
+        let pid = syscalls::getpid();
+        waiters.push_back(pid);
+        drop(waiters);
+        syscalls::block();
+        // A spurious return leaves this program queued, where it would soak up a later wakeup
+        let mut waiters = self.waiters.lock();
+        if let Some(index) = waiters.iter().position(|&waiter| waiter == pid) {
+            waiters.remove(index);
+        }
+    }
+
+    /// Wakes up to `count` programs waiting on this futex.
+    ///
+    /// Returns the number of programs woken
+    #[inline]
+    pub fn wake(&self, count: usize) -> usize {
+        let mut waiters = self.waiters.lock();
+        let woken: VecDeque<u16> = waiters.drain(..count.min(waiters.len())).collect();
+        drop(waiters);
+        woken
+            .into_iter()
+            .filter(|&pid| syscalls::unblock(pid))
+            .count()
+    }
+}
@@ -4,10 +4,39 @@ use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicU32;
+
+use crate::os::syscalls;
+#[cfg(debug_assertions)]
+use crate::println;
+
+mod condvar;
+mod futex;
+mod semaphore;
+pub use condvar::CondVar;
+pub use futex::Futex;
+pub use semaphore::Semaphore;
+
+/// Number of times a contended `SpinLock` is polled before the waiter yields its timeslice
+const SPIN_LIMIT: u32 = 100;
+
+/// Number of times a waiter yields on a contended `SpinLock` before reporting a possible
+/// deadlock, in debug builds
+#[cfg(debug_assertions)]
+const DEADLOCK_YIELDS: u32 = 1000;
+
+/// Owner value for an unheld `SpinLock`
+#[cfg(debug_assertions)]
+const NO_OWNER: u32 = u32::MAX;
+
 /// A spinlock mutex
 pub struct SpinLock<T: ?Sized> {
     /// Whether or not the spinlock is taken
     is_locked: AtomicBool,
+    /// PID of the holder of the lock, for deadlock reports
+    #[cfg(debug_assertions)]
+    owner: AtomicU32,
     /// The protected data
     data: UnsafeCell<T>,
 }
@@ -22,22 +51,69 @@ impl<T> SpinLock<T> {
         Self {
             data: UnsafeCell::new(data),
             is_locked: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            owner: AtomicU32::new(NO_OWNER),
         }
     }
 
     /// Locks the mutex. The mutex is automatically unlocked when the returned `MutexGuard` is
     /// dropped
+    ///
+    /// If the lock is contended, this spins for a bounded number of attempts before yielding to
+    /// the scheduler, so that a holder sharing this core can run and release the lock. In debug
+    /// builds, yielding for a suspiciously long time prints a possible deadlock warning, before
+    /// continuing to wait
     #[inline]
     pub fn lock(&self) -> MutexGuard<T> {
+        #[cfg(debug_assertions)]
+        let mut yields = 0_u32;
         while self.is_locked.swap(true, Ordering::Acquire) {
+            let mut spins = 0..SPIN_LIMIT;
             while self.is_locked.load(Ordering::Relaxed) {
-                core::hint::spin_loop();
+                if spins.next().is_some() {
+                    core::hint::spin_loop();
+                } else {
+                    #[cfg(debug_assertions)]
+                    {
+                        yields = yields.saturating_add(1);
+                        if yields == DEADLOCK_YIELDS {
+                            println!(
+                                "possible deadlock: lock held by {}, waiter {}",
+                                self.owner.load(Ordering::Relaxed),
+                                syscalls::getpid()
+                            );
+                        }
+                    }
+                    syscalls::sched_yield();
+                    spins = 0..SPIN_LIMIT;
+                }
             }
         }
 
+        #[cfg(debug_assertions)]
+        self.owner
+            .store(syscalls::getpid().into(), Ordering::Relaxed);
+
         MutexGuard(self)
     }
 
+    /// Attempts to lock the mutex without waiting. The mutex is automatically unlocked when the
+    /// returned `MutexGuard` is dropped
+    ///
+    /// Returns `None` if the mutex is already locked
+    #[inline]
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        self.is_locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| {
+                #[cfg(debug_assertions)]
+                self.owner
+                    .store(syscalls::getpid().into(), Ordering::Relaxed);
+                MutexGuard(self)
+            })
+    }
+
     /// Unlocks the mutex
     ///
     /// # Safety
@@ -45,6 +121,8 @@ impl<T> SpinLock<T> {
     /// This must only be called by the destructor of the `MutexGuard` that locked this mutex
     #[inline]
     unsafe fn unlock(&self) {
+        #[cfg(debug_assertions)]
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
         self.is_locked.store(false, Ordering::Release);
     }
 }
@@ -0,0 +1,82 @@
+use super::SpinLock;
+use crate::os::syscalls;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// A counting semaphore, for coordinating producers and consumers across programs.
+///
+/// Acquirers that find the count at zero block through the `block`/`unblock` syscalls instead of
+/// spinning, and each `release` wakes at most one of them, in order of arrival. As with `Futex`, a
+/// woken acquirer may still lose the race for the count to another program, in which case it
+/// simply waits again
+pub struct Semaphore {
+    /// Number of permits currently available
+    count: AtomicU32,
+    /// PIDs of programs waiting for a permit, in order of arrival
+    waiters: SpinLock<VecDeque<u16>>,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the given number of permits available
+    #[inline]
+    #[must_use]
+    pub const fn new(initial: u32) -> Self {
+        Self {
+            count: AtomicU32::new(initial),
+            waiters: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Takes a permit if one is available, without blocking.
+    ///
+    /// Returns whether a permit was taken
+    #[inline]
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Takes a permit, blocking the current program until one is available
+    #[inline]
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            let mut waiters = self.waiters.lock();
+            // Checking under the waiter lock orders this against any `release` that follows
+            if self.count.load(Ordering::Relaxed) != 0 {
+                continue;
+            }
+            let pid = syscalls::getpid();
+            waiters.push_back(pid);
+            drop(waiters);
+            syscalls::block();
+            // A spurious return leaves this program queued, where it would soak up a later release
+            let mut waiters = self.waiters.lock();
+            if let Some(index) = waiters.iter().position(|&waiter| waiter == pid) {
+                waiters.remove(index);
+            }
+        }
+    }
+
+    /// Returns a permit, and wakes the longest-waiting blocked acquirer, if any
+    ///
+    /// # Panics
+    ///
+    /// Panics if this would overflow the number of permits
+    #[inline]
+    pub fn release(&self) {
+        self.count
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |count| {
+                count.checked_add(1)
+            })
+            .expect("Semaphore count should not overflow");
+        // Skip over any waiters that have since exited, so that the permit is not left unclaimed
+        while let Some(pid) = self.waiters.lock().pop_front() {
+            if syscalls::unblock(pid) {
+                break;
+            }
+        }
+    }
+}
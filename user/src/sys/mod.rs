@@ -1 +1,2 @@
+pub mod stat;
 pub mod types;
@@ -0,0 +1,15 @@
+pub mod ffi {
+    use crate::sys::types::ffi::{mode_t, off_t};
+
+    /// File type bits of `st_mode` for a FIFO, which pipes are
+    pub const S_IFIFO: mode_t = 0o010_000;
+
+    /// Information about a file, as filled in by `ffstat`
+    #[repr(C)]
+    pub struct stat {
+        /// File type and mode
+        pub st_mode: mode_t,
+        /// For pipes, the number of bytes buffered and not yet read
+        pub st_size: off_t,
+    }
+}
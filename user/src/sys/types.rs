@@ -1,4 +1,5 @@
 pub mod ffi {
+    pub type mode_t = u32;
     pub type off_t = u64;
     pub type pid_t = u16;
     pub type uid_t = u16;
@@ -1,6 +1,6 @@
 #![feature(iterator_try_collect)]
 use core::iter;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
 use std::io::Write;
 use std::{env, path::Path, process::Command};
@@ -98,33 +98,55 @@ fn build(is_debug: bool, output_dir: impl AsRef<Path>) -> Result<(), DynError> {
         .append(true)
         .open(output_dir.as_ref().join("kernel"))?;
 
-    let to_append = ["init", "pipe", "serial"];
-    let files = to_append
-        .iter()
-        .map(|x| File::open(output_dir.as_ref().join(x)).unwrap())
-        .collect::<Box<[_]>>();
-    let lengths = files
-        .iter()
-        .map(|x| u16::try_from(x.metadata().unwrap().len()).unwrap())
-        .collect::<Box<[_]>>();
-    let mut suffix_sum = lengths
-        .iter()
-        .rev()
-        .scan(0_u16, |x, &y| {
-            *x += y.checked_add(2).unwrap();
-            Some(*x)
-        })
-        .map(|x| x - 2)
-        .collect::<Box<[_]>>();
-    suffix_sum.reverse();
-    println!("lens {lengths:?}");
-    println!("suffix sum {suffix_sum:?}");
-    for (mut file, &len) in files.iter().zip(suffix_sum.iter()) {
-        kernel.write_all(&(len.to_le_bytes()))?;
-        io::copy(&mut file, &mut kernel)?;
+    // The kernel copies init, along with everything following it, into init's memory. Init then
+    // locates the remaining binaries by name, so they each get a header describing them
+    let mut table = Vec::new();
+    for name in ["pipe", "serial"] {
+        let binary = fs::read(output_dir.as_ref().join(name))?;
+        append_entry(&mut table, name, &binary)?;
     }
+    let table_len = u16::try_from(table.len()).map_err(|_| "Boot image table is too large")?;
+
+    let init = fs::read(output_dir.as_ref().join("init"))?;
+    let init_len = init
+        .len()
+        .checked_add(2)
+        .and_then(|len| len.checked_add(table.len()))
+        .and_then(|len| u16::try_from(len).ok())
+        .ok_or("Init and its boot image table are too large")?;
+    println!("init {} bytes, table {table_len} bytes", init.len());
+
+    kernel.write_all(&init_len.to_le_bytes())?;
+    kernel.write_all(&init)?;
+    kernel.write_all(&table_len.to_le_bytes())?;
+    kernel.write_all(&table)?;
 
     println!("Concatenated objects");
 
     Ok(())
 }
+
+/// Magic marking the start of each entry in the boot image table. Must match `common::boot_image`
+const ENTRY_MAGIC: [u8; 4] = *b"BIMG";
+/// Length of the (NUL-padded) name field of each entry
+const ENTRY_NAME_LEN: usize = 24;
+/// Alignment of each entry, relative to the start of the table
+const ENTRY_ALIGN: usize = 8;
+
+/// Appends an entry for the given binary to the boot image table, padded so that the next entry is
+/// aligned
+fn append_entry(table: &mut Vec<u8>, name: &str, binary: &[u8]) -> Result<(), DynError> {
+    if name.len() > ENTRY_NAME_LEN {
+        Err(format!("Binary name {name} is too long"))?;
+    }
+    let len = u32::try_from(binary.len())?;
+    println!("{name} {len} bytes");
+
+    table.extend_from_slice(&ENTRY_MAGIC);
+    table.extend_from_slice(&len.to_le_bytes());
+    table.extend_from_slice(name.as_bytes());
+    table.resize(table.len() + ENTRY_NAME_LEN - name.len(), 0);
+    table.extend_from_slice(binary);
+    table.resize(table.len().next_multiple_of(ENTRY_ALIGN), 0);
+    Ok(())
+}